@@ -0,0 +1,57 @@
+#![feature(test)]
+
+// These benchmarks can be run from the project root with:
+// > cargo bench --package mentat
+
+extern crate test;
+extern crate mentat;
+
+use test::Bencher;
+
+use mentat::{
+    QueryBuilder,
+    Store,
+};
+
+const ROWS: usize = 500_000;
+
+fn store_with_rows(rows: usize) -> Store {
+    let mut store = Store::open("").expect("store");
+    store.transact(r#"[
+        {:db/ident :bench/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+    ]"#).expect("schema");
+
+    let mut written = 0;
+    while written < rows {
+        let batch = ::std::cmp::min(10_000, rows - written);
+        let mut edn = String::from("[");
+        for i in written..(written + batch) {
+            edn.push_str(&format!("[:db/add \"e{}\" :bench/n {}]", i, i));
+        }
+        edn.push(']');
+        store.transact(&edn).expect("data");
+        written += batch;
+    }
+    store
+}
+
+// Compare the two to see the cost of growing a 500k-row result vector as rows arrive.
+
+#[bench]
+fn bench_execute_rel_500k(b: &mut Bencher) {
+    let mut store = store_with_rows(ROWS);
+    b.iter(|| {
+        QueryBuilder::new(&mut store, "[:find ?e ?n :where [?e :bench/n ?n]]")
+            .execute_rel().expect("results")
+    });
+}
+
+#[bench]
+fn bench_execute_hinted_500k(b: &mut Bencher) {
+    let mut store = store_with_rows(ROWS);
+    b.iter(|| {
+        QueryBuilder::new(&mut store, "[:find ?e ?n :where [?e :bench/n ?n]]")
+            .execute_hinted(ROWS).expect("output")
+            .into_rel().expect("results")
+    });
+}
@@ -127,8 +127,10 @@ impl enum_set::CLike for ValueType {
 }
 
 impl ValueType {
-    pub fn into_keyword(self) -> NamespacedKeyword {
-        NamespacedKeyword::new("db.type", match self {
+    /// The name of this type within the `:db.type` namespace, e.g., `"long"`. Unlike the enum's
+    /// discriminant, this is stable, so it's safe to persist or to hand across an FFI boundary.
+    pub fn name(self) -> &'static str {
+        match self {
             ValueType::Ref => "ref",
             ValueType::Boolean => "boolean",
             ValueType::Instant => "instant",
@@ -137,20 +139,30 @@ impl ValueType {
             ValueType::String => "string",
             ValueType::Keyword => "keyword",
             ValueType::Uuid => "uuid",
-        })
+        }
+    }
+
+    /// The inverse of `name`.
+    pub fn from_name(name: &str) -> Option<ValueType> {
+        match name {
+            "ref" => Some(ValueType::Ref),
+            "boolean" => Some(ValueType::Boolean),
+            "instant" => Some(ValueType::Instant),
+            "long" => Some(ValueType::Long),
+            "double" => Some(ValueType::Double),
+            "string" => Some(ValueType::String),
+            "keyword" => Some(ValueType::Keyword),
+            "uuid" => Some(ValueType::Uuid),
+            _ => None,
+        }
+    }
+
+    pub fn into_keyword(self) -> NamespacedKeyword {
+        NamespacedKeyword::new("db.type", self.name())
     }
 
     pub fn into_typed_value(self) -> TypedValue {
-        TypedValue::typed_ns_keyword("db.type", match self {
-            ValueType::Ref => "ref",
-            ValueType::Boolean => "boolean",
-            ValueType::Instant => "instant",
-            ValueType::Long => "long",
-            ValueType::Double => "double",
-            ValueType::String => "string",
-            ValueType::Keyword => "keyword",
-            ValueType::Uuid => "uuid",
-        })
+        TypedValue::typed_ns_keyword("db.type", self.name())
     }
 
     pub fn into_edn_value(self) -> edn::Value {
@@ -406,6 +418,23 @@ impl TypedValue {
             _ => None,
         }
     }
+
+    /// Convert this value to the provided type, if that can be done without loss.
+    /// A value always coerces to its own type. Beyond that, longs and refs interconvert (refs
+    /// must be non-negative), and instants interconvert with longs as microseconds since the
+    /// epoch. Any other coercion returns `None`.
+    pub fn coerce_to(self, value_type: ValueType) -> Option<TypedValue> {
+        if self.matches_type(value_type) {
+            return Some(self);
+        }
+        match (self, value_type) {
+            (TypedValue::Ref(v), ValueType::Long) => Some(TypedValue::Long(v)),
+            (TypedValue::Long(v), ValueType::Ref) if v >= 0 => Some(TypedValue::Ref(v)),
+            (TypedValue::Instant(v), ValueType::Long) => Some(TypedValue::Long(v.to_micros())),
+            (TypedValue::Long(v), ValueType::Instant) => Some(TypedValue::instant(v)),
+            _ => None,
+        }
+    }
 }
 
 /// Type safe representation of the possible return values from SQLite's `typeof`
@@ -1059,6 +1088,30 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_coerce_to() {
+        assert_eq!(TypedValue::Long(5).coerce_to(ValueType::Long), Some(TypedValue::Long(5)));
+        assert_eq!(TypedValue::Long(5).coerce_to(ValueType::Ref), Some(TypedValue::Ref(5)));
+        assert_eq!(TypedValue::Long(-5).coerce_to(ValueType::Ref), None);
+        assert_eq!(TypedValue::Ref(5).coerce_to(ValueType::Long), Some(TypedValue::Long(5)));
+
+        let micros = 1_493_399_581_314_000;
+        assert_eq!(TypedValue::Long(micros).coerce_to(ValueType::Instant), Some(TypedValue::instant(micros)));
+        assert_eq!(TypedValue::instant(micros).coerce_to(ValueType::Long), Some(TypedValue::Long(micros)));
+
+        assert_eq!(TypedValue::typed_string("5").coerce_to(ValueType::Long), None);
+        assert_eq!(TypedValue::Boolean(true).coerce_to(ValueType::Long), None);
+    }
+
+    #[test]
+    fn test_value_type_names() {
+        for value_type in ValueType::all_enums().iter() {
+            assert_eq!(ValueType::from_name(value_type.name()), Some(value_type));
+        }
+        assert_eq!(ValueType::Long.name(), "long");
+        assert_eq!(ValueType::from_name(":db.type/long"), None);
+    }
+
     #[test]
     fn test_as_edn_value() {
         let mut schema = Schema::default();
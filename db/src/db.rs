@@ -11,7 +11,10 @@
 #![allow(dead_code)]
 
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{
+    BTreeSet,
+    HashMap,
+};
 use std::collections::hash_map::{
     Entry,
 };
@@ -557,6 +560,10 @@ pub trait MentatStoring {
 
     /// Extract metadata-related [e a typed_value added] datoms committed in the given transaction.
     fn committed_metadata_assertions(&self, tx_id: Entid) -> Result<Vec<(Entid, Entid, TypedValue, bool)>>;
+
+    /// Extract the entities with datoms committed in the given transaction, other than the
+    /// transaction entity itself.
+    fn committed_entids(&self, tx_id: Entid) -> Result<BTreeSet<Entid>>;
 }
 
 /// Take search rows and complete `temp.search_results`.
@@ -660,13 +667,60 @@ fn update_datoms(conn: &rusqlite::Connection, tx: Entid) -> Result<()> {
       AttributeBitFlags::UniqueValue as u8);
 
     let mut stmt = conn.prepare_cached(&s)?;
-    stmt.execute(&[&tx])
-        .map(|_c| ())
-        .chain_err(|| "Could not update datoms: failed to add datoms not already present")?;
+    if let Err(e) = stmt.execute(&[&tx]) {
+        // SQLite only tells us that `idx_datoms_unique_value` was violated; find out by what, so
+        // that consumers can say which value is taken. If that search fails, report `e` as is.
+        if is_constraint_violation(&e) {
+            if let Ok(Some((a, v))) = unique_value_conflict(conn) {
+                bail!(ErrorKind::UniqueConstraintViolation(a, v.to_edn_value_pair().0));
+            }
+        }
+        return Err(e).chain_err(|| "Could not update datoms: failed to add datoms not already present");
+    }
 
     Ok(())
 }
 
+fn is_constraint_violation(e: &rusqlite::Error) -> bool {
+    match e {
+        &rusqlite::Error::SqliteFailure(rusqlite::ffi::Error { code: rusqlite::ErrorCode::ConstraintViolation, .. }, _) => true,
+        _ => false,
+    }
+}
+
+/// Find an `[a v]` pair in `search_results` that can't be added to `datoms` without violating
+/// `:db/unique`: either another entity already has it, or the transaction adds it to two
+/// entities.
+fn unique_value_conflict(conn: &rusqlite::Connection) -> Result<Option<(Entid, TypedValue)>> {
+    let s = format!(r#"
+      SELECT s.a0, coalesce(f.text, s.v0), s.value_type_tag0
+      FROM temp.search_results AS s
+      LEFT JOIN fulltext_values AS f
+      ON s.value_type_tag0 = 10 AND typeof(s.v0) = 'integer' AND f.rowid = s.v0
+      WHERE s.added0 IS 1 AND s.flags0 & {} IS NOT 0 AND ((s.rid IS NULL) OR (s.v0 IS NOT s.v)) AND
+            (EXISTS (SELECT 1 FROM datoms AS d
+                     WHERE d.a = s.a0 AND d.value_type_tag = s.value_type_tag0 AND d.v = s.v0 AND
+                           d.e IS NOT s.e0 AND d.unique_value IS NOT 0) OR
+             EXISTS (SELECT 1 FROM temp.search_results AS o
+                     WHERE o.a0 = s.a0 AND o.value_type_tag0 = s.value_type_tag0 AND o.v0 = s.v0 AND
+                           o.e0 IS NOT s.e0 AND o.added0 IS 1))
+      LIMIT 1"#,
+      AttributeBitFlags::UniqueValue as u8);
+
+    let mut stmt = conn.prepare(&s)?;
+    let mut rows = stmt.query(&[])?;
+    match rows.next() {
+        Some(row) => {
+            let row = row?;
+            let a: Entid = row.get_checked(0)?;
+            let v: rusqlite::types::Value = row.get_checked(1)?;
+            let value_type_tag: i32 = row.get_checked(2)?;
+            Ok(Some((a, TypedValue::from_sql_value_pair(v, value_type_tag)?)))
+        },
+        None => Ok(None),
+    }
+}
+
 impl MentatStoring for rusqlite::Connection {
     fn resolve_avs<'a>(&self, avs: &'a [&'a AVPair]) -> Result<AVMap<'a>> {
         // Start search_id's at some identifiable number.
@@ -1001,6 +1055,15 @@ impl MentatStoring for rusqlite::Connection {
         })?.collect();
         m
     }
+
+    fn committed_entids(&self, tx_id: Entid) -> Result<BTreeSet<Entid>> {
+        let mut stmt = self.prepare_cached("SELECT DISTINCT e FROM transactions WHERE tx = ? AND e != ?")?;
+        let params = [&tx_id as &ToSql, &tx_id as &ToSql];
+        let m: Result<BTreeSet<Entid>> = stmt.query_and_then(&params[..], |row| -> Result<Entid> {
+            Ok(row.get_checked(0)?)
+        })?.collect();
+        m
+    }
 }
 
 /// Update the current partition map materialized view.
@@ -1406,6 +1469,20 @@ mod tests {
         //                  Err("Should fail!"));
     }
 
+    #[test]
+    fn test_tx_report_changed_entids() {
+        let mut conn = TestConn::default();
+
+        let report = conn.transact("[[:db/add 100 :db/ident :name/Ivan]
+                                     [:db/add 101 :db/ident :name/Petr]]").expect("transact");
+        assert_eq!(report.changed_entids, vec![100, 101].into_iter().collect::<BTreeSet<Entid>>());
+
+        // Reasserting an existing datom changes nothing.
+        let report = conn.transact("[[:db/add 100 :db/ident :name/Ivan]
+                                     [:db/retract 101 :db/ident :name/Petr]]").expect("transact");
+        assert_eq!(report.changed_entids, vec![101].into_iter().collect::<BTreeSet<Entid>>());
+    }
+
     #[test]
     fn test_retract() {
         let mut conn = TestConn::default();
@@ -1819,6 +1896,37 @@ mod tests {
                                  [:db/add 221 :test/ident 2]]");
     }
 
+    #[test]
+    fn test_unique_value_violation() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 100 :db/ident :test/email]
+                                 [:db/add 100 :db/valueType :db.type/string]
+                                 [:db/add 100 :db/cardinality :db.cardinality/one]
+                                 [:db/add 100 :db/unique :db.unique/value]
+                                 [:db/add 100 :db/index true]]");
+        assert_transact!(conn, "[[:db/add 200 :test/email \"a@b.com\"]]");
+
+        // Another entity can't take a value that's in use…
+        assert_transact!(conn, "[[:db/add 201 :test/email \"a@b.com\"]]",
+                         Err("unique constraint violated: value \"a@b.com\" of attribute 100 is already asserted for another entity"));
+
+        // … nor can two entities take the same value at once.
+        assert_transact!(conn, "[[:db/add 201 :test/email \"c@d.com\"]
+                                 [:db/add 202 :test/email \"c@d.com\"]]",
+                         Err("unique constraint violated: value \"c@d.com\" of attribute 100 is already asserted for another entity"));
+
+        // Reasserting an entity's own value is fine, and the store is unchanged by the failures.
+        assert_transact!(conn, "[[:db/add 200 :test/email \"a@b.com\"]]");
+        assert_matches!(conn.datoms(),
+                        "[[100 :db/ident :test/email]
+                          [100 :db/valueType :db.type/string]
+                          [100 :db/cardinality :db.cardinality/one]
+                          [100 :db/unique :db.unique/value]
+                          [100 :db/index true]
+                          [200 :test/email \"a@b.com\"]]");
+    }
+
     /// Verify that we can't alter :db/fulltext schema characteristics at all.
     #[test]
     fn test_db_alter_fulltext() {
@@ -102,5 +102,12 @@ error_chain! {
             description("schema alteration failed")
             display("schema alteration failed: {}", t)
         }
+
+        /// A transaction asserted a value of a `:db/unique` attribute that another entity already
+        /// has, or asserted it for two entities at once.
+        UniqueConstraintViolation(attr: Entid, value: edn::types::Value) {
+            description("unique constraint violated")
+            display("unique constraint violated: value {} of attribute {} is already asserted for another entity", value, attr)
+        }
     }
 }
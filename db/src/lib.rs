@@ -89,8 +89,11 @@ pub use tx::{
 
 pub use tx_observer::{
     InProgressObserverTransactWatcher,
+    SchemaObserver,
     TxObservationService,
     TxObserver,
+    ValueChange,
+    ValueObserver,
 };
 
 pub use types::{
@@ -708,6 +708,8 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         self.store.commit_transaction(self.tx_id)?;
         }
 
+        let changed_entids = self.store.committed_entids(self.tx_id)?;
+
         db::update_partition_map(self.store, &self.partition_map)?;
         self.watcher.done(&self.tx_id, self.schema)?;
 
@@ -734,6 +736,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             tx_id: self.tx_id,
             tx_instant,
             tempids: tempids,
+            changed_entids: changed_entids,
         })
     }
 }
@@ -8,6 +8,11 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
 use std::sync::{
     Arc,
     Weak,
@@ -28,6 +33,7 @@ use indexmap::{
 
 use mentat_core::{
     Entid,
+    NamespacedKeyword,
     Schema,
     TypedValue,
 };
@@ -70,6 +76,53 @@ impl TxObserver {
     }
 }
 
+/// Notified after a commit that installs, alters, or removes attributes, with the idents of
+/// those attributes.
+pub struct SchemaObserver {
+    notify_fn: Arc<Box<Fn(&str, &BTreeSet<NamespacedKeyword>) + Send + Sync>>,
+}
+
+impl SchemaObserver {
+    pub fn new<F>(notify_fn: F) -> SchemaObserver where F: Fn(&str, &BTreeSet<NamespacedKeyword>) + 'static + Send + Sync {
+        SchemaObserver {
+            notify_fn: Arc::new(Box::new(notify_fn)),
+        }
+    }
+
+    fn notify(&self, key: &str, attributes: &BTreeSet<NamespacedKeyword>) {
+        (*self.notify_fn)(key, attributes);
+    }
+}
+
+/// A change to an observed attribute of one entity: the entity, the value retracted, and the
+/// value asserted. Either value is absent if the transaction only asserted or only retracted.
+pub type ValueChange = (Entid, Option<TypedValue>, Option<TypedValue>);
+
+/// Notified after a commit that changes values of one attribute, with the old and new values.
+/// This suits cardinality-one attributes: for cardinality-many attributes, only one retracted and
+/// one asserted value per entity are reported.
+pub struct ValueObserver {
+    attribute: Entid,
+    notify_fn: Arc<Box<Fn(&str, &[ValueChange]) + Send + Sync>>,
+}
+
+impl ValueObserver {
+    pub fn new<F>(attribute: Entid, notify_fn: F) -> ValueObserver where F: Fn(&str, &[ValueChange]) + 'static + Send + Sync {
+        ValueObserver {
+            attribute,
+            notify_fn: Arc::new(Box::new(notify_fn)),
+        }
+    }
+
+    pub fn attribute(&self) -> Entid {
+        self.attribute
+    }
+
+    fn notify(&self, key: &str, changes: &[ValueChange]) {
+        (*self.notify_fn)(key, changes);
+    }
+}
+
 pub trait Command {
     fn execute(&mut self);
 }
@@ -101,8 +154,42 @@ impl Command for TxCommand {
     }
 }
 
+pub struct SchemaCommand {
+    attributes: BTreeSet<NamespacedKeyword>,
+    observers: Weak<IndexMap<String, Arc<SchemaObserver>>>,
+}
+
+impl Command for SchemaCommand {
+    fn execute(&mut self) {
+        self.observers.upgrade().map(|observers| {
+            for (key, observer) in observers.iter() {
+                observer.notify(&key, &self.attributes);
+            }
+        });
+    }
+}
+
+pub struct ValueCommand {
+    changes: BTreeMap<Entid, Vec<ValueChange>>,
+    observers: Weak<IndexMap<String, Arc<ValueObserver>>>,
+}
+
+impl Command for ValueCommand {
+    fn execute(&mut self) {
+        self.observers.upgrade().map(|observers| {
+            for (key, observer) in observers.iter() {
+                if let Some(changes) = self.changes.get(&observer.attribute) {
+                    observer.notify(&key, changes);
+                }
+            }
+        });
+    }
+}
+
 pub struct TxObservationService {
     observers: Arc<IndexMap<String, Arc<TxObserver>>>,
+    schema_observers: Arc<IndexMap<String, Arc<SchemaObserver>>>,
+    value_observers: Arc<IndexMap<String, Arc<ValueObserver>>>,
     executor: Option<Sender<Box<Command + Send>>>,
 }
 
@@ -110,6 +197,8 @@ impl TxObservationService {
     pub fn new() -> Self {
         TxObservationService {
             observers: Arc::new(IndexMap::new()),
+            schema_observers: Arc::new(IndexMap::new()),
+            value_observers: Arc::new(IndexMap::new()),
             executor: None,
         }
     }
@@ -127,17 +216,87 @@ impl TxObservationService {
         Arc::make_mut(&mut self.observers).remove(key);
     }
 
+    /// Drop every transaction, schema, and value observer, and with them anything their closures
+    /// captured.
+    pub fn deregister_all(&mut self) {
+        self.observers = Arc::new(IndexMap::new());
+        self.schema_observers = Arc::new(IndexMap::new());
+        self.value_observers = Arc::new(IndexMap::new());
+    }
+
+    /// The keys of every registered observer: transaction observers, then schema observers, then
+    /// value observers, each in the order they were registered.
+    pub fn observer_keys(&self) -> Vec<String> {
+        self.observers.keys()
+            .chain(self.schema_observers.keys())
+            .chain(self.value_observers.keys())
+            .cloned()
+            .collect()
+    }
+
     pub fn has_observers(&self) -> bool {
         !self.observers.is_empty()
     }
 
+    pub fn register_schema_observer(&mut self, key: String, observer: Arc<SchemaObserver>) {
+        Arc::make_mut(&mut self.schema_observers).insert(key, observer);
+    }
+
+    pub fn deregister_schema_observer(&mut self, key: &String) {
+        Arc::make_mut(&mut self.schema_observers).remove(key);
+    }
+
+    pub fn register_value_observer(&mut self, key: String, observer: Arc<ValueObserver>) {
+        Arc::make_mut(&mut self.value_observers).insert(key, observer);
+    }
+
+    pub fn deregister_value_observer(&mut self, key: &String) {
+        Arc::make_mut(&mut self.value_observers).remove(key);
+    }
+
+    /// The attributes whose value changes a transaction needs to collect.
+    pub fn value_observed_attributes(&self) -> AttributeSet {
+        self.value_observers.values().map(|o| o.attribute).collect()
+    }
+
     pub fn in_progress_did_commit(&mut self, txes: IndexMap<Entid, AttributeSet>) {
         // Don't spawn a thread only to say nothing.
         if !self.has_observers() {
             return;
         }
 
-        let executor = self.executor.get_or_insert_with(|| {
+        let cmd = Box::new(TxCommand::new(&self.observers, txes));
+        self.executor().send(cmd).unwrap();
+    }
+
+    /// Tell schema observers that a commit changed the given attributes.
+    pub fn in_progress_did_change_schema(&mut self, attributes: BTreeSet<NamespacedKeyword>) {
+        if self.schema_observers.is_empty() || attributes.is_empty() {
+            return;
+        }
+
+        let cmd = Box::new(SchemaCommand {
+            attributes,
+            observers: Arc::downgrade(&self.schema_observers),
+        });
+        self.executor().send(cmd).unwrap();
+    }
+
+    /// Tell value observers about committed changes, keyed by attribute.
+    pub fn in_progress_did_change_values(&mut self, changes: BTreeMap<Entid, Vec<ValueChange>>) {
+        if self.value_observers.is_empty() || changes.is_empty() {
+            return;
+        }
+
+        let cmd = Box::new(ValueCommand {
+            changes,
+            observers: Arc::downgrade(&self.value_observers),
+        });
+        self.executor().send(cmd).unwrap();
+    }
+
+    fn executor(&mut self) -> &Sender<Box<Command + Send>> {
+        self.executor.get_or_insert_with(|| {
             let (tx, rx): (Sender<Box<Command + Send>>, Receiver<Box<Command + Send>>) = channel();
             let mut worker = CommandExecutor::new(rx);
 
@@ -146,10 +305,7 @@ impl TxObservationService {
             });
 
             tx
-        });
-
-        let cmd = Box::new(TxCommand::new(&self.observers, txes));
-        executor.send(cmd).unwrap();
+        })
     }
 }
 
@@ -103,4 +103,9 @@ pub struct TxReport {
     /// existing entid, or is allocated a new entid.  (It is possible for multiple distinct string
     /// literal tempids to all unify to a single freshly allocated entid.)
     pub tempids: BTreeMap<String, Entid>,
+
+    /// The entities whose datoms the transaction asserted or retracted, not counting the
+    /// transaction entity itself.  Asserting a datom that is already present changes nothing, so
+    /// doesn't by itself add its entity here.
+    pub changed_entids: BTreeSet<Entid>,
 }
@@ -0,0 +1,62 @@
+#![feature(test)]
+
+// These benchmarks can be run from the project root with:
+// > cargo bench --package mentat_ffi
+
+extern crate test;
+extern crate mentat;
+extern crate mentat_ffi;
+
+use std::ffi::CString;
+
+use test::Bencher;
+
+use mentat::Store;
+
+use mentat_ffi::{
+    store_set_long_for_attribute_on_entid,
+};
+use mentat_ffi::utils::strings::kw_from_string;
+
+const ASSERTS: usize = 10_000;
+
+// Compare the two to see what `kw_from_string`'s cache saves when the same attribute is named
+// over and over, as in a bulk load. Past the first 1024 the distinct keywords aren't cached, so
+// that benchmark measures parsing every time.
+
+#[bench]
+fn bench_kw_from_string_10k_identical(b: &mut Bencher) {
+    b.iter(|| {
+        for _ in 0..ASSERTS {
+            test::black_box(kw_from_string(":bench/n".to_string()));
+        }
+    });
+}
+
+#[bench]
+fn bench_kw_from_string_10k_distinct(b: &mut Bencher) {
+    let keywords: Vec<String> = (0..ASSERTS).map(|i| format!(":bench/n{}", i)).collect();
+    b.iter(|| {
+        for keyword in keywords.iter() {
+            test::black_box(kw_from_string(keyword.clone()));
+        }
+    });
+}
+
+#[bench]
+fn bench_set_long_10k_identical_attribute(b: &mut Bencher) {
+    let mut store = Store::open("").expect("store");
+    store.transact(r#"[
+        {:db/ident :bench/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+    ]"#).expect("schema");
+    let entid = store.transact(r#"[[:db/add "e" :bench/n 0]]"#).expect("data").tempids["e"];
+    let attribute = CString::new(":bench/n").expect("attribute");
+    b.iter(|| {
+        for i in 0..ASSERTS {
+            unsafe {
+                let res = Box::from_raw(store_set_long_for_attribute_on_entid(&mut store, entid, attribute.as_ptr(), i as i64));
+                assert!(res.err.is_null());
+            }
+        }
+    });
+}
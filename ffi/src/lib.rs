@@ -11,63 +11,143 @@
 extern crate libc;
 extern crate mentat;
 
+use std::cmp::Ordering;
 use std::collections::{
     BTreeSet,
 };
+use std::ffi::CString;
+use std::fs::File;
 use std::os::raw::{
     c_char,
     c_int,
     c_void,
 };
+#[cfg(unix)]
+use std::os::unix::io::{
+    FromRawFd,
+    IntoRawFd,
+};
+use std::rc::Rc;
 use std::slice;
 use std::sync::{
     Arc,
+    Mutex,
 };
+use std::thread;
+use std::time::Duration;
 use std::vec;
 
 use libc::time_t;
 
 pub use mentat::{
+    Attribute,
+    BindInputs,
+    CacheDirection,
+    CachedQuery,
     Entid,
     FindSpec,
     HasSchema,
+    InProgress,
+    InProgressRead,
+    JournalMode,
     KnownEntid,
+    LoggedTransaction,
     NamespacedKeyword,
     Queryable,
     QueryBuilder,
+    QueryHandle,
     QueryInputs,
     QueryOutput,
     QueryResults,
+    QueryTiming,
+    SchemaObserver,
     Store,
+    StoreReader,
+    SyncInterrupt,
+    SyncPhase,
+    SyncStatus,
     Syncable,
+    TxReport,
     TypedValue,
     TxObserver,
     Uuid,
+    ValueObserver,
     ValueType,
     Variable,
 };
 
+use mentat::entity_builder::{
+    BuildTerms,
+    InProgressBuilder,
+};
+use mentat::errors::{
+    DbErrorKind,
+    Error,
+    ErrorKind,
+    SyncErrorKind,
+};
+use mentat::vocabulary::attribute::Unique;
+
 pub mod android;
 pub mod utils;
 
 pub use utils::strings::{
+    c_char_array_to_strings,
     c_char_to_string,
+    c_char_to_string_checked,
     c_char_from_rc,
     kw_from_string,
+    kws_from_strings,
+    string_from_bytes,
+    string_to_bytes,
     string_to_c_char,
+    ExternString,
 };
 
 pub type TypedValueIterator = vec::IntoIter<TypedValue>;
 pub type TypedValueListIterator = vec::IntoIter<Vec<TypedValue>>;
-
+pub type TempIdIterator = vec::IntoIter<(String, Entid)>;
+pub type AttributeIterator = vec::IntoIter<(NamespacedKeyword, Entid, Attribute)>;
+pub type StringIterator = vec::IntoIter<String>;
+pub type EntidIterator = vec::IntoIter<Entid>;
+pub type TimedQueryOutput = (QueryOutput, QueryTiming);
+pub type AttributeValues = Vec<(NamespacedKeyword, TypedValue)>;
+pub type AttributeValuesList = Vec<AttributeValues>;
+pub type TxLog = Vec<LoggedTransaction>;
+
+/// One transaction reported to an observer.
 #[repr(C)]
 #[derive(Debug, Clone)]
 pub struct ExternTxReport {
     pub txid: Entid,
+    /// The entids of the attributes the transaction touched, in ascending order. Observers are
+    /// told which attributes changed, not which entities.
     pub changes: Box<[Entid]>,
     pub changes_len: usize,
 }
 
+/// One attribute of the schema. `unique` is 0 if the attribute isn't unique, 1 for
+/// `:db.unique/value`, and 2 for `:db.unique/identity`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExternAttribute {
+    pub ident: *mut c_char,
+    pub entid: Entid,
+    pub value_type: ValueType,
+    pub multival: bool,
+    pub unique: c_int,
+    pub index: bool,
+    pub fulltext: bool,
+    pub component: bool,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExternTempId {
+    pub tempid: *mut c_char,
+    pub entid: Entid,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct ExternTxReportList {
@@ -75,6 +155,30 @@ pub struct ExternTxReportList {
     pub len: usize,
 }
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExternRecoveredStore {
+    pub store: *mut Store,
+    pub recovered: bool,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExternIntegrityReport {
+    pub problems: Box<[*mut c_char]>,
+    pub len: usize,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExternFileInfo {
+    pub path: *mut c_char,
+    pub db_size: u64,
+    pub wal_size: u64,
+    pub shm_size: u64,
+    pub journal_size: u64,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct ExternOption {
@@ -89,545 +193,2896 @@ impl<T> From<Option<T>> for ExternOption {
     }
 }
 
+/// What kind of failure an `ExternResult` reports, so callers can branch without parsing `err`.
+/// These values are part of the ABI: codes are only ever added, never renumbered.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExternErrorCode {
+    Ok = 0,
+    Other = 1,
+    Io = 2,
+    Sqlite = 3,
+    EdnParse = 4,
+    Query = 5,
+    Transaction = 6,
+    Unknown = 7,
+    ValueType = 8,
+    InvalidArgument = 9,
+    TransactionVetoed = 10,
+    Cancelled = 11,
+    Sync = 12,
+    Vocabulary = 13,
+    /// `err_detail` is a `UniqueConstraintViolation`.
+    UniqueConstraintViolation = 14,
+    /// The server has moved on since the last sync. Mentat can't merge its changes yet, so the
+    /// sync was refused and nothing was uploaded or downloaded.
+    SyncDiverged = 15,
+    /// Another connection's reads or writes kept a checkpoint from completing. Nothing was lost;
+    /// try again later.
+    CheckpointBlocked = 16,
+}
+
+fn error_code(kind: &ErrorKind) -> ExternErrorCode {
+    match *kind {
+        ErrorKind::IoError(_) => ExternErrorCode::Io,
+        ErrorKind::Rusqlite(_) |
+        ErrorKind::SqlError(_) => ExternErrorCode::Sqlite,
+        ErrorKind::EdnParseError(_) => ExternErrorCode::EdnParse,
+        ErrorKind::QueryError(_) |
+        ErrorKind::QueryParseError(_) |
+        ErrorKind::ProjectorError(_) |
+        ErrorKind::TranslatorError(_) |
+        ErrorKind::UnboundVariables(_) |
+        ErrorKind::OrderByUnknownVariable(_) |
+        ErrorKind::CannotCountAggregateQuery |
+        ErrorKind::PreparedQuerySchemaMismatch => ExternErrorCode::Query,
+        ErrorKind::DbError(DbErrorKind::UniqueConstraintViolation(_, _)) => ExternErrorCode::UniqueConstraintViolation,
+        ErrorKind::DbError(_) |
+        ErrorKind::TxParseError(_) |
+        ErrorKind::InvalidJsonTransaction(_) => ExternErrorCode::Transaction,
+        // The failing transaction's own code says more than the fact it was part of a batch.
+        ErrorKind::BatchTransactionFailed(_, ref cause) => error_code(cause),
+        ErrorKind::UnknownAttribute(_) |
+        ErrorKind::UnknownIdent(_) |
+        ErrorKind::UnknownTempId(_) => ExternErrorCode::Unknown,
+        ErrorKind::UnexpectedValueType(_, _) |
+        ErrorKind::CannotCoerce(_, _) |
+        ErrorKind::ValueTypeMismatch(_, _) => ExternErrorCode::ValueType,
+        ErrorKind::InvalidUuid(_) |
+        ErrorKind::InvalidKeyword(_) |
+        ErrorKind::InvalidUtf8(_) |
+        ErrorKind::UuidParseError(_) |
+        ErrorKind::InvalidArgumentName(_) |
+        ErrorKind::NoSuchColumn(_) |
+        ErrorKind::NotUniqueAttribute(_) |
+        ErrorKind::InvalidJournalMode(_) |
+        ErrorKind::PathAlreadyExists(_) |
+        ErrorKind::EmptyTransactionBatch |
+        ErrorKind::InMemoryStore => ExternErrorCode::InvalidArgument,
+        ErrorKind::TransactionVetoed => ExternErrorCode::TransactionVetoed,
+        ErrorKind::QueryCancelled => ExternErrorCode::Cancelled,
+        ErrorKind::CheckpointBlocked => ExternErrorCode::CheckpointBlocked,
+        ErrorKind::SyncError(SyncErrorKind::ServerDiverged(_, _)) => ExternErrorCode::SyncDiverged,
+        ErrorKind::SyncError(_) => ExternErrorCode::Sync,
+        ErrorKind::InvalidVocabularyVersion |
+        ErrorKind::ConflictingAttributeDefinitions(_, _, _, _, _) |
+        ErrorKind::ExistingVocabularyTooNew(_, _, _) |
+        ErrorKind::UnsupportedVocabularyVersion(_, _, _, _) |
+        ErrorKind::UnexpectedCoreSchema(_) |
+        ErrorKind::MissingCoreVocabulary(_) => ExternErrorCode::Vocabulary,
+        _ => ExternErrorCode::Other,
+    }
+}
+
+/// The value of a `:db/unique` attribute that a transaction tried to give to a second entity, as
+/// the `err_detail` of a failed `ExternResult`.
+#[derive(Debug)]
+pub struct UniqueConstraintViolation {
+    pub attribute: Entid,
+    pub value: TypedValue,
+}
+
+/// The structured detail behind an error, for those codes that have one, or null.
+fn error_detail(kind: &ErrorKind) -> *const c_void {
+    match *kind {
+        ErrorKind::DbError(DbErrorKind::UniqueConstraintViolation(attribute, ref value)) => {
+            match typed_value_from_edn(value.clone()) {
+                Some(value) => Box::into_raw(Box::new(UniqueConstraintViolation { attribute, value })) as *const c_void,
+                None => std::ptr::null(),
+            }
+        },
+        ErrorKind::BatchTransactionFailed(_, ref cause) => error_detail(cause),
+        _ => std::ptr::null(),
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct ExternResult {
     pub ok: *const c_void,
     pub err: *const c_char,
+    /// An `ExternErrorCode`: `Ok` on success.
+    pub err_code: c_int,
+    /// Detail for some error codes, whose type the code names; otherwise null. Freed with the
+    /// result by `extern_result_destroy`.
+    pub err_detail: *const c_void,
+}
+
+impl ExternResult {
+    /// A successful result whose `ok` owns `value`.
+    fn ok<T>(value: T) -> ExternResult {
+        ExternResult::ok_ptr(Box::into_raw(Box::new(value)) as *const _ as *const c_void)
+    }
+
+    /// A successful result whose `ok` is `ok`, which may be null for "no value".
+    fn ok_ptr(ok: *const c_void) -> ExternResult {
+        ExternResult {
+            ok: ok,
+            err: std::ptr::null(),
+            err_code: ExternErrorCode::Ok as c_int,
+            err_detail: std::ptr::null(),
+        }
+    }
+
+    /// A failed result for `e`. `err` is the error's full message, as for every failure.
+    fn err(e: &Error) -> ExternResult {
+        ExternResult {
+            ok: std::ptr::null(),
+            err: string_to_c_char(e.to_string()),
+            err_code: error_code(e.kind()) as c_int,
+            err_detail: error_detail(e.kind()),
+        }
+    }
+
+    /// A failed result for an error that isn't a Mentat `Error`.
+    fn err_with_code<T>(code: ExternErrorCode, message: T) -> ExternResult where T: Into<String> {
+        ExternResult {
+            ok: std::ptr::null(),
+            err: string_to_c_char(message),
+            err_code: code as c_int,
+            err_detail: std::ptr::null(),
+        }
+    }
 }
 
-impl<T, E> From<Result<T, E>> for ExternResult where E: std::error::Error {
+impl<T, E> From<Result<T, E>> for ExternResult where E: std::error::Error + 'static {
     fn from(result: Result<T, E>) -> Self {
         match result {
-            Ok(value) => {
-                ExternResult {
-                    err: std::ptr::null(),
-                    ok: Box::into_raw(Box::new(value)) as *const _ as *const c_void,
-                }
-            },
+            Ok(value) => ExternResult::ok(value),
             Err(e) => {
-                ExternResult {
-                    err: string_to_c_char(e.description()),
-                    ok: std::ptr::null(),
+                match (&e as &(std::error::Error + 'static)).downcast_ref::<Error>() {
+                    Some(e) => ExternResult::err(e),
+                    None => ExternResult::err_with_code(ExternErrorCode::Other, e.to_string()),
                 }
             }
         }
     }
 }
 
+/// Unwraps `$e`, or returns its error from the enclosing function as an `ExternResult`.
+macro_rules! try_extern (
+    ($e:expr) => (
+        match $e {
+            Ok(v) => v,
+            Err(e) => return Box::into_raw(Box::new(ExternResult::err(&e))),
+        }
+    )
+);
+
 // A store cannot be opened twice to the same location.
 // Once created, the reference to the store is held by the caller and not Rust,
 // therefore the caller is responsible for calling `destroy` to release the memory
 // used by the Store in order to avoid a memory leak.
-// TODO: Start returning `ExternResult`s rather than crashing on error.
+// On success, `ok` is the `Store`; on failure, `err` describes what went wrong.
+#[no_mangle]
+pub extern "C" fn store_open(uri: *const c_char) -> *mut ExternResult {
+    let uri = c_char_to_string(uri);
+    let res = Store::open(&uri);
+    Box::into_raw(Box::new(res.into()))
+}
+
+// TODO: `store_open_encrypted(uri, key)` and `store_change_encryption_key(store, key)`. Mentat has
+// no encrypted open path yet: rusqlite isn't built against SQLCipher, so there's nowhere to thread
+// a key to. Once it is, `PRAGMA key` must run before anything else touches the connection, and a
+// wrong key should come back as an `err` rather than a panic.
+
+/// The old behaviour of `store_open`: returns the store, aborting the process if it can't be
+/// opened. Kept for one release while callers move to `store_open`.
 #[no_mangle]
-pub extern "C" fn store_open(uri: *const c_char) -> *mut Store {
+pub extern "C" fn store_open_or_panic(uri: *const c_char) -> *mut Store {
     let uri = c_char_to_string(uri);
     let store = Store::open(&uri).expect("expected a store");
     Box::into_raw(Box::new(store))
 }
 
-// TODO: open empty
+/// Opens the store at `uri` unless it holds a vocabulary, other than the core schema, whose
+/// version is outside `min_version..=max_version`. On success, `ok` is the `Store`.
+#[no_mangle]
+pub extern "C" fn store_open_with_version(uri: *const c_char, min_version: u32, max_version: u32) -> *mut ExternResult {
+    let uri = c_char_to_string(uri);
+    let res = Store::open_with_version(&uri, min_version, max_version);
+    Box::into_raw(Box::new(res.into()))
+}
 
-// TODO: dismantle
+/// Opens the store at `uri`, first moving it to `backup_path` and starting afresh if it is
+/// corrupt. On success, `ok` is an `ExternRecoveredStore` whose `recovered` flag says whether that
+/// happened. The caller owns its `store`; free the wrapper with `recovered_store_destroy`.
+#[no_mangle]
+pub extern "C" fn store_open_or_recover(uri: *const c_char, backup_path: *const c_char) -> *mut ExternResult {
+    let uri = c_char_to_string(uri);
+    let backup_path = c_char_to_string(backup_path);
+    let res = Store::open_or_recover(&uri, &backup_path).map(|(store, recovered)| {
+        ExternRecoveredStore {
+            store: Box::into_raw(Box::new(store)),
+            recovered: recovered,
+        }
+    });
+    Box::into_raw(Box::new(res.into()))
+}
 
-// TODO: conn
+// TODO: open empty
 
-// TODO: begin_read
+/// A store that can be used from several threads at once. Every `shared_store_*` call takes the
+/// store's lock for its duration, so calls from different threads run one after another rather
+/// than racing. That safety costs a lock per call, and a long transaction or query holds up every
+/// other thread; a consumer that already confines a store to one thread should keep using the raw
+/// `*mut Store` functions, which don't lock.
+pub struct SharedStore(Arc<Mutex<Store>>);
+
+// `Store` isn't `Send`: its connection, caches and values are full of `Rc`s, whose counts aren't
+// atomic. They're only safe to touch from one thread at a time, which the lock guarantees, and
+// only if none of them escapes it. So every `shared_store_*` call builds its `ExternResult` while
+// holding the lock, and hands out values that share nothing with the store (see `unshared`).
+unsafe impl Send for SharedStore {}
+unsafe impl Sync for SharedStore {}
+
+impl SharedStore {
+    fn with_store<F, T>(&self, f: F) -> T where F: FnOnce(&mut Store) -> T {
+        let mut store = self.0.lock().unwrap();
+        f(&mut store)
+    }
+}
 
-// TODO: begin_transaction
+/// A copy of `value` that shares no `Rc` with it, so that it can be handed to a thread that
+/// doesn't hold the store's lock.
+fn unshared(value: TypedValue) -> TypedValue {
+    match value {
+        TypedValue::String(s) => TypedValue::String(Rc::new((*s).clone())),
+        TypedValue::Keyword(k) => TypedValue::Keyword(Rc::new((*k).clone())),
+        value => value,
+    }
+}
 
-// TODO: cache
+/// Like `store_open`, but `ok` is a `SharedStore`, which may be used from any thread. Free it with
+/// `shared_store_destroy`.
+#[no_mangle]
+pub extern "C" fn store_open_shared(uri: *const c_char) -> *mut ExternResult {
+    let uri = c_char_to_string(uri);
+    let res = Store::open(&uri).map(|store| SharedStore(Arc::new(Mutex::new(store))));
+    Box::into_raw(Box::new(res.into()))
+}
 
-// TODO: q_once
+/// Another handle to the same shared store, for handing to another thread. The store is closed
+/// once every handle has been destroyed.
 #[no_mangle]
-pub unsafe extern "C" fn store_query<'a>(store: *mut Store, query: *const c_char) -> *mut QueryBuilder<'a> {
-    let query = c_char_to_string(query);
-    let store = &mut*store;
-    let query_builder = QueryBuilder::new(store, query);
-    Box::into_raw(Box::new(query_builder))
+pub unsafe extern "C" fn shared_store_clone(store: *const SharedStore) -> *mut SharedStore {
+    let store = &*store;
+    Box::into_raw(Box::new(SharedStore(store.0.clone())))
 }
 
+/// Like `store_transact`, but safe to call while other threads use the store.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_int(query_builder: *mut QueryBuilder, var: *const c_char, value: c_int) {
-    let var = c_char_to_string(var);
-    let query_builder = &mut*query_builder;
-    let value = value as i32;
-    query_builder.bind_value(&var, value);
+pub unsafe extern "C" fn shared_store_transact(store: *const SharedStore, transaction: *const c_char) -> *mut ExternResult {
+    let store = &*store;
+    let transaction = c_char_to_string(transaction);
+    let res: ExternResult = store.with_store(|store| store.transact(&transaction).into());
+    Box::into_raw(Box::new(res))
 }
 
+/// Runs `query`, which takes no inputs, and returns its rows as `query_builder_execute` does.
+/// Safe to call while other threads use the store.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_long(query_builder: *mut QueryBuilder, var: *const c_char, value: i64) {
-    let var = c_char_to_string(var);
-    let query_builder = &mut*query_builder;
-   query_builder.bind_long(&var, value);
+pub unsafe extern "C" fn shared_store_query(store: *const SharedStore, query: *const c_char) -> *mut ExternResult {
+    let store = &*store;
+    let query = c_char_to_string(query);
+    let res: ExternResult = store.with_store(|store| {
+        QueryBuilder::new(store, query).execute_rel()
+                                       .map(|rows| rows.into_iter()
+                                                       .map(|row| row.into_iter().map(unshared).collect::<Vec<TypedValue>>())
+                                                       .collect::<Vec<Vec<TypedValue>>>())
+                                       .into()
+    });
+    Box::into_raw(Box::new(res))
 }
 
+/// Like `store_value_for_attribute`, but safe to call while other threads use the store.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_ref(query_builder: *mut QueryBuilder, var: *const c_char, value: i64) {
-    let var = c_char_to_string(var);
-    let query_builder = &mut*query_builder;
-    query_builder.bind_ref(&var, value);
+pub unsafe extern "C" fn shared_store_value_for_attribute(store: *const SharedStore, entid: i64, attribute: *const c_char) -> *mut ExternResult {
+    let store = &*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let value = store.with_store(|store| {
+        match store.lookup_value_for_attribute(entid, &kw) {
+            Ok(Some(v)) => ExternResult::ok(unshared(v)),
+            Ok(None) => ExternResult::ok_ptr(std::ptr::null()),
+            Err(e) => ExternResult::err(&e),
+        }
+    });
+    Box::into_raw(Box::new(value))
 }
 
+/// Like `store_flush`, but safe to call while other threads use the store.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_ref_kw(query_builder: *mut QueryBuilder, var: *const c_char, value: *const c_char) {
-    let var = c_char_to_string(var);
-    let kw = kw_from_string(c_char_to_string(value));
-    let query_builder = &mut*query_builder;
-    if let Some(err) = query_builder.bind_ref_from_kw(&var, kw).err() {
-        panic!(err);
-    }
+pub unsafe extern "C" fn shared_store_flush(store: *const SharedStore) -> *mut ExternResult {
+    let store = &*store;
+    let res = store.with_store(|store| {
+        match store.flush() {
+            Ok(()) => ExternResult::ok(()),
+            Err(e) => ExternResult::err(&e),
+        }
+    });
+    Box::into_raw(Box::new(res))
 }
 
+/// Shut the store down gracefully and free it: checkpoint its log into the database file, drop
+/// every observer, and close the underlying SQLite connection, so the same URI can be opened
+/// again immediately. If the checkpoint fails, `err` says why and the store is left open and
+/// owned by the caller, who may retry or fall back to `store_destroy`.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_kw(query_builder: *mut QueryBuilder, var: *const c_char, value: *const c_char) {
-    let var = c_char_to_string(var);
-    let query_builder = &mut*query_builder;
-    let kw = kw_from_string(c_char_to_string(value));
-    query_builder.bind_value(&var, kw);
+pub unsafe extern "C" fn store_dismantle(store: *mut Store) -> *mut ExternResult {
+    if let Err(e) = (&*store).flush() {
+        return Box::into_raw(Box::new(ExternResult::err(&e)));
+    }
+    let mut store = Box::from_raw(store);
+    store.unregister_all_observers();
+    let res = store.close();
+    Box::into_raw(Box::new(res.into()))
 }
 
-// boolean
+/// Checkpoint the store's write-ahead log into the database file and truncate it, so that every
+/// committed write is durable in the main file; call it before the app is backgrounded. If another
+/// connection blocks the checkpoint, or SQLite reports an IO error, `err` says so and it's safe to
+/// try again. Does nothing for a store that isn't in WAL mode.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_boolean(query_builder: *mut QueryBuilder, var: *const c_char, value: bool) {
-    let var = c_char_to_string(var);
-    let query_builder = &mut*query_builder;
-    query_builder.bind_value(&var, value);
+pub unsafe extern "C" fn store_flush(store: *mut Store) -> *mut ExternResult {
+    let store = &*store;
+    let res = match store.flush() {
+        Ok(()) => ExternResult::ok(()),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(res))
 }
 
-// double
+/// Make writes that find the database locked wait up to `millis` milliseconds before failing.
+/// A newly opened store waits up to five seconds; zero or a negative value makes a locked
+/// database fail at once.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_double(query_builder: *mut QueryBuilder, var: *const c_char, value: f64) {
-    let var = c_char_to_string(var);
-    let query_builder = &mut*query_builder;
-    query_builder.bind_value(&var, value);
+pub unsafe extern "C" fn store_set_busy_timeout(store: *mut Store, millis: c_int) -> *mut ExternResult {
+    let store = &mut*store;
+    let res = store.set_busy_timeout(Duration::from_millis(millis.max(0) as u64));
+    Box::into_raw(Box::new(res.into()))
 }
 
-// instant
+/// Switches the journal mode: 0 for DELETE, 1 for MEMORY, 2 for WAL. On success `ok` points to
+/// the `c_int` code of the mode in effect afterwards, which may differ from the one asked for.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_timestamp(query_builder: *mut QueryBuilder, var: *const c_char, value: time_t) {
-    let var = c_char_to_string(var);
-    let query_builder = &mut*query_builder;
-    query_builder.bind_instant(&var, value as i64);
+pub unsafe extern "C" fn store_set_journal_mode(store: *mut Store, mode: c_int) -> *mut ExternResult {
+    let store = &mut*store;
+    let mode = match mode {
+        0 => Ok(JournalMode::Delete),
+        1 => Ok(JournalMode::Memory),
+        2 => Ok(JournalMode::Wal),
+        _ => Err(Error::from(ErrorKind::InvalidJournalMode(mode.to_string()))),
+    };
+    let res: Result<c_int, Error> = mode.and_then(|mode| store.set_journal_mode(mode))
+                                        .map(|effective| match effective {
+                                            JournalMode::Delete => 0,
+                                            JournalMode::Memory => 1,
+                                            JournalMode::Wal => 2,
+                                        });
+    Box::into_raw(Box::new(res.into()))
 }
 
-// string
+/// Returns an `ExternFileInfo` describing the database file and its journals. `path` is null for
+/// an in-memory store. Free the result with `store_file_info_destroy`.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_string(query_builder: *mut QueryBuilder, var: *const c_char, value: *const c_char) {
-    let var = c_char_to_string(var);
-    let value = c_char_to_string(value);
-    let query_builder = &mut*query_builder;
-    query_builder.bind_value(&var, value);
+pub unsafe extern "C" fn store_file_info(store: *mut Store) -> *mut ExternResult {
+    let store = &*store;
+    let res = store.file_info().map(|info| {
+        ExternFileInfo {
+            path: info.path.map_or(std::ptr::null_mut(), |p| string_to_c_char(p.to_string_lossy())),
+            db_size: info.db_size,
+            wal_size: info.wal_size,
+            shm_size: info.shm_size,
+            journal_size: info.journal_size,
+        }
+    });
+    Box::into_raw(Box::new(res.into()))
 }
 
-// uuid
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_bind_uuid(query_builder: *mut QueryBuilder, var: *const c_char, value: *const c_char) {
-    let var = c_char_to_string(var);
-    let value = Uuid::parse_str(&c_char_to_string(value)).expect("valid uuid");
-    let query_builder = &mut*query_builder;
-    query_builder.bind_value(&var, value);
+pub unsafe extern "C" fn store_file_info_destroy(info: *mut ExternFileInfo) {
+    if info.is_null() {
+        return;
+    }
+    let info = Box::from_raw(info);
+    if !info.path.is_null() {
+        let _ = CString::from_raw(info.path);
+    }
 }
 
+/// Checks the database for corruption and returns an `ExternIntegrityReport` listing one
+/// description per problem found; `len` is zero for a healthy store. Free the result with
+/// `store_integrity_report_destroy`.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_execute_scalar(query_builder: *mut QueryBuilder) -> *mut ExternResult {
-    let query_builder = &mut*query_builder;
-    let results = query_builder.execute_scalar();
-    Box::into_raw(Box::new(results.into()))
+pub unsafe extern "C" fn store_integrity_check(store: *mut Store) -> *mut ExternResult {
+    let store = &*store;
+    let res = store.integrity_check().map(|report| {
+        let problems: Vec<*mut c_char> = report.problems().into_iter().map(string_to_c_char).collect();
+        ExternIntegrityReport {
+            len: problems.len(),
+            problems: problems.into_boxed_slice(),
+        }
+    });
+    Box::into_raw(Box::new(res.into()))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_execute_coll(query_builder: *mut QueryBuilder) -> *mut ExternResult {
-    let query_builder = &mut*query_builder;
-    let results = query_builder.execute_coll();
-    Box::into_raw(Box::new(results.into()))
+pub unsafe extern "C" fn store_integrity_report_destroy(report: *mut ExternIntegrityReport) {
+    if report.is_null() {
+        return;
+    }
+    let report = Box::from_raw(report);
+    for problem in report.problems.iter() {
+        let _ = CString::from_raw(*problem);
+    }
 }
 
+// TODO: conn
+
+/// Transacts the EDN in `transaction`. On success, `ok` holds the `TxReport`; free it with
+/// `tx_report_destroy`. `transaction` must be valid UTF-8; if it isn't, nothing is transacted
+/// and `err` says so.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_execute_tuple(query_builder: *mut QueryBuilder) -> *mut ExternResult {
-    let query_builder = &mut*query_builder;
-    let results = query_builder.execute_tuple();
-    Box::into_raw(Box::new(results.into()))
+pub unsafe extern "C" fn store_transact(store: *mut Store, transaction: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let res = match c_char_to_string_checked(transaction).and_then(|transaction| store.transact(&transaction)) {
+        Ok(report) => ExternResult::ok(report),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(res))
 }
 
+/// Transacts each of the `count` EDN strings in `transactions`, in order, and commits them all at
+/// once, which is far cheaper than a commit per string. On success `ok` is the `TxReport` of the
+/// last. If any string fails, nothing is committed and `err` names the index of the one that failed;
+/// `err_code` and `err_detail` are those that string's own failure would have.
 #[no_mangle]
-pub unsafe extern "C" fn query_builder_execute(query_builder: *mut QueryBuilder) -> *mut ExternResult {
-    let query_builder = &mut*query_builder;
-    let results = query_builder.execute_rel();
-    Box::into_raw(Box::new(results.into()))
+pub unsafe extern "C" fn store_transact_many(store: *mut Store, transactions: *const *const c_char, count: usize) -> *mut ExternResult {
+    let store = &mut*store;
+    let transactions = c_char_array_to_strings(transactions, count);
+    let res = match store.transact_many(&transactions) {
+        Ok(report) => ExternResult::ok(report),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(res))
 }
 
-// as_long
+/// Transacts each of the `count` EDN strings in `transactions` as a transaction of its own, in
+/// order, so that one failing doesn't undo the others. Returns an array of `count` results, the
+/// `i`th for the `i`th string: on success its `ok` holds the `TxReport`. The array is allocated by
+/// Rust; free each `ok` with `tx_report_destroy` and then the array with
+/// `extern_result_list_destroy`, passing the same `count`. Null if `count` is zero.
 #[no_mangle]
-pub unsafe extern "C" fn typed_value_as_long(typed_value: *mut TypedValue) ->  i64 {
-    let typed_value = Box::from_raw(typed_value);
-    typed_value.into_long().expect("Typed value cannot be coerced into a Long")
+pub unsafe extern "C" fn store_transact_each(store: *mut Store, transactions: *const *const c_char, count: usize) -> *mut ExternResult {
+    if count == 0 {
+        return std::ptr::null_mut();
+    }
+    let store = &mut*store;
+    let results: Vec<ExternResult> = slice::from_raw_parts(transactions, count).iter().map(|transaction| {
+        match c_char_to_string_checked(*transaction).and_then(|transaction| store.transact(&transaction)) {
+            Ok(report) => ExternResult::ok(report),
+            Err(e) => ExternResult::err(&e),
+        }
+    }).collect();
+    Box::into_raw(results.into_boxed_slice()) as *mut ExternResult
 }
 
-// as_entid
+/// Checks that `transaction` would transact against the store's schema, then rolls it back.
+/// `err` is null if it would, and otherwise carries the full message `store_transact` would have
+/// failed with, including the position of any EDN syntax error. Nothing is committed.
 #[no_mangle]
-pub unsafe extern "C" fn typed_value_as_entid(typed_value: *mut TypedValue) ->  Entid {
-    let typed_value = Box::from_raw(typed_value);
-    typed_value.into_entid().expect("Typed value cannot be coerced into an Entid")
+pub unsafe extern "C" fn store_validate_transaction(store: *mut Store, transaction: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let res = match c_char_to_string_checked(transaction).and_then(|transaction| store.validate_transaction(&transaction)) {
+        Ok(()) => ExternResult::ok(()),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(res))
 }
 
-// kw
+/// Transacts the file at `path`, a sequence of EDN transactions, reading it a line at a time and
+/// committing 500 transactions at a time. On success `ok` points to the number of transactions
+/// applied, a `usize`. IO and parse errors are reported in `err`, which names the index of a
+/// failing transaction; transactions committed before it stay applied.
 #[no_mangle]
-pub unsafe extern "C" fn typed_value_as_kw(typed_value: *mut TypedValue) ->  *const c_char {
-    let typed_value = Box::from_raw(typed_value);
-    string_to_c_char(typed_value.into_kw().expect("Typed value cannot be coerced into a Namespaced Keyword").to_string())
+pub unsafe extern "C" fn store_import_edn_file(store: *mut Store, path: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let path = c_char_to_string(path);
+    let res = match store.import_edn_file(&path) {
+        Ok(applied) => ExternResult::ok(applied),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(res))
 }
 
-//as_boolean
+/// Writes the store's datoms to the file at `path` as EDN that `store_import_edn_file` can read
+/// back: schema first, then data. Bootstrap datoms are written only if `include_system` is true.
+/// On success `ok` points to the number of datoms written, a `usize`.
 #[no_mangle]
-pub unsafe extern "C" fn typed_value_as_boolean(typed_value: *mut TypedValue) ->  bool {
-    let typed_value = Box::from_raw(typed_value);
-    typed_value.into_boolean().expect("Typed value cannot be coerced into a Boolean")
+pub unsafe extern "C" fn store_export_edn(store: *mut Store, path: *const c_char, include_system: bool) -> *mut ExternResult {
+    let store = &*store;
+    let path = c_char_to_string(path);
+    let res = match store.export_edn_file(&path, include_system) {
+        Ok(written) => ExternResult::ok(written),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(res))
 }
 
-//as_double
+/// Reads the transactions committed after `tx` from the log, oldest first. On success `ok` holds a
+/// `TxLog`, read with `tx_log_len` and `tx_log_at`, and freed with `tx_log_destroy`. Pass `0` for
+/// the whole history.
 #[no_mangle]
-pub unsafe extern "C" fn typed_value_as_double(typed_value: *mut TypedValue) ->  f64 {
-    let typed_value = Box::from_raw(typed_value);
-    typed_value.into_double().expect("Typed value cannot be coerced into a Double")
+pub unsafe extern "C" fn store_transactions_since(store: *mut Store, tx: Entid) -> *mut ExternResult {
+    let store = &*store;
+    let res: Result<TxLog, Error> = store.transactions_since(tx);
+    Box::into_raw(Box::new(res.into()))
 }
 
-//as_timestamp
 #[no_mangle]
-pub unsafe extern "C" fn typed_value_as_timestamp(typed_value: *mut TypedValue) ->  i64 {
-    let typed_value = Box::from_raw(typed_value);
-    let val = typed_value.into_timestamp().expect("Typed value cannot be coerced into a Timestamp");
-    val
+pub unsafe extern "C" fn tx_log_len(log: *const TxLog) -> usize {
+    let log = &*log;
+    log.len()
 }
 
-//as_string
+/// Returns the transaction at `index`, or null if `index` is out of range. It is borrowed from
+/// `log` and must not be freed separately.
 #[no_mangle]
-pub unsafe extern "C" fn typed_value_as_string(typed_value: *mut TypedValue) ->  *const c_char {
-    let typed_value = Box::from_raw(typed_value);
-    c_char_from_rc(typed_value.into_string().expect("Typed value cannot be coerced into a String"))
+pub unsafe extern "C" fn tx_log_at(log: *const TxLog, index: usize) -> *const LoggedTransaction {
+    let log = &*log;
+    log.get(index).map_or(std::ptr::null(), |tx| tx as *const LoggedTransaction)
 }
 
-//as_uuid
 #[no_mangle]
-pub unsafe extern "C" fn typed_value_as_uuid(typed_value: *mut TypedValue) ->  *const c_char {
-    let typed_value = Box::from_raw(typed_value);
-    string_to_c_char(typed_value.into_uuid_string().expect("Typed value cannot be coerced into a Uuid"))
+pub unsafe extern "C" fn logged_tx_id(tx: *const LoggedTransaction) -> Entid {
+    let tx = &*tx;
+    tx.tx
 }
 
+/// The transaction's `:db/txInstant`, in microseconds since the epoch.
 #[no_mangle]
-pub unsafe extern "C" fn row_at_index(rows: *mut Vec<Vec<TypedValue>>, index: c_int) ->  *mut Vec<TypedValue> {
-    let result = &*rows;
-    result.get(index as usize).map_or(std::ptr::null_mut(), |v| Box::into_raw(Box::new(v.clone())))
+pub unsafe extern "C" fn logged_tx_instant_micros(tx: *const LoggedTransaction) -> i64 {
+    let tx = &*tx;
+    tx.instant.timestamp() * 1_000_000 + i64::from(tx.instant.timestamp_subsec_micros())
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn rows_iter(rows: *mut Vec<Vec<TypedValue>>) ->  *mut TypedValueListIterator {
-    let result = Box::from_raw(rows);
-    Box::into_raw(Box::new(result.into_iter()))
+pub unsafe extern "C" fn logged_tx_datoms_len(tx: *const LoggedTransaction) -> usize {
+    let tx = &*tx;
+    tx.datoms.len()
 }
 
+/// The entity of the datom at `index`. `index` must be in range.
 #[no_mangle]
-pub unsafe extern "C" fn rows_iter_next(iter: *mut TypedValueListIterator) ->  *mut Vec<TypedValue> {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| Box::into_raw(Box::new(v)))
+pub unsafe extern "C" fn logged_tx_datom_entity_at(tx: *const LoggedTransaction, index: usize) -> Entid {
+    let tx = &*tx;
+    tx.datoms[index].0
 }
 
+/// The attribute entid of the datom at `index`. `index` must be in range.
 #[no_mangle]
-pub unsafe extern "C" fn values_iter(values: *mut Vec<TypedValue>) ->  *mut TypedValueIterator {
-    let result = Box::from_raw(values);
-    Box::into_raw(Box::new(result.into_iter()))
+pub unsafe extern "C" fn logged_tx_datom_attribute_at(tx: *const LoggedTransaction, index: usize) -> Entid {
+    let tx = &*tx;
+    tx.datoms[index].1
 }
 
+/// Returns the value of the datom at `index`, or null if `index` is out of range. The value is
+/// borrowed from `tx` and lives as long as it does.
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next(iter: *mut TypedValueIterator) ->  *const TypedValue {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| &v as *const TypedValue)
+pub unsafe extern "C" fn logged_tx_datom_value_at(tx: *const LoggedTransaction, index: usize) -> *const TypedValue {
+    let tx = &*tx;
+    tx.datoms.get(index).map_or(std::ptr::null(), |&(_, _, ref value, _)| value as *const TypedValue)
 }
 
-//as_long
+/// Whether the datom at `index` was asserted, rather than retracted. `index` must be in range.
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next_as_long(iter: *mut TypedValueIterator) ->  *const i64 {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| &v.into_long().expect("Typed value cannot be coerced into a Long") as *const i64)
+pub unsafe extern "C" fn logged_tx_datom_added_at(tx: *const LoggedTransaction, index: usize) -> bool {
+    let tx = &*tx;
+    tx.datoms[index].3
 }
-// as ref
+
+/// Transacts the EDN read from the open file descriptor `fd`, which stays owned by the caller
+/// and is left open. Reading starts at the descriptor's current offset. The input may hold
+/// several transactions, which are read and applied one at a time and committed together; see
+/// `Store::transact_reader`.
+#[cfg(unix)]
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next_as_entid(iter: *mut TypedValueIterator) ->  *const Entid {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| &v.into_entid().expect("Typed value cannot be coerced into am Entid") as *const Entid)
+pub unsafe extern "C" fn store_transact_fd(store: *mut Store, fd: c_int) -> *mut ExternResult {
+    let store = &mut*store;
+    let file = File::from_raw_fd(fd);
+    let res = store.transact_reader(&file);
+    // Don't close the caller's descriptor.
+    file.into_raw_fd();
+    Box::into_raw(Box::new(res.into()))
 }
 
-// as kw
+/// Sets `callback` to run before each `store_transact` commits, with the pending report. A
+/// nonzero return rolls the transaction back and `store_transact` fails. The callback must not
+/// call back into the store to write: the write lock is held while it runs. The report is only
+/// valid for the duration of the call and must not be destroyed by the callback.
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next_as_kw(iter: *mut TypedValueIterator) ->  *const c_char {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| string_to_c_char(v.into_kw().expect("Typed value cannot be coerced into a Namespaced Keyword").to_string()))
+pub unsafe extern "C" fn store_set_transaction_validator(store: *mut Store, callback: extern fn(report: *mut TxReport) -> c_int) {
+    let store = &mut*store;
+    store.set_transaction_validator(Some(move |report: &TxReport| {
+        callback(report as *const TxReport as *mut TxReport) == 0
+    }));
 }
 
-//as_boolean
+/// Removes the validator set by `store_set_transaction_validator`.
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next_as_boolean(iter: *mut TypedValueIterator) ->  *const bool {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| &v.into_boolean().expect("Typed value cannot be coerced into a Boolean") as *const bool)
+pub unsafe extern "C" fn store_clear_transaction_validator(store: *mut Store) {
+    let store = &mut*store;
+    store.set_transaction_validator(None::<fn(&TxReport) -> bool>);
 }
 
-//as_double
+/// Retract all data in the user partition, keeping the installed schema.
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next_as_double(iter: *mut TypedValueIterator) ->  *const f64 {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| &v.into_double().expect("Typed value cannot be coerced into a Double") as *const f64)
+pub unsafe extern "C" fn store_clear_user_data(store: *mut Store) -> *mut ExternResult {
+    let store = &mut*store;
+    let res = store.clear_user_data();
+    Box::into_raw(Box::new(res.into()))
 }
 
-//as_timestamp
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next_as_timestamp(iter: *mut TypedValueIterator) ->  *const i64 {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| v.into_timestamp().expect("Typed value cannot be coerced into a Timestamp") as *const i64)
+pub unsafe extern "C" fn tx_report_get_entid(tx_report: *mut TxReport) -> Entid {
+    let tx_report = &*tx_report;
+    tx_report.tx_id
 }
 
-//as_string
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next_as_string(iter: *mut TypedValueIterator) ->  *const c_char {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| c_char_from_rc(v.into_string().expect("Typed value cannot be coerced into a String")))
+pub unsafe extern "C" fn tx_report_get_tx_instant(tx_report: *mut TxReport) -> i64 {
+    let tx_report = &*tx_report;
+    tx_report.tx_instant.timestamp()
 }
 
-//as_uuid
 #[no_mangle]
-pub unsafe extern "C" fn values_iter_next_as_uuid(iter: *mut TypedValueIterator) ->  *const c_char {
-    let iter = &mut *iter;
-    iter.next().map_or(std::ptr::null_mut(), |v| string_to_c_char(v.into_uuid_string().expect("Typed value cannot be coerced into a Uuid")))
+pub unsafe extern "C" fn tx_report_entity_for_temp_id(tx_report: *mut TxReport, tempid: *const c_char) -> *mut Entid {
+    let tx_report = &*tx_report;
+    let key = c_char_to_string(tempid);
+    tx_report.tempids.get(&key).map_or(std::ptr::null_mut(), |entid| Box::into_raw(Box::new(*entid)))
 }
 
+/// Returns an iterator over every tempid resolved by the transaction, in tempid order. The
+/// iterator holds its own copy of the mappings, so `tx_report` may be freed before it.
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index(values: *mut Vec<TypedValue>, index: c_int) ->  *const TypedValue {
-    let result = &*values;
-    result.get(index as usize).expect("No value at index") as *const TypedValue
+pub unsafe extern "C" fn tx_report_tempids_iter(tx_report: *mut TxReport) -> *mut TempIdIterator {
+    let tx_report = &*tx_report;
+    let tempids: Vec<(String, Entid)> = tx_report.tempids.iter().map(|(tempid, entid)| (tempid.clone(), *entid)).collect();
+    Box::into_raw(Box::new(tempids.into_iter()))
 }
 
-//as_long
+/// Returns an iterator over the entities whose datoms the transaction asserted or retracted, in
+/// entid order, so a caller can refresh exactly those after its own transact. The transaction
+/// entity itself isn't included. The iterator holds its own copy, so `tx_report` may be freed
+/// before it.
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index_as_long(values: *mut Vec<TypedValue>, index: c_int) ->  i64 {
+pub unsafe extern "C" fn tx_report_changed_entids_iter(tx_report: *mut TxReport) -> *mut EntidIterator {
+    let tx_report = &*tx_report;
+    let entids: Vec<Entid> = tx_report.changed_entids.iter().cloned().collect();
+    Box::into_raw(Box::new(entids.into_iter()))
+}
+
+/// The number of entids not yet returned by `iter`.
+#[no_mangle]
+pub unsafe extern "C" fn entid_iter_remaining(iter: *const EntidIterator) -> usize {
+    let iter = &*iter;
+    iter.len()
+}
+
+/// Returns the next entid, to be freed with `entid_destroy`, or null when there are no more.
+#[no_mangle]
+pub unsafe extern "C" fn entid_iter_next(iter: *mut EntidIterator) -> *mut Entid {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |entid| Box::into_raw(Box::new(entid)))
+}
+
+/// Returns the next mapping, to be freed with `extern_tempid_destroy`, or null when there are no
+/// more.
+#[no_mangle]
+pub unsafe extern "C" fn tempid_iter_next(iter: *mut TempIdIterator) -> *mut ExternTempId {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |(tempid, entid)| {
+        Box::into_raw(Box::new(ExternTempId {
+            tempid: string_to_c_char(tempid),
+            entid: entid,
+        }))
+    })
+}
+
+/// Frees a C string returned by this library. Every string handed to the caller, including the
+/// `err` of an `ExternResult`, is allocated by Rust, and must be freed here rather than with
+/// `free`, which may use a different allocator.
+#[no_mangle]
+pub unsafe extern "C" fn rust_string_destroy(s: *mut c_char) {
+    if !s.is_null() {
+        let _ = CString::from_raw(s);
+    }
+}
+
+/// Whether `result` succeeded. Prefer these accessors to reading the struct's fields, whose layout
+/// may change.
+#[no_mangle]
+pub unsafe extern "C" fn extern_result_is_ok(result: *const ExternResult) -> bool {
+    let result = &*result;
+    result.err.is_null()
+}
+
+/// The success payload of `result`, whose type depends on the function that returned it, or null
+/// on failure. Some functions also succeed with a null payload, meaning "none".
+#[no_mangle]
+pub unsafe extern "C" fn extern_result_ok(result: *const ExternResult) -> *const c_void {
+    let result = &*result;
+    result.ok
+}
+
+/// The error message of `result`, or null on success. The string belongs to `result` and is freed
+/// with it by `extern_result_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn extern_result_err(result: *const ExternResult) -> *const c_char {
+    let result = &*result;
+    result.err
+}
+
+/// The `ExternErrorCode` of `result`: `Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn extern_result_err_code(result: *const ExternResult) -> c_int {
+    let result = &*result;
+    result.err_code
+}
+
+/// The `err_detail` of `result`: see `ExternErrorCode` for its type. It belongs to `result` and
+/// is freed with it by `extern_result_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn extern_result_err_detail(result: *const ExternResult) -> *const c_void {
+    let result = &*result;
+    result.err_detail
+}
+
+/// The entid of the attribute whose unique value `violation` would have duplicated.
+#[no_mangle]
+pub unsafe extern "C" fn unique_violation_attribute(violation: *const UniqueConstraintViolation) -> Entid {
+    let violation = &*violation;
+    violation.attribute
+}
+
+/// The value that's already taken, as a `TypedValue` owned by the caller.
+#[no_mangle]
+pub unsafe extern "C" fn unique_violation_value(violation: *const UniqueConstraintViolation) -> *mut TypedValue {
+    let violation = &*violation;
+    Box::into_raw(Box::new(violation.value.clone()))
+}
+
+/// Frees an `ExternResult`, its `err` string and its `err_detail`. `ok` isn't freed, as its type isn't known here:
+/// hand it to the destructor for its type first, or keep it.
+#[no_mangle]
+pub unsafe extern "C" fn extern_result_destroy(result: *mut ExternResult) {
+    if result.is_null() {
+        return;
+    }
+    let result = Box::from_raw(result);
+    free_result_error(&result);
+}
+
+/// Frees the `err` string and `err_detail` of `result`, which must not be used again.
+unsafe fn free_result_error(result: &ExternResult) {
+    rust_string_destroy(result.err as *mut c_char);
+    if !result.err_detail.is_null() && result.err_code == ExternErrorCode::UniqueConstraintViolation as c_int {
+        let _ = Box::from_raw(result.err_detail as *mut UniqueConstraintViolation);
+    }
+}
+
+/// Frees a string returned as an `ExternString`, and its bytes.
+#[no_mangle]
+pub unsafe extern "C" fn extern_string_destroy(string: *mut ExternString) {
+    if string.is_null() {
+        return;
+    }
+    let string = Box::from_raw(string);
+    let _ = Box::from_raw(slice::from_raw_parts_mut(string.bytes, string.len) as *mut [u8]);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn extern_tempid_destroy(tempid: *mut ExternTempId) {
+    if tempid.is_null() {
+        return;
+    }
+    let tempid = Box::from_raw(tempid);
+    let _ = CString::from_raw(tempid.tempid);
+}
+
+/// Begins a read transaction. On success, `ok` is an `InProgressRead` handle; every query made
+/// with `in_progress_read_query` sees the store as it was when the first of them ran, whatever is
+/// written meanwhile by other connections.
+///
+/// The handle borrows the store mutably: until it is freed with `in_progress_read_destroy`, which
+/// ends the transaction and releases its snapshot, the store must not be used or destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn store_begin_read<'m>(store: *mut Store) -> *mut ExternResult {
+    let store = &mut*store;
+    let res: Result<InProgressRead<'m, 'm>, Error> = store.begin_read();
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Creates a `QueryBuilder` for `query` that runs in the read transaction. Free each builder with
+/// `query_builder_destroy` before freeing the read handle.
+#[no_mangle]
+pub unsafe extern "C" fn in_progress_read_query<'m>(in_progress_read: *mut InProgressRead<'m, 'm>, query: *const c_char) -> *mut QueryBuilder<'m> {
+    let in_progress_read = &*in_progress_read;
+    let query = c_char_to_string(query);
+    let query_builder = QueryBuilder::for_read(in_progress_read, query);
+    Box::into_raw(Box::new(query_builder))
+}
+
+/// Begins a write transaction. On success, `ok` is an `InProgress` handle with which to make
+/// several transacts that commit, or roll back, together.
+///
+/// The handle borrows the store mutably: until it is passed to `in_progress_commit`,
+/// `in_progress_rollback`, or `in_progress_destroy`, the store must not be used in any way, not
+/// even to read, and must not be destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn store_begin_transaction<'m>(store: *mut Store) -> *mut ExternResult {
+    let store = &mut*store;
+    let res: Result<InProgress<'m, 'm>, Error> = store.begin_transaction();
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Transacts `transaction` within the open transaction. On success, `ok` holds the `TxReport`;
+/// free it with `tx_report_destroy`. A failed transact leaves earlier ones in place.
+#[no_mangle]
+pub unsafe extern "C" fn in_progress_transact<'m>(in_progress: *mut InProgress<'m, 'm>, transaction: *const c_char) -> *mut ExternResult {
+    let in_progress = &mut*in_progress;
+    let transaction = c_char_to_string(transaction);
+    let res = in_progress.transact(&transaction);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Commits the transaction and frees the handle, which must not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn in_progress_commit<'m>(in_progress: *mut InProgress<'m, 'm>) -> *mut ExternResult {
+    let in_progress = Box::from_raw(in_progress);
+    let res = in_progress.commit();
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Discards the transaction and frees the handle, which must not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn in_progress_rollback<'m>(in_progress: *mut InProgress<'m, 'm>) -> *mut ExternResult {
+    let in_progress = Box::from_raw(in_progress);
+    let res = in_progress.rollback();
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Starts a transaction and returns a builder in `ok` for assembling its datoms, which are all
+/// transacted together by `entity_builder_commit`. The store is borrowed exactly as for
+/// `store_begin_transaction` until the builder is committed or passed to `entity_builder_destroy`,
+/// which rolls it back.
+#[no_mangle]
+pub unsafe extern "C" fn store_entity_builder<'m>(store: *mut Store) -> *mut ExternResult {
+    let store = &mut*store;
+    let res: Result<InProgressBuilder<'m, 'm>, Error> = store.begin_transaction().map(|in_progress| in_progress.builder());
+    Box::into_raw(Box::new(res.into()))
+}
+
+fn entity_builder_add<'m, V>(builder: &mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: String, value: V) -> *mut ExternResult
+where V: Into<TypedValue> {
+    let kw = try_extern!(kw_from_string(attribute));
+    let res = builder.add_kw(KnownEntid(entid), &kw, value.into());
+    Box::into_raw(Box::new(res.into()))
+}
+
+// The `entity_builder_add_*` functions add `[entid attribute value]` to the builder. Nothing is
+// written until `entity_builder_commit`; an `err` here means the attribute is unknown or expects
+// another type, and leaves the builder usable.
+
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_long<'m>(builder: *mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: *const c_char, value: i64) -> *mut ExternResult {
+    let builder = &mut*builder;
+    entity_builder_add(builder, entid, c_char_to_string(attribute), TypedValue::Long(value))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_ref<'m>(builder: *mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: *const c_char, value: Entid) -> *mut ExternResult {
+    let builder = &mut*builder;
+    entity_builder_add(builder, entid, c_char_to_string(attribute), TypedValue::Ref(value))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_keyword<'m>(builder: *mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let builder = &mut*builder;
+    entity_builder_add(builder, entid, c_char_to_string(attribute), TypedValue::Keyword(try_extern!(kw_from_string(c_char_to_string(value)))))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_boolean<'m>(builder: *mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: *const c_char, value: bool) -> *mut ExternResult {
+    let builder = &mut*builder;
+    entity_builder_add(builder, entid, c_char_to_string(attribute), value)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_double<'m>(builder: *mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: *const c_char, value: f64) -> *mut ExternResult {
+    let builder = &mut*builder;
+    entity_builder_add(builder, entid, c_char_to_string(attribute), value)
+}
+
+/// `value` is in microseconds since the epoch.
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_timestamp<'m>(builder: *mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: *const c_char, value: i64) -> *mut ExternResult {
+    let builder = &mut*builder;
+    entity_builder_add(builder, entid, c_char_to_string(attribute), TypedValue::instant(value))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_string<'m>(builder: *mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let builder = &mut*builder;
+    entity_builder_add(builder, entid, c_char_to_string(attribute), c_char_to_string(value))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_uuid<'m>(builder: *mut InProgressBuilder<'m, 'm>, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let builder = &mut*builder;
+    match parse_uuid(value) {
+        Ok(uuid) => entity_builder_add(builder, entid, c_char_to_string(attribute), uuid),
+        Err(e) => Box::into_raw(Box::new(e)),
+    }
+}
+
+/// Adds `[tempid attribute value]`, where `tempid` names a new entity. Every use of the same
+/// `tempid` within the builder refers to the same entity, whose entid can be found in the
+/// committed `TxReport`. `value` is borrowed, not consumed.
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_add_to_new<'m>(builder: *mut InProgressBuilder<'m, 'm>, tempid: *const c_char, attribute: *const c_char, value: *const TypedValue) -> *mut ExternResult {
+    let builder = &mut*builder;
+    let tempid = builder.named_tempid(c_char_to_string(tempid));
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = builder.add_kw(tempid, &kw, (&*value).clone());
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Transacts everything added to the builder and commits, or rolls back if that fails. On
+/// success `ok` holds the `TxReport`; free it with `tx_report_destroy`. The builder is freed
+/// either way and must not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn entity_builder_commit<'m>(builder: *mut InProgressBuilder<'m, 'm>) -> *mut ExternResult {
+    let builder = Box::from_raw(builder);
+    let res = builder.commit();
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Caches the values of `attribute` in memory for lookups from entity to value. Fails if the
+/// attribute isn't in the schema.
+#[no_mangle]
+pub unsafe extern "C" fn store_cache_attribute_forward(store: *mut Store, attribute: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.cache(&kw, CacheDirection::Forward);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Caches the values of `attribute` in memory for lookups from value to entity. Fails if the
+/// attribute isn't in the schema.
+#[no_mangle]
+pub unsafe extern "C" fn store_cache_attribute_reverse(store: *mut Store, attribute: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.cache(&kw, CacheDirection::Reverse);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Caches the values of `attribute` in memory for lookups in both directions. Fails if the
+/// attribute isn't in the schema.
+#[no_mangle]
+pub unsafe extern "C" fn store_cache_attribute_bi(store: *mut Store, attribute: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.cache(&kw, CacheDirection::Both);
+    Box::into_raw(Box::new(res.into()))
+}
+
+// TODO: attach read-only sources, e.g. `store_attach_readonly_source(store, alias, uri)`.
+// SQLite could `ATTACH` a reference database read-only, but nothing above it could use it: the
+// query parser never fills `in_sources`, and the algebrizer panics on any pattern whose source
+// isn't `$`. Named sources need algebrizer and translator support (per-source table aliases and
+// schemas) before they can be exposed here.
+
+/// Runs `query`, which takes no inputs, without a `QueryBuilder`. On success `ok` holds a
+/// `QueryOutput`: check its shape with `query_output_find_spec_kind`, read it with
+/// `query_output_rows`, and free it with `query_output_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn store_q_once(store: *mut Store, query: *const c_char) -> *mut ExternResult {
+    let store = &*store;
+    let query = c_char_to_string(query);
+    let res = store.q_once(&query, None);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Opens a read-only handle with its own database connection. Unlike the store, the reader may
+/// be used from another thread, concurrently with writes; see `StoreReader` for what each query
+/// sees. Returns null for an in-memory store or if the database can't be opened again. Free the
+/// reader with `store_reader_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn store_reader(store: *mut Store) -> *mut StoreReader {
+    let store = &*store;
+    store.reader().map_or(std::ptr::null_mut(), |reader| Box::into_raw(Box::new(reader)))
+}
+
+/// Runs `query` on `reader`. On success `ok` holds a `QueryOutput`; use `query_output_rows` to
+/// read the results and free it with `query_output_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn store_reader_q_once(reader: *mut StoreReader, query: *const c_char) -> *mut ExternResult {
+    let reader = &*reader;
+    let query = c_char_to_string(query);
+    let res = reader.q_once(&query, None);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Prepares `query` to run on its own connection, where it can be cancelled. Call
+/// `query_handle_await` on a background thread to run it, and `query_handle_cancel` from any
+/// other thread to stop it. Returns null for an in-memory store or if the database can't be opened
+/// again. Free the handle with `query_handle_destroy` once `query_handle_await` has returned.
+#[no_mangle]
+pub unsafe extern "C" fn store_query_cancellable(store: *mut Store, query: *const c_char) -> *mut QueryHandle {
+    let store = &*store;
+    let query = c_char_to_string(query);
+    store.q_cancellable(&query, None).map_or(std::ptr::null_mut(), |handle| Box::into_raw(Box::new(handle)))
+}
+
+/// Cancels the query, which makes `query_handle_await` fail, or return at once if it hasn't yet
+/// been called. Safe to call from any thread while the query runs.
+#[no_mangle]
+pub unsafe extern "C" fn query_handle_cancel(handle: *const QueryHandle) {
+    let handle = &*handle;
+    handle.cancel();
+}
+
+/// Runs the query, blocking until it finishes or is cancelled. On success `ok` holds a
+/// `QueryOutput`; use `query_output_rows` to read the results and free it with
+/// `query_output_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn query_handle_await(handle: *const QueryHandle) -> *mut ExternResult {
+    let handle = &*handle;
+    let res = handle.run();
+    Box::into_raw(Box::new(res.into()))
+}
+
+// TODO: `store_query_as_of` and `store_query_since`. Mentat has no timepoint support to build
+// on: the algebrizer only ever reads the current `datoms`, and history lives in `transactions`,
+// which no query source exposes. Historical and incremental views need a `FindQuery` source
+// that resolves against `transactions` up to, or after, a given tx first.
+
+#[no_mangle]
+pub unsafe extern "C" fn store_query<'a>(store: *mut Store, query: *const c_char) -> *mut QueryBuilder<'a> {
+    let query = c_char_to_string(query);
+    let store = &mut*store;
+    let query_builder = QueryBuilder::new(store, query);
+    Box::into_raw(Box::new(query_builder))
+}
+
+/// Binds `var` to `value` in `bindable`, a `QueryBuilder` or `CachedQuery`.
+unsafe fn bind_value<B, T>(bindable: *mut B, var: *const c_char, value: T) where B: BindInputs, T: Into<TypedValue> {
+    let var = c_char_to_string(var);
+    let bindable = &mut*bindable;
+    bindable.bind_value(&var, value);
+}
+
+/// Binds `var` to the keyword `value` in `bindable`, or says why `value` isn't one.
+unsafe fn bind_kw<B>(bindable: *mut B, var: *const c_char, value: *const c_char) -> *mut ExternResult where B: BindInputs {
+    let kw = try_extern!(kw_from_string(c_char_to_string(value)));
+    bind_value(bindable, var, TypedValue::Keyword(kw));
+    Box::into_raw(Box::new(ExternResult::ok(())))
+}
+
+/// Binds `var` to the UUID written in `value` in `bindable`, or says why `value` isn't one.
+unsafe fn bind_uuid<B>(bindable: *mut B, var: *const c_char, value: *const c_char) -> *mut ExternResult where B: BindInputs {
+    let res = match parse_uuid(value) {
+        Ok(value) => {
+            bind_value(bindable, var, value);
+            ExternResult::ok(())
+        },
+        Err(e) => e,
+    };
+    Box::into_raw(Box::new(res))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_int(query_builder: *mut QueryBuilder, var: *const c_char, value: c_int) {
+    bind_value(query_builder, var, value as i32);
+}
+
+/// Binds `var` to the `len` bytes of UTF-8 text at `value`, which may contain NULs.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_string_bytes(query_builder: *mut QueryBuilder, var: *const c_char, value: *const u8, len: usize) {
+    bind_value(query_builder, var, string_from_bytes(value, len));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_long(query_builder: *mut QueryBuilder, var: *const c_char, value: i64) {
+    bind_value(query_builder, var, TypedValue::Long(value));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_ref(query_builder: *mut QueryBuilder, var: *const c_char, value: i64) {
+    bind_value(query_builder, var, TypedValue::Ref(value));
+}
+
+/// Binds `var` to the entid of the ident `value`. `err` is set if `value` isn't in the schema.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_ref_kw(query_builder: *mut QueryBuilder, var: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let var = c_char_to_string(var);
+    let kw = try_extern!(kw_from_string(c_char_to_string(value)));
+    let query_builder = &mut*query_builder;
+    let res = query_builder.bind_ref_from_kw(&var, (*kw).clone()).map(|_| ());
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Binds `var` to the keyword `value`, written as `:namespace/name`. If `value` isn't a keyword,
+/// `err` says so and nothing is bound.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_kw(query_builder: *mut QueryBuilder, var: *const c_char, value: *const c_char) -> *mut ExternResult {
+    bind_kw(query_builder, var, value)
+}
+
+// boolean
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_boolean(query_builder: *mut QueryBuilder, var: *const c_char, value: bool) {
+    bind_value(query_builder, var, value);
+}
+
+// double
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_double(query_builder: *mut QueryBuilder, var: *const c_char, value: f64) {
+    bind_value(query_builder, var, value);
+}
+
+// instant
+/// Kept for compatibility; prefer `query_builder_bind_timestamp_micros`. `value` is taken as
+/// microseconds since the epoch, whatever its `time_t` type suggests, and where `time_t` is 32
+/// bits it can't hold present-day instants at that precision, so values are truncated.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_timestamp(query_builder: *mut QueryBuilder, var: *const c_char, value: time_t) {
+    bind_value(query_builder, var, TypedValue::instant(value as i64));
+}
+
+/// Binds `var` to the instant `micros` microseconds after the epoch, Mentat's own precision.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_timestamp_micros(query_builder: *mut QueryBuilder, var: *const c_char, micros: i64) {
+    bind_value(query_builder, var, TypedValue::instant(micros));
+}
+
+// string
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_string(query_builder: *mut QueryBuilder, var: *const c_char, value: *const c_char) {
+    bind_value(query_builder, var, c_char_to_string(value));
+}
+
+// any type
+/// Binds `var` to `value`, whatever its type. This takes ownership of `value`: don't use or free
+/// it afterwards. Use `typed_value_clone` first to bind a value that belongs to something else.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_typed_value(query_builder: *mut QueryBuilder, var: *const c_char, value: *mut TypedValue) {
+    bind_value(query_builder, var, *Box::from_raw(value));
+}
+
+// uuid
+/// Binds `var` to the UUID written in `value`. If `value` isn't a valid UUID, `err` says so,
+/// quoting it, and nothing is bound.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_uuid(query_builder: *mut QueryBuilder, var: *const c_char, value: *const c_char) -> *mut ExternResult {
+    bind_uuid(query_builder, var, value)
+}
+
+// collection
+/// Binds `var` to each of `values` as a collection input. Takes ownership of `values`.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_coll(query_builder: *mut QueryBuilder, var: *const c_char, values: *mut Vec<TypedValue>) {
+    let var = c_char_to_string(var);
+    let values = Box::from_raw(values);
+    let query_builder = &mut*query_builder;
+    query_builder.bind_coll(&var, *values);
+}
+
+/// Binds `var` to each of the `len` longs in `values`, which is copied. An empty list binds an
+/// empty collection, so the query matches nothing; `values` may then be null.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_long_coll(query_builder: *mut QueryBuilder, var: *const c_char, values: *const i64, len: usize) {
+    let var = c_char_to_string(var);
+    let values: Vec<TypedValue> = if len == 0 { vec![] } else { slice::from_raw_parts(values, len).iter().map(|v| TypedValue::Long(*v)).collect() };
+    let query_builder = &mut*query_builder;
+    query_builder.bind_coll(&var, values);
+}
+
+/// Like `query_builder_bind_long_coll`, but binds refs.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_ref_coll(query_builder: *mut QueryBuilder, var: *const c_char, values: *const Entid, len: usize) {
+    let var = c_char_to_string(var);
+    let values: Vec<TypedValue> = if len == 0 { vec![] } else { slice::from_raw_parts(values, len).iter().map(|v| TypedValue::Ref(*v)).collect() };
+    let query_builder = &mut*query_builder;
+    query_builder.bind_coll(&var, values);
+}
+
+/// Like `query_builder_bind_long_coll`, but binds strings from `len` C strings.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_string_coll(query_builder: *mut QueryBuilder, var: *const c_char, values: *const *const c_char, len: usize) {
+    let var = c_char_to_string(var);
+    let values = c_char_array_to_strings(values, len);
+    let query_builder = &mut*query_builder;
+    query_builder.bind_coll(&var, values);
+}
+
+// collection from a prior result set
+/// Binds `var` to the values in `column` of each row of `results`, as a collection input.
+/// `results` is not consumed. If `column` is negative or some row has no such column, the error
+/// code is `InvalidArgument` and nothing is bound.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_bind_from_result(query_builder: *mut QueryBuilder, var: *const c_char, results: *mut Vec<Vec<TypedValue>>, column: c_int) -> *mut ExternResult {
+    let var = c_char_to_string(var);
+    let results = &*results;
+    let values: Option<Vec<TypedValue>> = if column < 0 {
+        None
+    } else {
+        results.iter().map(|row| row.get(column as usize).cloned()).collect()
+    };
+    let res = match values {
+        Some(values) => {
+            let query_builder = &mut*query_builder;
+            query_builder.bind_coll(&var, values);
+            ExternResult::ok(())
+        },
+        None => ExternResult::err(&ErrorKind::NoSuchColumn(column as i64).into()),
+    };
+    Box::into_raw(Box::new(res))
+}
+
+/// Orders results by `var`, after any ordering given in the query or by earlier calls. `var`
+/// must be a variable in `:find`; if it isn't, or the query doesn't parse, the error code is
+/// `Query` and the ordering is unchanged.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_order_by(query_builder: *mut QueryBuilder, var: *const c_char, ascending: bool) -> *mut ExternResult {
+    let var = c_char_to_string(var);
+    let query_builder = &mut*query_builder;
+    let res = match query_builder.order_by(&var, ascending) {
+        Ok(_) => ExternResult::ok(()),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(res))
+}
+
+/// Returns at most `limit` rows, replacing any `:limit` in the query; the last call wins. A
+/// negative `limit` is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_set_limit(query_builder: *mut QueryBuilder, limit: i64) {
+    let query_builder = &mut*query_builder;
+    if limit >= 0 {
+        query_builder.limit(limit as u64);
+    }
+}
+
+/// Skips the first `offset` rows. Pair it with `query_builder_order_by`, or pages may overlap
+/// as the store changes. A negative `offset` is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_set_offset(query_builder: *mut QueryBuilder, offset: i64) {
+    let query_builder = &mut*query_builder;
+    if offset >= 0 {
+        query_builder.offset(offset as u64);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_scalar(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let results = query_builder.execute_scalar();
+    Box::into_raw(Box::new(results.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_coll(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let results = query_builder.execute_coll();
+    Box::into_raw(Box::new(results.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_tuple(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let results = query_builder.execute_tuple();
+    Box::into_raw(Box::new(results.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let results = query_builder.execute_rel();
+    Box::into_raw(Box::new(results.into()))
+}
+
+/// Counts the rows the query would return, without fetching them. On success `ok` points to the
+/// count, an `i64`. Queries with aggregates in `:find` are an error.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_count(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let result = match query_builder.execute_count() {
+        Ok(count) => ExternResult::ok(count),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(result))
+}
+
+/// Like `query_builder_execute`, but reserves room for `expected_rows` rows before collecting
+/// the results. Use this when a large relation is expected and its size can be estimated.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_hinted(query_builder: *mut QueryBuilder, expected_rows: usize) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let results: Result<Vec<Vec<TypedValue>>, Error> = query_builder.execute_hinted(expected_rows)
+                                                                    .and_then(|output| output.into_rel().map_err(|e| e.into()));
+    Box::into_raw(Box::new(results.into()))
+}
+
+/// Runs the query and returns its results as CSV with a header row. On success `ok` is the CSV
+/// as an `ExternString`, which keeps any NULs in the values; free it with
+/// `extern_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_to_csv(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let result = match query_builder.execute_to_csv() {
+        Ok(csv) => ExternResult::ok_ptr(string_to_bytes(csv) as *const c_void),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(result))
+}
+
+/// Runs the query and returns its results as a JSON array of objects, one per row, keyed by the
+/// `:find` variables. On success `ok` is the JSON as a C string owned by the caller.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_to_json(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let result = match query_builder.execute_to_json() {
+        Ok(json) => ExternResult::ok_ptr(string_to_c_char(json) as *const c_void),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(result))
+}
+
+/// Like `query_builder_execute`, but `ok` holds the whole `QueryOutput`, which knows its find
+/// spec. Use `query_output_rows` to read the results.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_output(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let results = query_builder.execute();
+    Box::into_raw(Box::new(results.into()))
+}
+
+/// Like `query_builder_execute_output`, but also measures the query. On success `ok` holds a
+/// `TimedQueryOutput`: read its timings with `query_timing_prepare_micros`,
+/// `query_timing_execute_micros` and `query_timing_row_count`, borrow its results with
+/// `timed_query_output_output`, and free it with `timed_query_output_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_with_timing(query_builder: *mut QueryBuilder) -> *mut ExternResult {
+    let query_builder = &mut*query_builder;
+    let results: Result<TimedQueryOutput, Error> = query_builder.execute_with_timing();
+    Box::into_raw(Box::new(results.into()))
+}
+
+fn duration_micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + (duration.subsec_nanos() / 1_000) as u64
+}
+
+/// Microseconds spent parsing, algebrizing, translating and preparing the query.
+#[no_mangle]
+pub unsafe extern "C" fn query_timing_prepare_micros(timed: *const TimedQueryOutput) -> u64 {
+    let &(_, ref timing) = &*timed;
+    duration_micros(timing.prepare)
+}
+
+/// Microseconds spent running the query and materializing its results.
+#[no_mangle]
+pub unsafe extern "C" fn query_timing_execute_micros(timed: *const TimedQueryOutput) -> u64 {
+    let &(_, ref timing) = &*timed;
+    duration_micros(timing.execute)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_timing_row_count(timed: *const TimedQueryOutput) -> usize {
+    let &(_, ref timing) = &*timed;
+    timing.rows
+}
+
+/// The query's results, borrowed from `timed`.
+#[no_mangle]
+pub unsafe extern "C" fn timed_query_output_output(timed: *const TimedQueryOutput) -> *const QueryOutput {
+    let &(ref output, _) = &*timed;
+    output as *const QueryOutput
+}
+
+/// The shape of a query's results, from its find spec.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FindSpecKind {
+    Rel = 0,
+    Coll = 1,
+    Tuple = 2,
+    Scalar = 3,
+}
+
+impl<'a> From<&'a FindSpec> for FindSpecKind {
+    fn from(spec: &'a FindSpec) -> FindSpecKind {
+        match spec {
+            &FindSpec::FindRel(_) => FindSpecKind::Rel,
+            &FindSpec::FindColl(_) => FindSpecKind::Coll,
+            &FindSpec::FindTuple(_) => FindSpecKind::Tuple,
+            &FindSpec::FindScalar(_) => FindSpecKind::Scalar,
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_output_find_spec_kind(output: *const QueryOutput) -> FindSpecKind {
+    let output = &*output;
+    FindSpecKind::from(&*output.spec)
+}
+
+/// The shape the query will yield, so callers can choose which `query_builder_execute_*` to
+/// call. This parses the query but doesn't run it. On success `ok` points to the
+/// `FindSpecKind`; free it with `find_spec_kind_destroy`. A query that doesn't parse is an error.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_find_spec_kind(query_builder: *const QueryBuilder) -> *mut ExternResult {
+    let query_builder = &*query_builder;
+    let spec = try_extern!(query_builder.find_spec());
+    Box::into_raw(Box::new(ExternResult::ok(FindSpecKind::from(&spec))))
+}
+
+/// The names of the query's columns, in the order its results hold them: `?x` for a variable,
+/// `(max ?x)` for an aggregate. This parses the query but doesn't run it. On success `ok` points
+/// to a `StringIterator`; step through it with `string_iter_next` and free it with
+/// `string_iter_destroy`. A query that doesn't parse is an error.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_column_names(query_builder: *const QueryBuilder) -> *mut ExternResult {
+    let query_builder = &*query_builder;
+    let names: StringIterator = try_extern!(query_builder.column_names()).into_iter();
+    Box::into_raw(Box::new(ExternResult::ok(names)))
+}
+
+/// Returns a copy of the results in `output` as rows, whatever the kind of find spec.
+#[no_mangle]
+pub unsafe extern "C" fn query_output_rows(output: *const QueryOutput) -> *mut Vec<Vec<TypedValue>> {
+    let output = &*output;
+    let rows = match output.results {
+        QueryResults::Scalar(ref v) => v.iter().map(|v| vec![v.clone()]).collect(),
+        QueryResults::Tuple(ref t) => t.iter().cloned().collect(),
+        QueryResults::Coll(ref vs) => vs.iter().map(|v| vec![v.clone()]).collect(),
+        QueryResults::Rel(ref rows) => rows.clone(),
+    };
+    Box::into_raw(Box::new(rows))
+}
+
+/// Returns the value in `values`, a row of `output`, in the column for `var_name`, or null if the
+/// query doesn't project that variable.
+#[no_mangle]
+pub unsafe extern "C" fn row_value_for_variable(values: *mut Vec<TypedValue>, output: *mut QueryOutput, var_name: *const c_char) -> *const TypedValue {
+    let values = &*values;
+    let output = &*output;
+    let var = Variable::from_valid_name(&c_char_to_string(var_name));
+    output.spec.column_index(&var)
+          .and_then(|index| values.get(index))
+          .map_or(std::ptr::null(), |v| v as *const TypedValue)
+}
+
+/// Builds a list of long `TypedValue`s from `len` values in one call, e.g. for
+/// `query_builder_bind_coll`. `values` is copied, not consumed, and may be null if `len` is zero.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_long_vec(values: *const i64, len: usize) -> *mut Vec<TypedValue> {
+    let values: Vec<TypedValue> = if len == 0 { vec![] } else { slice::from_raw_parts(values, len).iter().map(|v| TypedValue::Long(*v)).collect() };
+    Box::into_raw(Box::new(values))
+}
+
+/// Like `typed_value_long_vec`, but builds refs.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_ref_vec(values: *const Entid, len: usize) -> *mut Vec<TypedValue> {
+    let values: Vec<TypedValue> = if len == 0 { vec![] } else { slice::from_raw_parts(values, len).iter().map(|v| TypedValue::Ref(*v)).collect() };
+    Box::into_raw(Box::new(values))
+}
+
+/// Like `typed_value_long_vec`, but builds strings from `len` C strings.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_string_vec(values: *const *const c_char, len: usize) -> *mut Vec<TypedValue> {
+    let values = c_char_array_to_strings(values, len);
+    Box::into_raw(Box::new(values.into_iter().map(|v| TypedValue::String(Rc::new(v))).collect()))
+}
+
+// Ownership of `TypedValue`s across this boundary:
+//
+// - Consumed: `typed_value_as_*` (on success) and `typed_value_coerce` take the box and free it.
+//   Don't touch the pointer afterwards.
+// - Borrowed: `value_at_index` and `typed_value_clone` read through the pointer. The value still
+//   belongs to whoever owned it before, and a pointer from `value_at_index` must not be freed; it
+//   dies with its list.
+// - Owned: every other function returning `*mut TypedValue` hands the caller a value that must be
+//   released with `typed_value_destroy` or consumed by one of the functions above.
+//
+// To keep a borrowed value beyond the life of its owner, or to pass it to a consuming function,
+// take an owned copy with `typed_value_clone` first.
+
+/// Returns an independently-owned copy of `typed_value`, which is only borrowed.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_clone(typed_value: *const TypedValue) -> *mut TypedValue {
+    let typed_value = &*typed_value;
+    Box::into_raw(Box::new(typed_value.clone()))
+}
+
+// Constructors. Each returns an owned value, to be freed with `typed_value_destroy` or handed to
+// a function that consumes it.
+
+#[no_mangle]
+pub extern "C" fn typed_value_from_long(value: i64) -> *mut TypedValue {
+    Box::into_raw(Box::new(TypedValue::Long(value)))
+}
+
+#[no_mangle]
+pub extern "C" fn typed_value_from_ref(value: Entid) -> *mut TypedValue {
+    Box::into_raw(Box::new(TypedValue::Ref(value)))
+}
+
+#[no_mangle]
+pub extern "C" fn typed_value_from_boolean(value: bool) -> *mut TypedValue {
+    Box::into_raw(Box::new(TypedValue::Boolean(value)))
+}
+
+#[no_mangle]
+pub extern "C" fn typed_value_from_double(value: f64) -> *mut TypedValue {
+    Box::into_raw(Box::new(value.into()))
+}
+
+/// An instant `micros` microseconds after the epoch.
+#[no_mangle]
+pub extern "C" fn typed_value_from_instant_micros(micros: i64) -> *mut TypedValue {
+    Box::into_raw(Box::new(TypedValue::instant(micros)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_from_string(value: *const c_char) -> *mut TypedValue {
+    Box::into_raw(Box::new(TypedValue::typed_string(&c_char_to_string(value))))
+}
+
+/// A string of the `len` bytes of UTF-8 text at `value`, which may contain NULs.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_from_string_bytes(value: *const u8, len: usize) -> *mut TypedValue {
+    Box::into_raw(Box::new(string_from_bytes(value, len).into()))
+}
+
+/// A keyword written as `:namespace/name`. On success `ok` is the new value; if `value` isn't
+/// written that way, `err` says so, quoting it.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_from_keyword(value: *const c_char) -> *mut ExternResult {
+    let kw = try_extern!(kw_from_string(c_char_to_string(value)));
+    Box::into_raw(Box::new(ExternResult::ok(TypedValue::Keyword(kw))))
+}
+
+/// The UUID written in `value`. On success `ok` is the new value; if `value` isn't a valid UUID,
+/// `err` says so, quoting it.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_from_uuid(value: *const c_char) -> *mut ExternResult {
+    let res = match parse_uuid(value) {
+        Ok(uuid) => ExternResult::ok::<TypedValue>(uuid.into()),
+        Err(e) => e,
+    };
+    Box::into_raw(Box::new(res))
+}
+
+/// A fresh random (version 4) UUID as a hyphenated string owned by the caller, formatted as
+/// Mentat formats and parses UUIDs.
+#[no_mangle]
+pub unsafe extern "C" fn uuid_generate_v4() -> *const c_char {
+    string_to_c_char(Uuid::new_v4().hyphenated().to_string())
+}
+
+/// Whether `a` and `b` hold the same value of the same type. Both are only borrowed. Values of
+/// different types are never equal: `Ref(5)` is not `Long(5)`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_eq(a: *const TypedValue, b: *const TypedValue) -> bool {
+    *a == *b
+}
+
+/// Orders `a` relative to `b`: -1 if it sorts first, 0 if they are equal, 1 if it sorts after.
+/// Both are only borrowed. Values sort first by type and then by value, which gives a consistent
+/// order for sorting and deduplicating mixed results.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_cmp(a: *const TypedValue, b: *const TypedValue) -> c_int {
+    match (*a).cmp(&*b) {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    }
+}
+
+/// Returns a readable rendering of `typed_value`, whatever its type, for logging: `42`, `true`,
+/// `1.5`, `"text"`, `:some/keyword`, `#inst "2018-01-01T00:00:00.000000Z"`, or
+/// `#uuid "..."`. Refs print as bare numbers, like longs. The value is only borrowed; the string
+/// is owned by the caller.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_debug_string(typed_value: *const TypedValue) -> *const c_char {
+    let typed_value = &*typed_value;
+    let rendered = match typed_value {
+        &TypedValue::Ref(x) => x.to_string(),
+        &TypedValue::Long(x) => x.to_string(),
+        &TypedValue::Boolean(x) => x.to_string(),
+        &TypedValue::Double(x) => format!("{:?}", x.0),
+        &TypedValue::Instant(ref x) => format!("#inst \"{}\"", x.format("%Y-%m-%dT%H:%M:%S%.6fZ")),
+        &TypedValue::String(ref x) => format!("{:?}", x),
+        &TypedValue::Keyword(ref x) => x.to_string(),
+        &TypedValue::Uuid(ref x) => format!("#uuid \"{}\"", x.hyphenated()),
+    };
+    string_to_c_char(rendered)
+}
+
+/// Consumes `typed_value`, returning it converted to `target` in the `ok` slot, or an error
+/// naming both types if the conversion can't be made without loss.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_coerce(typed_value: *mut TypedValue, target: ValueType) -> *mut ExternResult {
+    let typed_value = Box::from_raw(typed_value);
+    let provided = typed_value.value_type();
+    let res: Result<TypedValue, Error> = typed_value.coerce_to(target)
+                                                    .ok_or_else(|| ErrorKind::CannotCoerce(provided, target).into());
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Returns the stable name of `value_type`, such as "long" or "ref", as a string owned by the
+/// caller. Prefer this to the enum's numeric value when logging or persisting a type.
+#[no_mangle]
+pub extern "C" fn value_type_name(value_type: ValueType) -> *const c_char {
+    string_to_c_char(value_type.name())
+}
+
+/// The inverse of `value_type_name`: `value` points to the `ValueType` named by `name`, or is null
+/// if there's no such type.
+#[no_mangle]
+pub unsafe extern "C" fn value_type_from_name(name: *const c_char) -> *mut ExternOption {
+    let name = c_char_to_string(name);
+    Box::into_raw(Box::new(ValueType::from_name(&name).into()))
+}
+
+/// Checks that `typed_value` holds a value of type `expected`. If it does, the value is consumed
+/// and `extract` produces the `ok` pointer. If it doesn't, `typed_value` is left untouched, so the
+/// caller can try another accessor or destroy it, and `err` names both types.
+unsafe fn typed_value_as<F>(typed_value: *mut TypedValue, expected: ValueType, extract: F) -> *mut ExternResult
+where F: FnOnce(TypedValue) -> *const c_void {
+    let provided = (&*typed_value).value_type();
+    let result = if provided == expected {
+        ExternResult::ok_ptr(extract(*Box::from_raw(typed_value)))
+    } else {
+        ExternResult::err(&ErrorKind::UnexpectedValueType(expected, provided).into())
+    };
+    Box::into_raw(Box::new(result))
+}
+
+/// On success `ok` points to an `i64`, to be freed with `entid_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_long(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::Long, |v| {
+        Box::into_raw(Box::new(v.into_long().unwrap())) as *const c_void
+    })
+}
+
+/// On success `ok` points to an `Entid`, to be freed with `entid_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_entid(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::Ref, |v| {
+        Box::into_raw(Box::new(v.into_entid().unwrap())) as *const c_void
+    })
+}
+
+/// On success `ok` is the keyword as a C string, to be freed with `rust_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_kw(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::Keyword, |v| {
+        string_to_c_char(v.into_kw().unwrap().to_string()) as *const c_void
+    })
+}
+
+/// On success `ok` points to a `bool`, to be freed with `bool_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_boolean(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::Boolean, |v| {
+        Box::into_raw(Box::new(v.into_boolean().unwrap())) as *const c_void
+    })
+}
+
+/// On success `ok` points to an `f64`, to be freed with `double_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_double(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::Double, |v| {
+        Box::into_raw(Box::new(v.into_double().unwrap())) as *const c_void
+    })
+}
+
+/// On success `ok` points to an `i64` holding seconds since the epoch, to be freed with
+/// `entid_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_timestamp(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::Instant, |v| {
+        Box::into_raw(Box::new(v.into_timestamp().unwrap())) as *const c_void
+    })
+}
+
+/// On success `ok` is the string as a C string, to be freed with `rust_string_destroy`. A string
+/// holding a NUL is cut off there; use `typed_value_as_string_bytes` to get all of it.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_string(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::String, |v| {
+        c_char_from_rc(v.into_string().unwrap()) as *const c_void
+    })
+}
+
+/// On success `ok` is the string as an `ExternString`, which keeps any NULs that
+/// `typed_value_as_string` would cut the string off at. Free it with `extern_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_string_bytes(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::String, |v| {
+        string_to_bytes(v.into_string().unwrap().as_str()) as *const c_void
+    })
+}
+
+/// On success `ok` is the hyphenated UUID as a C string, to be freed with `rust_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_as_uuid(typed_value: *mut TypedValue) -> *mut ExternResult {
+    typed_value_as(typed_value, ValueType::Uuid, |v| {
+        string_to_c_char(v.into_uuid_string().unwrap()) as *const c_void
+    })
+}
+
+// TODO: `typed_value_as_bytes` and `query_builder_bind_bytes`. Mentat has no bytes value type yet:
+// `ValueType` and `TypedValue` would need a `Bytes` variant, with a SQL tag and blob storage in
+// `datoms`, before binary values could cross this boundary.
+
+/// The number of rows in `rows`, which is only borrowed.
+#[no_mangle]
+pub unsafe extern "C" fn rows_count(rows: *mut Vec<Vec<TypedValue>>) -> usize {
+    let rows = &*rows;
+    rows.len()
+}
+
+/// The number of values in `row`, which is only borrowed.
+#[no_mangle]
+pub unsafe extern "C" fn row_column_count(row: *mut Vec<TypedValue>) -> usize {
+    let row = &*row;
+    row.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn row_at_index(rows: *mut Vec<Vec<TypedValue>>, index: c_int) ->  *mut Vec<TypedValue> {
+    let result = &*rows;
+    result.get(index as usize).map_or(std::ptr::null_mut(), |v| Box::into_raw(Box::new(v.clone())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rows_iter(rows: *mut Vec<Vec<TypedValue>>) ->  *mut TypedValueListIterator {
+    let result = Box::from_raw(rows);
+    Box::into_raw(Box::new(result.into_iter()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rows_iter_next(iter: *mut TypedValueListIterator) ->  *mut Vec<TypedValue> {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| Box::into_raw(Box::new(v)))
+}
+
+/// The number of rows not yet returned by `iter`. Destroying `iter` with
+/// `typed_value_result_set_iter_destroy` frees these along with the backing buffer.
+#[no_mangle]
+pub unsafe extern "C" fn rows_iter_remaining(iter: *const TypedValueListIterator) -> usize {
+    let iter = &*iter;
+    iter.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn values_iter(values: *mut Vec<TypedValue>) ->  *mut TypedValueIterator {
+    let result = Box::from_raw(values);
+    Box::into_raw(Box::new(result.into_iter()))
+}
+
+/// The number of values not yet returned by `iter`. Destroying `iter` with
+/// `typed_value_list_iter_destroy` frees these along with the backing buffer.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_list_iter_remaining(iter: *const TypedValueIterator) -> usize {
+    let iter = &*iter;
+    iter.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next(iter: *mut TypedValueIterator) ->  *const TypedValue {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| &v as *const TypedValue)
+}
+
+//as_long
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next_as_long(iter: *mut TypedValueIterator) ->  *const i64 {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| &v.into_long().expect("Typed value cannot be coerced into a Long") as *const i64)
+}
+// as ref
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next_as_entid(iter: *mut TypedValueIterator) ->  *const Entid {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| &v.into_entid().expect("Typed value cannot be coerced into am Entid") as *const Entid)
+}
+
+// as kw
+/// The keyword is owned by the caller; free it with `rust_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next_as_kw(iter: *mut TypedValueIterator) ->  *const c_char {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| string_to_c_char(v.into_kw().expect("Typed value cannot be coerced into a Namespaced Keyword").to_string()))
+}
+
+//as_boolean
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next_as_boolean(iter: *mut TypedValueIterator) ->  *const bool {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| &v.into_boolean().expect("Typed value cannot be coerced into a Boolean") as *const bool)
+}
+
+//as_double
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next_as_double(iter: *mut TypedValueIterator) ->  *const f64 {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| &v.into_double().expect("Typed value cannot be coerced into a Double") as *const f64)
+}
+
+//as_timestamp
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next_as_timestamp(iter: *mut TypedValueIterator) ->  *const i64 {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| v.into_timestamp().expect("Typed value cannot be coerced into a Timestamp") as *const i64)
+}
+
+//as_string
+/// The string is owned by the caller; free it with `rust_string_destroy`. It ends at the first
+/// NUL in the value, if any.
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next_as_string(iter: *mut TypedValueIterator) ->  *const c_char {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| c_char_from_rc(v.into_string().expect("Typed value cannot be coerced into a String")))
+}
+
+//as_uuid
+/// The string is owned by the caller; free it with `rust_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn values_iter_next_as_uuid(iter: *mut TypedValueIterator) ->  *const c_char {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |v| string_to_c_char(v.into_uuid_string().expect("Typed value cannot be coerced into a Uuid")))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index(values: *mut Vec<TypedValue>, index: c_int) ->  *const TypedValue {
+    let result = &*values;
+    result.get(index as usize).expect("No value at index") as *const TypedValue
+}
+
+//as_long
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_long(values: *mut Vec<TypedValue>, index: c_int) ->  i64 {
     let result = &*values;
     let value = result.get(index as usize).expect("No value at index");
     value.clone().into_long().expect("Typed value cannot be coerced into a Long")
 }
-// as ref
+// as ref
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_entid(values: *mut Vec<TypedValue>, index: c_int) ->  Entid {
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    value.clone().into_entid().expect("Typed value cannot be coerced into an Entid")
+}
+
+// as kw
+/// The keyword is owned by the caller; free it with `rust_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_kw(values: *mut Vec<TypedValue>, index: c_int) ->  *const c_char {
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    string_to_c_char(value.clone().into_kw().expect("Typed value cannot be coerced into a Namespaced Keyword").to_string())
+}
+
+//as_boolean
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_boolean(values: *mut Vec<TypedValue>, index: c_int) ->  bool {
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    value.clone().into_boolean().expect("Typed value cannot be coerced into a Boolean")
+}
+
+//as_double
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_double(values: *mut Vec<TypedValue>, index: c_int) ->  f64 {
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    value.clone().into_double().expect("Typed value cannot be coerced into a Double")
+}
+
+//as_timestamp
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_timestamp(values: *mut Vec<TypedValue>, index: c_int) ->  i64 {
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    value.clone().into_timestamp().expect("Typed value cannot be coerced into a timestamp")
+}
+
+//as_string
+/// The string is owned by the caller; free it with `rust_string_destroy`. It ends at the first
+/// NUL in the value, if any.
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_string(values: *mut Vec<TypedValue>, index: c_int) ->  *mut c_char {
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    c_char_from_rc(value.clone().into_string().expect("Typed value cannot be coerced into a String"))
+}
+
+/// As `value_at_index_as_string`, but as an `ExternString`, which keeps any NULs in the value.
+/// Free it with `extern_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_string_bytes(values: *mut Vec<TypedValue>, index: c_int) -> *mut ExternString {
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    string_to_bytes(value.clone().into_string().expect("Typed value cannot be coerced into a String").as_str())
+}
+
+//as_uuid
+/// The string is owned by the caller; free it with `rust_string_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_as_uuid(values: *mut Vec<TypedValue>, index: c_int) ->  *mut c_char {
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    string_to_c_char(value.clone().into_uuid_string().expect("Typed value cannot be coerced into a Uuid"))
+}
+
+/// Parses `query` once and returns a handle that can be bound and run repeatedly with the
+/// `prepared_bind_*` and `prepared_execute*` functions. Bindings persist between executions until
+/// replaced, and the query is only translated to SQL again when they or the schema change. On success `ok` holds the handle, which must be freed with `prepared_destroy`; a
+/// query that can't be parsed is reported in `err`.
+#[no_mangle]
+pub unsafe extern "C" fn store_prepare<'a>(store: *mut Store, query: *const c_char) -> *mut ExternResult {
+    let query = c_char_to_string(query);
+    let store = &mut*store;
+    let result: Result<CachedQuery<'a>, Error> = CachedQuery::new(store, query);
+    let result = match result {
+        Ok(prepared) => ExternResult::ok(prepared),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(result))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_bind_long(prepared: *mut CachedQuery, var: *const c_char, value: i64) {
+    bind_value(prepared, var, TypedValue::Long(value));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_bind_ref(prepared: *mut CachedQuery, var: *const c_char, value: i64) {
+    bind_value(prepared, var, TypedValue::Ref(value));
+}
+
+/// Like `query_builder_bind_kw`.
+#[no_mangle]
+pub unsafe extern "C" fn prepared_bind_kw(prepared: *mut CachedQuery, var: *const c_char, value: *const c_char) -> *mut ExternResult {
+    bind_kw(prepared, var, value)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_bind_boolean(prepared: *mut CachedQuery, var: *const c_char, value: bool) {
+    bind_value(prepared, var, value);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_bind_double(prepared: *mut CachedQuery, var: *const c_char, value: f64) {
+    bind_value(prepared, var, value);
+}
+
+/// Binds `var` to the instant `micros` microseconds after the epoch.
+#[no_mangle]
+pub unsafe extern "C" fn prepared_bind_timestamp_micros(prepared: *mut CachedQuery, var: *const c_char, micros: i64) {
+    bind_value(prepared, var, TypedValue::instant(micros));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_bind_string(prepared: *mut CachedQuery, var: *const c_char, value: *const c_char) {
+    bind_value(prepared, var, c_char_to_string(value));
+}
+
+/// Binds `var` to the UUID written in `value`. If `value` isn't a valid UUID, `err` says so and
+/// nothing is bound.
+#[no_mangle]
+pub unsafe extern "C" fn prepared_bind_uuid(prepared: *mut CachedQuery, var: *const c_char, value: *const c_char) -> *mut ExternResult {
+    bind_uuid(prepared, var, value)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_execute_scalar(prepared: *mut CachedQuery) -> *mut ExternResult {
+    let prepared = &mut*prepared;
+    let results = prepared.execute_scalar();
+    Box::into_raw(Box::new(results.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_execute_coll(prepared: *mut CachedQuery) -> *mut ExternResult {
+    let prepared = &mut*prepared;
+    let results = prepared.execute_coll();
+    Box::into_raw(Box::new(results.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_execute_tuple(prepared: *mut CachedQuery) -> *mut ExternResult {
+    let prepared = &mut*prepared;
+    let results = prepared.execute_tuple();
+    Box::into_raw(Box::new(results.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn prepared_execute(prepared: *mut CachedQuery) -> *mut ExternResult {
+    let prepared = &mut*prepared;
+    let results = prepared.execute_rel();
+    Box::into_raw(Box::new(results.into()))
+}
+
+/// Like `prepared_execute`, but `ok` holds the whole `QueryOutput`, which knows its find spec.
+#[no_mangle]
+pub unsafe extern "C" fn prepared_execute_output(prepared: *mut CachedQuery) -> *mut ExternResult {
+    let prepared = &mut*prepared;
+    let results = prepared.execute();
+    Box::into_raw(Box::new(results.into()))
+}
+
+/// Explains how `query` would run, without running it. On success `ok` is a C string owned by the
+/// caller holding the translated SQL, its bindings, and SQLite's `EXPLAIN QUERY PLAN` output, or a
+/// note that the query is known in advance to be empty or constant. A query that can't be parsed
+/// or algebrized is reported in `err`.
+#[no_mangle]
+pub unsafe extern "C" fn store_query_explain(store: *mut Store, query: *const c_char) -> *mut ExternResult {
+    let store = &*store;
+    let query = c_char_to_string(query);
+    let result = match store.q_explain(&query, None) {
+        Ok(explanation) => ExternResult::ok_ptr(string_to_c_char(explanation.to_string()) as *const c_void),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(result))
+}
+
+// TODO: lookup_values_for_attribute
+
+#[no_mangle]
+pub unsafe extern "C" fn store_value_for_attribute(store: *mut Store, entid: i64, attribute: *const c_char) ->  *mut ExternResult {
+    let store = &*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let value = match store.lookup_value_for_attribute(entid, &kw) {
+        Ok(Some(v)) => ExternResult::ok(v),
+        Ok(None) => ExternResult::ok_ptr(std::ptr::null()),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(value))
+}
+
+/// Whether `entid` has any value for `attribute`. On success `ok` points to a `bool`; naming an
+/// attribute that isn't in the schema is an error.
+#[no_mangle]
+pub unsafe extern "C" fn store_entid_has_attribute(store: *mut Store, entid: Entid, attribute: *const c_char) -> *mut ExternResult {
+    let store = &*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.entid_has_attribute(entid, &kw);
+    Box::into_raw(Box::new(res.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_transact_and_fetch(store: *mut Store, transaction: *const c_char, entid_tempid: *const c_char, attributes: *const *const c_char, count: usize) -> *mut ExternResult {
+    let store = &mut*store;
+    let transaction = c_char_to_string(transaction);
+    let tempid = c_char_to_string(entid_tempid);
+    let attributes: Vec<NamespacedKeyword> = try_extern!(kws_from_strings(c_char_array_to_strings(attributes, count)));
+    let res = store.transact_and_fetch(&transaction, &tempid, &attributes);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Reads several attributes of `entid` with one query. On success `ok` holds a
+/// `Vec<Option<TypedValue>>` with one entry per attribute, in order, read with
+/// `optional_value_at_index` (null for an absent value) and freed with
+/// `optional_typed_value_list_destroy`. Naming an unknown attribute is an error.
+#[no_mangle]
+pub unsafe extern "C" fn store_entity_attributes(store: *mut Store, entid: Entid, attributes: *const *const c_char, count: usize) -> *mut ExternResult {
+    let store = &*store;
+    let attributes: Vec<NamespacedKeyword> = try_extern!(kws_from_strings(c_char_array_to_strings(attributes, count)));
+    let res = store.entity_attributes(entid, &attributes);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Reads every value `entid` has for any of `attributes`. On success `ok` holds an
+/// `AttributeValues`: (attribute, value) pairs, read with `attribute_values_len`,
+/// `attribute_values_attribute_at` and `attribute_values_value_at`, and freed with
+/// `attribute_values_destroy`. A cardinality-many attribute appears once per value. Naming an
+/// unknown attribute is an error.
+#[no_mangle]
+pub unsafe extern "C" fn store_pull_attributes(store: *mut Store, entid: Entid, attributes: *const *const c_char, count: usize) -> *mut ExternResult {
+    let store = &*store;
+    let attributes: Vec<NamespacedKeyword> = try_extern!(kws_from_strings(c_char_array_to_strings(attributes, count)));
+    let res: Result<AttributeValues, Error> = store.pull_attributes(entid, &attributes);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// As `store_pull_attributes`, for every attribute `entid` has.
+#[no_mangle]
+pub unsafe extern "C" fn store_pull_all_attributes(store: *mut Store, entid: Entid) -> *mut ExternResult {
+    let store = &*store;
+    let res: Result<AttributeValues, Error> = store.pull_all_attributes(entid);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// As `store_pull_attributes`, for each of `entids` in one query. On success `ok` holds an
+/// `AttributeValuesList` with one `AttributeValues` per entid, in order, read with
+/// `attribute_values_list_len` and `attribute_values_list_at`. Free the whole structure with
+/// `attribute_values_list_destroy`. If `entid_count` is zero, `entids` may be null.
+#[no_mangle]
+pub unsafe extern "C" fn store_pull_attributes_many(store: *mut Store, entids: *const Entid, entid_count: usize, attributes: *const *const c_char, attr_count: usize) -> *mut ExternResult {
+    let store = &*store;
+    let entids: &[Entid] = if entid_count == 0 { &[] } else { slice::from_raw_parts(entids, entid_count) };
+    let attributes: Vec<NamespacedKeyword> = try_extern!(kws_from_strings(c_char_array_to_strings(attributes, attr_count)));
+    let res: Result<AttributeValuesList, Error> = store.pull_attributes_many(entids, &attributes);
+    Box::into_raw(Box::new(res.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn attribute_values_list_len(list: *const AttributeValuesList) -> usize {
+    let list = &*list;
+    list.len()
+}
+
+/// Returns the pairs for the entity at `index`, or null if `index` is out of range. They are
+/// borrowed from `list` and must not be freed separately.
+#[no_mangle]
+pub unsafe extern "C" fn attribute_values_list_at(list: *const AttributeValuesList, index: usize) -> *const AttributeValues {
+    let list = &*list;
+    list.get(index).map_or(std::ptr::null(), |values| values as *const AttributeValues)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn attribute_values_len(values: *const AttributeValues) -> usize {
+    let values = &*values;
+    values.len()
+}
+
+/// Returns the attribute of the pair at `index` as a keyword string owned by the caller, or null
+/// if `index` is out of range.
+#[no_mangle]
+pub unsafe extern "C" fn attribute_values_attribute_at(values: *const AttributeValues, index: usize) -> *mut c_char {
+    let values = &*values;
+    values.get(index).map_or(std::ptr::null_mut(), |&(ref attribute, _)| string_to_c_char(attribute.to_string()))
+}
+
+/// Returns the value of the pair at `index`, or null if `index` is out of range. The value is
+/// borrowed from `values` and lives as long as it does.
+#[no_mangle]
+pub unsafe extern "C" fn attribute_values_value_at(values: *const AttributeValues, index: usize) -> *const TypedValue {
+    let values = &*values;
+    values.get(index).map_or(std::ptr::null(), |&(_, ref value)| value as *const TypedValue)
+}
+
+/// If `entid_count` is zero, `entids` may be null.
+#[no_mangle]
+pub unsafe extern "C" fn store_pull(store: *mut Store, entids: *const Entid, entid_count: usize, attributes: *const *const c_char, attr_count: usize) -> *mut ExternResult {
+    let store = &mut*store;
+    let entids: &[Entid] = if entid_count == 0 { &[] } else { slice::from_raw_parts(entids, entid_count) };
+    let attributes: Vec<NamespacedKeyword> = try_extern!(kws_from_strings(c_char_array_to_strings(attributes, attr_count)));
+    let res = store.pull(entids, &attributes);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Returns `entid` and the entities reachable from it through up to `depth` refs as transactable
+/// EDN owned by the caller, or null if the export fails.
+#[no_mangle]
+pub unsafe extern "C" fn store_export_entity(store: *mut Store, entid: Entid, depth: c_int) -> *mut c_char {
+    let store = &*store;
+    store.export_entity(entid, depth.max(0) as usize)
+         .ok()
+         .map_or(std::ptr::null_mut(), string_to_c_char)
+}
+
+/// Returns the entities whose value for `attribute` lies between `low` and `high` as a list of
+/// ref `TypedValue`s. `low` and `high` are not consumed.
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index_as_entid(values: *mut Vec<TypedValue>, index: c_int) ->  Entid {
-    let result = &*values;
-    let value = result.get(index as usize).expect("No value at index");
-    value.clone().into_entid().expect("Typed value cannot be coerced into an Entid")
+pub unsafe extern "C" fn store_entities_in_range(store: *mut Store, attribute: *const c_char, low: *mut TypedValue, high: *mut TypedValue, inclusive: bool) -> *mut ExternResult {
+    let store = &*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let (low, high) = ((&*low).clone(), (&*high).clone());
+    let res = store.entities_in_range(&kw, low, high, inclusive)
+                   .map(|entids| entids.into_iter().map(TypedValue::Ref).collect::<Vec<TypedValue>>());
+    Box::into_raw(Box::new(res.into()))
 }
 
-// as kw
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index_as_kw(values: *mut Vec<TypedValue>, index: c_int) ->  *const c_char {
+pub unsafe extern "C" fn optional_row_at_index(rows: *mut Vec<Vec<Option<TypedValue>>>, index: c_int) -> *mut Vec<Option<TypedValue>> {
+    let result = &*rows;
+    result.get(index as usize).map_or(std::ptr::null_mut(), |v| Box::into_raw(Box::new(v.clone())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn optional_value_at_index(values: *mut Vec<Option<TypedValue>>, index: c_int) -> *const TypedValue {
     let result = &*values;
-    let value = result.get(index as usize).expect("No value at index");
-    string_to_c_char(value.clone().into_kw().expect("Typed value cannot be coerced into a Namespaced Keyword").to_string())
+    result.get(index as usize)
+          .and_then(|v| v.as_ref())
+          .map_or(std::ptr::null(), |v| v as *const TypedValue)
+}
+
+/// Register `old_kw` as a read-time alias for the attribute `new_kw`.
+/// This affects lookups such as `store_value_for_attribute` only; the stored schema is unchanged.
+#[no_mangle]
+pub unsafe extern "C" fn store_alias_attribute(store: *mut Store, old_kw: *const c_char, new_kw: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let old_kw = try_extern!(kw_from_string(c_char_to_string(old_kw)));
+    let new_kw = try_extern!(kw_from_string(c_char_to_string(new_kw)));
+    let res = store.alias_attribute((*old_kw).clone(), (*new_kw).clone());
+    Box::into_raw(Box::new(res.into()))
+}
+
+// TODO: tag reports with their origin (local write vs. sync) so observers can tell them apart.
+// Today every observed transaction is local: `Syncer::flow` only uploads, bails before applying
+// anything from a server that has moved on, and writes its own bookkeeping with raw SQL that
+// observers never see. The origin should be threaded through once sync applies remote
+// transactions via `InProgress`.
+#[no_mangle]
+pub unsafe extern "C" fn store_register_observer(store: *mut Store,
+                                                   key: *const c_char,
+                                            attributes: *const Entid,
+                                        attributes_len: usize,
+                                              callback: extern fn(key: *const c_char, reports: &ExternTxReportList)) {
+    let store = &mut*store;
+    let mut attribute_set = BTreeSet::new();
+    let slice = slice::from_raw_parts(attributes, attributes_len);
+    attribute_set.extend(slice.iter());
+    let key = c_char_to_string(key);
+    let tx_observer = Arc::new(TxObserver::new(attribute_set, move |obs_key, batch| {
+        let extern_reports: Vec<ExternTxReport> = batch.into_iter().map(|(tx_id, changes)| {
+            let changes: Vec<Entid> = changes.into_iter().map(|i|*i).collect();
+            let len = changes.len();
+            ExternTxReport {
+                txid: *tx_id,
+                changes: changes.into_boxed_slice(),
+                changes_len: len,
+            }
+        }).collect();
+        let len = extern_reports.len();
+        let reports = ExternTxReportList {
+            reports: extern_reports.into_boxed_slice(),
+            len: len,
+        };
+        callback(string_to_c_char(obs_key), &reports);
+    }));
+    store.register_observer(key, tx_observer);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_unregister_observer(store: *mut Store, key: *const c_char) {
+    let store = &mut*store;
+    let key = c_char_to_string(key);
+    store.unregister_observer(&key);
+}
+
+/// Unregisters every transaction, schema, and value observer, dropping their callbacks and
+/// anything they captured.
+#[no_mangle]
+pub unsafe extern "C" fn store_unregister_all_observers(store: *mut Store) {
+    let store = &mut*store;
+    store.unregister_all_observers();
+}
+
+/// Iterates over the keys of every registered observer, for finding observers that were never
+/// unregistered. Step through it with `string_iter_next` and free it with `string_iter_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn store_observer_keys_iter(store: *mut Store) -> *mut StringIterator {
+    let store = &*store;
+    Box::into_raw(Box::new(store.observer_keys().into_iter()))
+}
+
+/// Returns the next string, owned by the caller, or null when there are no more.
+#[no_mangle]
+pub unsafe extern "C" fn string_iter_next(iter: *mut StringIterator) -> *mut c_char {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), string_to_c_char)
+}
+
+/// Registers `callback` to be told, after each commit that adds, alters, or removes attributes,
+/// the idents of those attributes. The key and ident strings are only valid during the call.
+#[no_mangle]
+pub unsafe extern "C" fn store_register_schema_observer(store: *mut Store,
+                                                          key: *const c_char,
+                                                     callback: extern fn(key: *const c_char, attributes: *const *const c_char, attributes_len: usize)) {
+    let store = &mut*store;
+    let key = c_char_to_string(key);
+    let schema_observer = Arc::new(SchemaObserver::new(move |obs_key, attributes| {
+        let key = CString::new(obs_key).unwrap();
+        let idents: Vec<CString> = attributes.iter().map(|a| CString::new(a.to_string()).unwrap()).collect();
+        let pointers: Vec<*const c_char> = idents.iter().map(|i| i.as_ptr()).collect();
+        callback(key.as_ptr(), pointers.as_ptr(), pointers.len());
+    }));
+    store.register_schema_observer(key, schema_observer);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_unregister_schema_observer(store: *mut Store, key: *const c_char) {
+    let store = &mut*store;
+    let key = c_char_to_string(key);
+    store.unregister_schema_observer(&key);
+}
+
+/// Registers `callback` to be told, after each commit that changes values of `attribute`, the
+/// entity along with its retracted and asserted values. A null value pointer means no value was
+/// retracted or asserted; the values are only valid during the call.
+/// Errors if `attribute` is not an attribute in the current schema.
+#[no_mangle]
+pub unsafe extern "C" fn store_register_value_observer(store: *mut Store,
+                                                       key: *const c_char,
+                                                 attribute: *const c_char,
+                                                  callback: extern fn(entid: Entid, old: *mut TypedValue, new: *mut TypedValue)) -> *mut ExternResult {
+    let store = &mut*store;
+    let key = c_char_to_string(key);
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let entid = store.conn().current_schema().get_entid(&kw);
+    let res: Result<(), Error> = match entid {
+        Some(entid) => {
+            let value_observer = Arc::new(ValueObserver::new(entid.0, move |_obs_key, changes| {
+                for &(e, ref old, ref new) in changes.iter() {
+                    let mut old = old.clone();
+                    let mut new = new.clone();
+                    callback(e,
+                             old.as_mut().map_or(std::ptr::null_mut(), |v| v as *mut TypedValue),
+                             new.as_mut().map_or(std::ptr::null_mut(), |v| v as *mut TypedValue));
+                }
+            }));
+            store.register_value_observer(key, value_observer);
+            Ok(())
+        },
+        None => Err(ErrorKind::UnknownAttribute(kw.to_string()).into()),
+    };
+    Box::into_raw(Box::new(res.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_unregister_value_observer(store: *mut Store, key: *const c_char) {
+    let store = &mut*store;
+    let key = c_char_to_string(key);
+    store.unregister_value_observer(&key);
+}
+
+/// On success `ok` points to the entid of the attribute `attr`. An ident that isn't in the schema
+/// is an error naming it, not a crash, so callers may probe for attributes that may not exist yet.
+#[no_mangle]
+pub unsafe extern "C" fn store_entid_for_attribute(store: *mut Store, attr: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let keyword_string = c_char_to_string(attr);
+    let kw = try_extern!(kw_from_string(keyword_string));
+    let conn = store.conn();
+    let current_schema = conn.current_schema();
+    let result = match current_schema.get_entid(&kw) {
+        Some(entid) => ExternResult::ok::<Entid>(entid.into()),
+        None => ExternResult::err(&ErrorKind::UnknownAttribute(kw.to_string()).into()),
+    };
+    Box::into_raw(Box::new(result))
+}
+
+/// Returns the keyword naming the attribute `entid`, as a string owned by the caller, or null if
+/// `entid` isn't an attribute.
+#[no_mangle]
+pub unsafe extern "C" fn store_attribute_for_entid(store: *mut Store, entid: Entid) -> *const c_char {
+    let store = &*store;
+    let schema = store.conn().current_schema();
+    if !schema.is_attribute(entid) {
+        return std::ptr::null();
+    }
+    schema.get_ident(entid).map_or(std::ptr::null(), |ident| string_to_c_char(ident.to_string()))
+}
+
+/// Returns an iterator over every attribute in the store's current schema, in entid order.
+/// The iterator holds a snapshot: later schema changes don't affect it, and it may outlive the
+/// store.
+#[no_mangle]
+pub unsafe extern "C" fn store_attributes_iter(store: *mut Store) -> *mut AttributeIterator {
+    let store = &*store;
+    let schema = store.conn().current_schema();
+    let attributes: Vec<(NamespacedKeyword, Entid, Attribute)> =
+        schema.attribute_map
+              .iter()
+              .filter_map(|(entid, attribute)| schema.get_ident(*entid).map(|ident| (ident.clone(), *entid, attribute.clone())))
+              .collect();
+    Box::into_raw(Box::new(attributes.into_iter()))
+}
+
+/// Returns the next attribute, to be freed with `extern_attribute_destroy`, or null when there
+/// are no more.
+#[no_mangle]
+pub unsafe extern "C" fn attribute_iter_next(iter: *mut AttributeIterator) -> *mut ExternAttribute {
+    let iter = &mut *iter;
+    iter.next().map_or(std::ptr::null_mut(), |(ident, entid, attribute)| {
+        Box::into_raw(Box::new(ExternAttribute {
+            ident: string_to_c_char(ident.to_string()),
+            entid: entid,
+            value_type: attribute.value_type,
+            multival: attribute.multival,
+            unique: match attribute.unique {
+                None => 0,
+                Some(Unique::Value) => 1,
+                Some(Unique::Identity) => 2,
+            },
+            index: attribute.index,
+            fulltext: attribute.fulltext,
+            component: attribute.component,
+        }))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn extern_attribute_destroy(attribute: *mut ExternAttribute) {
+    if attribute.is_null() {
+        return;
+    }
+    let attribute = Box::from_raw(attribute);
+    let _ = CString::from_raw(attribute.ident);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tx_report_list_entry_at(tx_report_list: *mut ExternTxReportList, index: c_int) -> *const ExternTxReport {
+    let tx_report_list = &*tx_report_list;
+    let index = index as usize;
+    let report = Box::new(tx_report_list.reports[index].clone());
+    Box::into_raw(report)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn changelist_entry_at(tx_report: *mut ExternTxReport, index: c_int) -> Entid {
+    let tx_report = &*tx_report;
+    let index = index as usize;
+    tx_report.changes[index].clone()
+}
+
+/// The number of attributes the transaction touched.
+#[no_mangle]
+pub unsafe extern "C" fn changelist_attributes_len(tx_report: *const ExternTxReport) -> usize {
+    let tx_report = &*tx_report;
+    tx_report.changes_len
+}
+
+/// The entid of the `index`th attribute the transaction touched, in ascending entid order.
+#[no_mangle]
+pub unsafe extern "C" fn changelist_attribute_at(tx_report: *const ExternTxReport, index: usize) -> Entid {
+    let tx_report = &*tx_report;
+    tx_report.changes[index]
+}
+
+/// Syncs the store with the server at `server_uri`. Merging isn't supported: if the server has
+/// moved on since the last sync, nothing is synced and the error code is `SyncDiverged`, so the
+/// caller can tell the user rather than retrying.
+#[no_mangle]
+pub unsafe extern "C" fn store_sync(store: *mut Store, user_uuid: *const c_char, server_uri: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let user_uuid = c_char_to_string(user_uuid);
+    let server_uri = c_char_to_string(server_uri);
+    let res = store.sync(&server_uri, &user_uuid);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Like `store_sync`, but calls `callback` as the sync moves through its phases, with how much
+/// of the current phase is done out of how much in total. `phase` is 0 while downloading, 1 while
+/// transacting downloaded changes, and 2 while uploading local transactions. The callback runs on
+/// the calling thread before this function returns.
+#[no_mangle]
+pub unsafe extern "C" fn store_sync_with_progress(store: *mut Store,
+                                              user_uuid: *const c_char,
+                                             server_uri: *const c_char,
+                                               callback: extern fn(phase: c_int, done: usize, total: usize)) -> *mut ExternResult {
+    let store = &mut*store;
+    let user_uuid = c_char_to_string(user_uuid);
+    let server_uri = c_char_to_string(server_uri);
+    let res = store.sync_with_progress(&server_uri, &user_uuid, &mut |phase, done, total| {
+        let phase = match phase {
+            SyncPhase::Download => 0,
+            SyncPhase::Transact => 1,
+            SyncPhase::Upload => 2,
+        };
+        callback(phase, done, total);
+    });
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// A sync running on a background thread, started by `store_sync_cancellable`.
+pub struct BackgroundSync {
+    interrupt: Arc<SyncInterrupt>,
+    thread: Option<thread::JoinHandle<Result<(), String>>>,
+}
+
+/// Starts a sync on a background thread, using its own connection to the store's database, and
+/// returns a handle to it. Stop it with `sync_handle_cancel` and collect its result with
+/// `sync_handle_join`. Returns null for an in-memory store, an invalid `user_uuid`, or if the
+/// database can't be opened again. Free the handle with `sync_handle_destroy`; a sync that hasn't
+/// been joined by then keeps running to completion on its own.
+#[no_mangle]
+pub unsafe extern "C" fn store_sync_cancellable(store: *mut Store, user_uuid: *const c_char, server_uri: *const c_char) -> *mut BackgroundSync {
+    let store = &*store;
+    let user_uuid = c_char_to_string(user_uuid);
+    let server_uri = c_char_to_string(server_uri);
+    store.sync_cancellable(&server_uri, &user_uuid).map_or(std::ptr::null_mut(), |mut handle| {
+        let interrupt = handle.interrupt();
+        let thread = thread::spawn(move || handle.run().map_err(|e| e.to_string()));
+        Box::into_raw(Box::new(BackgroundSync { interrupt, thread: Some(thread) }))
+    })
+}
+
+/// Asks the sync to stop at its next safe point, between uploaded transactions. A cancelled sync
+/// commits nothing locally and `sync_handle_join` reports it as an error. Safe to call from any
+/// thread.
+#[no_mangle]
+pub unsafe extern "C" fn sync_handle_cancel(handle: *const BackgroundSync) {
+    let handle = &*handle;
+    handle.interrupt.cancel();
+}
+
+/// Waits for the sync to finish and returns its result. Only the first call waits; later calls
+/// report an error.
+#[no_mangle]
+pub unsafe extern "C" fn sync_handle_join(handle: *mut BackgroundSync) -> *mut ExternResult {
+    let handle = &mut*handle;
+    let res = match handle.thread.take().map(|thread| thread.join()) {
+        Some(Ok(Ok(()))) => ExternResult::ok(()),
+        Some(Ok(Err(message))) => ExternResult::err_with_code(ExternErrorCode::Sync, message),
+        Some(Err(_)) => ExternResult::err_with_code(ExternErrorCode::Other, "sync thread panicked"),
+        None => ExternResult::err_with_code(ExternErrorCode::InvalidArgument, "sync already joined"),
+    };
+    Box::into_raw(Box::new(res))
+}
+
+/// Reports what the store knows about its sync state without contacting the server. On success
+/// `ok` holds a `SyncStatus`: read it with the `sync_status_*` accessors and free it with
+/// `sync_status_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn store_sync_status(store: *mut Store) -> *mut ExternResult {
+    let store = &mut*store;
+    let res = store.sync_status();
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// When the last successful sync finished, in microseconds since the epoch. `value` is null if
+/// the store has never synced.
+#[no_mangle]
+pub unsafe extern "C" fn sync_status_last_sync_micros(status: *const SyncStatus) -> *mut ExternOption {
+    let status = &*status;
+    Box::into_raw(Box::new(status.last_sync.into()))
+}
+
+/// The server's head as of the last successful sync, as a hyphenated UUID string owned by the
+/// caller. This is the nil UUID if the store has never synced.
+#[no_mangle]
+pub unsafe extern "C" fn sync_status_remote_head(status: *const SyncStatus) -> *mut c_char {
+    let status = &*status;
+    string_to_c_char(status.remote_head.hyphenated().to_string())
+}
+
+/// The UUID the store's latest transaction was uploaded under, as a string owned by the caller,
+/// or null if that transaction hasn't been uploaded.
+#[no_mangle]
+pub unsafe extern "C" fn sync_status_local_head(status: *const SyncStatus) -> *mut c_char {
+    let status = &*status;
+    status.local_head.map_or(std::ptr::null_mut(), |uuid| string_to_c_char(uuid.hyphenated().to_string()))
+}
+
+/// Whether the store has transactions that the next sync would upload.
+#[no_mangle]
+pub unsafe extern "C" fn sync_status_has_local_changes(status: *const SyncStatus) -> bool {
+    let status = &*status;
+    status.has_local_changes
+}
+
+/// Parses `value` as a UUID. On failure, the error result names the offending input.
+unsafe fn parse_uuid(value: *const c_char) -> Result<Uuid, ExternResult> {
+    let value = c_char_to_string(value);
+    Uuid::parse_str(&value).map_err(|_| ExternResult::err(&ErrorKind::InvalidUuid(value.clone()).into()))
+}
+
+/// Parses `value` as a single scalar EDN value, such as `"a@b.com"`, `42` or `#uuid "..."`, as
+/// used to write a lookup ref. Collections and symbols aren't scalars, and are rejected.
+unsafe fn parse_edn_value(value: *const c_char) -> Result<TypedValue, ExternResult> {
+    let value = c_char_to_string(value);
+    let parsed = mentat::edn::parse::value(&value).map_err(|e| ExternResult::err(&e.into()))?;
+    typed_value_from_edn(parsed.without_spans())
+        .ok_or_else(|| ExternResult::err(&format!("not a scalar EDN value: {}", value).into()))
+}
+
+fn typed_value_from_edn(value: mentat::edn::Value) -> Option<TypedValue> {
+    match value {
+        mentat::edn::Value::Boolean(x) => Some(x.into()),
+        mentat::edn::Value::Instant(x) => Some(TypedValue::Instant(x)),
+        mentat::edn::Value::Integer(x) => Some(TypedValue::Long(x)),
+        mentat::edn::Value::Uuid(x) => Some(x.into()),
+        mentat::edn::Value::Float(x) => Some(TypedValue::Double(x)),
+        mentat::edn::Value::Text(x) => Some(x.into()),
+        mentat::edn::Value::NamespacedKeyword(x) => Some(TypedValue::Keyword(x.into())),
+        _ => None,
+    }
+}
+
+fn assert_datom<E, V>(store: &mut Store, entid: E, attribute: String, value: V) -> *mut ExternResult
+where E: Into<KnownEntid>,
+      V: Into<TypedValue> {
+    let kw = try_extern!(kw_from_string(attribute));
+    let res = store.assert_datom(entid.into(), (*kw).clone(), value.into());
+    Box::into_raw(Box::new(res.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_set_long_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: i64) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.assert_datom(KnownEntid(entid), (*kw).clone(), TypedValue::Long(value));
+    Box::into_raw(Box::new(res.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_set_entid_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: Entid) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.assert_datom(KnownEntid(entid), (*kw).clone(), TypedValue::Ref(value));
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Retracts every value of `attribute` on `entid`. On success, `ok` points to the number of
+/// datoms retracted.
+#[no_mangle]
+pub unsafe extern "C" fn store_retract_attribute(store: *mut Store, entid: Entid, attribute: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.retract_attribute(entid, &kw);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Transacts datoms described as a JSON array of `{"op", "e", "a", "v"}` objects; see
+/// `InProgress::transact_json` for the accepted shapes. On success `ok` points to a `TxReport`. On
+/// failure `err` says what went wrong and, for a malformed datom, at which index.
+#[no_mangle]
+pub unsafe extern "C" fn transaction_from_json(store: *mut Store, json: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let json = c_char_to_string(json);
+    let result = match store.transact_json(&json) {
+        Ok(report) => ExternResult::ok(report),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(result))
+}
+
+/// Retracts every datom of `entid`. Other entities' refs to `entid` are left dangling; retract
+/// them separately if that matters. On success `ok` points to a `TxReport`, to be freed with
+/// `tx_report_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn store_retract_entity(store: *mut Store, entid: Entid) -> *mut ExternResult {
+    let store = &mut*store;
+    let res = store.retract_entity(entid);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Retracts `value` of `attribute` from the entity whose unique attribute `unique_attr` has the
+/// value `lookup_value`. On success `ok` points to a `TxReport`, to be freed with
+/// `tx_report_destroy`. The typed values are borrowed, not consumed.
+#[no_mangle]
+pub unsafe extern "C" fn store_retract_by_lookup(store: *mut Store, unique_attr: *const c_char, lookup_value: *mut TypedValue, attribute: *const c_char, value: *mut TypedValue) -> *mut ExternResult {
+    let store = &mut*store;
+    let unique_attr = try_extern!(kw_from_string(c_char_to_string(unique_attr)));
+    let attribute = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.retract_by_lookup(&unique_attr, &*lookup_value, &attribute, &*value);
+    Box::into_raw(Box::new(res.into()))
+}
+
+/// Resolves the lookup ref `[unique_attr value]` to an entid. On success `ok` points to the
+/// `Entid`, or is null if no entity has that value. `err` is set if `unique_attr` isn't a known
+/// `:db/unique` attribute or `value` isn't of its type. `value` is borrowed, not consumed.
+#[no_mangle]
+pub unsafe extern "C" fn store_resolve_lookup_ref(store: *mut Store, unique_attr: *const c_char, value: *mut TypedValue) -> *mut ExternResult {
+    let store = &*store;
+    let unique_attr = try_extern!(kw_from_string(c_char_to_string(unique_attr)));
+    let res = match store.resolve_lookup_ref(&unique_attr, &*value) {
+        Ok(Some(entid)) => ExternResult::ok(entid),
+        Ok(None) => ExternResult::ok_ptr(std::ptr::null()),
+        Err(e) => ExternResult::err(&e),
+    };
+    Box::into_raw(Box::new(res))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_set_kw_ref_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let value = try_extern!(kw_from_string(c_char_to_string(value)));
+    let res = store.assert_kw_ref(KnownEntid(entid), (*kw).clone(), &value);
+    Box::into_raw(Box::new(res.into()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_retract_kw_ref_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let value = try_extern!(kw_from_string(c_char_to_string(value)));
+    let res = store.retract_kw_ref(KnownEntid(entid), (*kw).clone(), &value);
+    Box::into_raw(Box::new(res.into()))
 }
 
-//as_boolean
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index_as_boolean(values: *mut Vec<TypedValue>, index: c_int) ->  bool {
-    let result = &*values;
-    let value = result.get(index as usize).expect("No value at index");
-    value.clone().into_boolean().expect("Typed value cannot be coerced into a Boolean")
+pub unsafe extern "C" fn store_set_boolean_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: bool) -> *mut ExternResult {
+    let store = &mut*store;
+    assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), value)
 }
 
-//as_double
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index_as_double(values: *mut Vec<TypedValue>, index: c_int) ->  f64 {
-    let result = &*values;
-    let value = result.get(index as usize).expect("No value at index");
-    value.clone().into_double().expect("Typed value cannot be coerced into a Double")
+pub unsafe extern "C" fn store_set_double_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: f64) -> *mut ExternResult {
+    let store = &mut*store;
+    assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), value)
 }
 
-//as_timestamp
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index_as_timestamp(values: *mut Vec<TypedValue>, index: c_int) ->  i64 {
-    let result = &*values;
-    let value = result.get(index as usize).expect("No value at index");
-    value.clone().into_timestamp().expect("Typed value cannot be coerced into a timestamp")
+pub unsafe extern "C" fn store_set_timestamp_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: time_t) -> *mut ExternResult {
+    let store = &mut*store;
+    let kw = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = store.assert_datom(KnownEntid(entid), (*kw).clone(), TypedValue::instant(value as i64));
+    Box::into_raw(Box::new(res.into()))
 }
 
-//as_string
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index_as_string(values: *mut Vec<TypedValue>, index: c_int) ->  *mut c_char {
-    let result = &*values;
-    let value = result.get(index as usize).expect("No value at index");
-    c_char_from_rc(value.clone().into_string().expect("Typed value cannot be coerced into a String"))
+pub unsafe extern "C" fn store_set_string_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), c_char_to_string(value))
 }
 
-//as_uuid
+/// Like `store_set_string_for_attribute_on_entid`, for the `len` bytes of UTF-8 text at `value`,
+/// which may contain NULs.
 #[no_mangle]
-pub unsafe extern "C" fn value_at_index_as_uuid(values: *mut Vec<TypedValue>, index: c_int) ->  *mut c_char {
-    let result = &*values;
-    let value = result.get(index as usize).expect("No value at index");
-    string_to_c_char(value.clone().into_uuid_string().expect("Typed value cannot be coerced into a Uuid"))
+pub unsafe extern "C" fn store_set_string_bytes_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const u8, len: usize) -> *mut ExternResult {
+    let store = &mut*store;
+    assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), string_from_bytes(value, len))
 }
 
-// TODO: q_prepare
+#[no_mangle]
+pub unsafe extern "C" fn store_set_uuid_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let store = &mut*store;
+    match parse_uuid(value) {
+        Ok(uuid) => assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), uuid),
+        Err(e) => Box::into_raw(Box::new(e)),
+    }
+}
 
-// TODO: q_explain
+/// Asserts `value` of `attribute` on the entity whose unique attribute `unique_attr` has the value
+/// written in EDN as `unique_value_edn`, such as `"a@b.com"` for the lookup ref
+/// `[:person/email "a@b.com"]`.
+unsafe fn assert_datom_by_lookup<V>(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: V) -> *mut ExternResult
+where V: Into<TypedValue> {
+    let store = &mut*store;
+    let unique_attr = try_extern!(kw_from_string(c_char_to_string(unique_attr)));
+    let attribute = try_extern!(kw_from_string(c_char_to_string(attribute)));
+    let res = match parse_edn_value(unique_value_edn) {
+        Ok(lookup_value) => store.assert_by_lookup(&unique_attr, &lookup_value, &attribute, &value.into()).into(),
+        Err(e) => e,
+    };
+    Box::into_raw(Box::new(res))
+}
 
-// TODO: lookup_values_for_attribute
+/// Like `store_set_long_for_attribute_on_entid`, for the entity found by the lookup ref
+/// `[unique_attr unique_value_edn]`, where `unique_value_edn` is the unique value written in EDN.
+/// On success `ok` points to a `TxReport`. `err` says so if no entity matches.
+#[no_mangle]
+pub unsafe extern "C" fn store_set_long_for_attribute_on_lookup_ref(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: i64) -> *mut ExternResult {
+    assert_datom_by_lookup(store, unique_attr, unique_value_edn, attribute, TypedValue::Long(value))
+}
 
 #[no_mangle]
-pub unsafe extern "C" fn store_value_for_attribute(store: *mut Store, entid: i64, attribute: *const c_char) ->  *mut ExternResult {
-    let store = &*store;
-    let kw = kw_from_string(c_char_to_string(attribute));
-    let value = match store.lookup_value_for_attribute(entid, &kw) {
-        Ok(Some(v)) => ExternResult { ok: Box::into_raw(Box::new(v)) as *const _ as *const c_void, err: std::ptr::null() },
-        Ok(None) => ExternResult { ok: std::ptr::null(), err: std::ptr::null() },
-        Err(e) => ExternResult { ok: std::ptr::null(), err: string_to_c_char(e.description()) },
-    };
-    Box::into_raw(Box::new(value))
+pub unsafe extern "C" fn store_set_entid_for_attribute_on_lookup_ref(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: Entid) -> *mut ExternResult {
+    assert_datom_by_lookup(store, unique_attr, unique_value_edn, attribute, TypedValue::Ref(value))
 }
 
+/// `value` is the keyword ident of the entity to refer to.
 #[no_mangle]
-pub unsafe extern "C" fn store_register_observer(store: *mut Store,
-                                                   key: *const c_char,
-                                            attributes: *const Entid,
-                                        attributes_len: usize,
-                                              callback: extern fn(key: *const c_char, reports: &ExternTxReportList)) {
-    let store = &mut*store;
-    let mut attribute_set = BTreeSet::new();
-    let slice = slice::from_raw_parts(attributes, attributes_len);
-    attribute_set.extend(slice.iter());
-    let key = c_char_to_string(key);
-    let tx_observer = Arc::new(TxObserver::new(attribute_set, move |obs_key, batch| {
-        let extern_reports: Vec<ExternTxReport> = batch.into_iter().map(|(tx_id, changes)| {
-            let changes: Vec<Entid> = changes.into_iter().map(|i|*i).collect();
-            let len = changes.len();
-            ExternTxReport {
-                txid: *tx_id,
-                changes: changes.into_boxed_slice(),
-                changes_len: len,
-            }
-        }).collect();
-        let len = extern_reports.len();
-        let reports = ExternTxReportList {
-            reports: extern_reports.into_boxed_slice(),
-            len: len,
-        };
-        callback(string_to_c_char(obs_key), &reports);
-    }));
-    store.register_observer(key, tx_observer);
+pub unsafe extern "C" fn store_set_kw_ref_for_attribute_on_lookup_ref(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    let value = try_extern!(kw_from_string(c_char_to_string(value)));
+    assert_datom_by_lookup(store, unique_attr, unique_value_edn, attribute, TypedValue::Keyword(value))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_unregister_observer(store: *mut Store, key: *const c_char) {
-    let store = &mut*store;
-    let key = c_char_to_string(key);
-    store.unregister_observer(&key);
+pub unsafe extern "C" fn store_set_boolean_for_attribute_on_lookup_ref(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: bool) -> *mut ExternResult {
+    assert_datom_by_lookup(store, unique_attr, unique_value_edn, attribute, value)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_entid_for_attribute(store: *mut Store, attr: *const c_char) -> Entid {
-    let store = &mut*store;
-    let keyword_string = c_char_to_string(attr);
-    let kw = kw_from_string(keyword_string);
-    let conn = store.conn();
-    let current_schema = conn.current_schema();
-    current_schema.get_entid(&kw).expect("Unable to find entid for invalid attribute").into()
+pub unsafe extern "C" fn store_set_double_for_attribute_on_lookup_ref(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: f64) -> *mut ExternResult {
+    assert_datom_by_lookup(store, unique_attr, unique_value_edn, attribute, value)
 }
 
+/// `value` is in microseconds since the epoch.
 #[no_mangle]
-pub unsafe extern "C" fn tx_report_list_entry_at(tx_report_list: *mut ExternTxReportList, index: c_int) -> *const ExternTxReport {
-    let tx_report_list = &*tx_report_list;
-    let index = index as usize;
-    let report = Box::new(tx_report_list.reports[index].clone());
-    Box::into_raw(report)
+pub unsafe extern "C" fn store_set_timestamp_micros_for_attribute_on_lookup_ref(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: i64) -> *mut ExternResult {
+    assert_datom_by_lookup(store, unique_attr, unique_value_edn, attribute, TypedValue::instant(value))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn changelist_entry_at(tx_report: *mut ExternTxReport, index: c_int) -> Entid {
-    let tx_report = &*tx_report;
-    let index = index as usize;
-    tx_report.changes[index].clone()
+pub unsafe extern "C" fn store_set_string_for_attribute_on_lookup_ref(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    assert_datom_by_lookup(store, unique_attr, unique_value_edn, attribute, c_char_to_string(value))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_sync(store: *mut Store, user_uuid: *const c_char, server_uri: *const c_char) -> *mut ExternResult {
-    let store = &mut*store;
-    let user_uuid = c_char_to_string(user_uuid);
-    let server_uri = c_char_to_string(server_uri);
-    let res = store.sync(&server_uri, &user_uuid);
-    Box::into_raw(Box::new(res.into()))
+pub unsafe extern "C" fn store_set_uuid_for_attribute_on_lookup_ref(store: *mut Store, unique_attr: *const c_char, unique_value_edn: *const c_char, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+    match parse_uuid(value) {
+        Ok(uuid) => assert_datom_by_lookup(store, unique_attr, unique_value_edn, attribute, uuid),
+        Err(e) => Box::into_raw(Box::new(e)),
+    }
 }
 
-fn assert_datom<E, V>(store: &mut Store, entid: E, attribute: String, value: V) -> *mut ExternResult
+fn retract_datom<E, V>(store: &mut Store, entid: E, attribute: String, value: V) -> *mut ExternResult
 where E: Into<KnownEntid>,
       V: Into<TypedValue> {
-    let kw = kw_from_string(attribute);
-    let res = store.assert_datom(entid.into(), kw, value.into());
+    let kw = try_extern!(kw_from_string(attribute));
+    let res = store.retract_datom(entid.into(), (*kw).clone(), value.into());
     Box::into_raw(Box::new(res.into()))
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn store_set_long_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: i64) -> *mut ExternResult {
-    let store = &mut*store;
-    let kw = kw_from_string(c_char_to_string(attribute));
-    let res = store.assert_datom(KnownEntid(entid), kw, TypedValue::Long(value));
-    Box::into_raw(Box::new(res.into()))
-}
+// The `store_retract_*_for_attribute_on_entid` functions mirror the setters above, retracting the
+// single datom `[entid attribute value]`. Retracting a datom that isn't there is not an error.
 
 #[no_mangle]
-pub unsafe extern "C" fn store_set_entid_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: Entid) -> *mut ExternResult {
+pub unsafe extern "C" fn store_retract_long_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: i64) -> *mut ExternResult {
     let store = &mut*store;
-    let kw = kw_from_string(c_char_to_string(attribute));
-    let res = store.assert_datom(KnownEntid(entid), kw, TypedValue::Ref(value));
-    Box::into_raw(Box::new(res.into()))
+    retract_datom(store, KnownEntid(entid), c_char_to_string(attribute), TypedValue::Long(value))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_set_kw_ref_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+pub unsafe extern "C" fn store_retract_ref_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: Entid) -> *mut ExternResult {
     let store = &mut*store;
-    let kw = kw_from_string(c_char_to_string(attribute));
-    let value = kw_from_string(c_char_to_string(value));
-    let is_valid = store.conn().current_schema().get_entid(&value);
-    if is_valid.is_none() {
-        return Box::into_raw(Box::new(ExternResult { ok: std::ptr::null_mut(), err: string_to_c_char(format!("Unknown attribute {:?}", value)) }));
-    }
-    let kw_entid = is_valid.unwrap();
-    let res = store.assert_datom(KnownEntid(entid), kw, TypedValue::Ref(kw_entid.into()));
-    Box::into_raw(Box::new(res.into()))
+    retract_datom(store, KnownEntid(entid), c_char_to_string(attribute), TypedValue::Ref(value))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_set_boolean_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: bool) -> *mut ExternResult {
+pub unsafe extern "C" fn store_retract_boolean_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: bool) -> *mut ExternResult {
     let store = &mut*store;
-    assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), value)
+    retract_datom(store, KnownEntid(entid), c_char_to_string(attribute), value)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_set_double_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: f64) -> *mut ExternResult {
+pub unsafe extern "C" fn store_retract_double_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: f64) -> *mut ExternResult {
     let store = &mut*store;
-    assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), value)
+    retract_datom(store, KnownEntid(entid), c_char_to_string(attribute), value)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_set_timestamp_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: time_t) -> *mut ExternResult {
+pub unsafe extern "C" fn store_retract_timestamp_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: time_t) -> *mut ExternResult {
     let store = &mut*store;
-    let kw = kw_from_string(c_char_to_string(attribute));
-    let res = store.assert_datom(KnownEntid(entid), kw, TypedValue::instant(value as i64));
-    Box::into_raw(Box::new(res.into()))
+    retract_datom(store, KnownEntid(entid), c_char_to_string(attribute), TypedValue::instant(value as i64))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_set_string_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+pub unsafe extern "C" fn store_retract_string_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
     let store = &mut*store;
-    assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), c_char_to_string(value))
+    retract_datom(store, KnownEntid(entid), c_char_to_string(attribute), c_char_to_string(value))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn store_set_uuid_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
+pub unsafe extern "C" fn store_retract_uuid_for_attribute_on_entid(store: *mut Store, entid: Entid, attribute: *const c_char, value: *const c_char) -> *mut ExternResult {
     let store = &mut*store;
-    let uuid = Uuid::parse_str(&c_char_to_string(value)).expect("valid uuid");
-    assert_datom(store, KnownEntid(entid), c_char_to_string(attribute), uuid)
+    match parse_uuid(value) {
+        Ok(uuid) => retract_datom(store, KnownEntid(entid), c_char_to_string(attribute), uuid),
+        Err(e) => Box::into_raw(Box::new(e)),
+    }
 }
 
 #[no_mangle]
@@ -648,14 +3103,674 @@ macro_rules! define_destructor (
 );
 define_destructor!(query_builder_destroy, QueryBuilder);
 
+define_destructor!(prepared_destroy, CachedQuery);
+
+// Dropping an uncommitted `InProgress` rolls it back.
+define_destructor!(in_progress_destroy, InProgress);
+
+define_destructor!(in_progress_read_destroy, InProgressRead);
+
+define_destructor!(tempid_iter_destroy, TempIdIterator);
+
+define_destructor!(string_iter_destroy, StringIterator);
+
+define_destructor!(entid_iter_destroy, EntidIterator);
+
+define_destructor!(entid_destroy, Entid);
+
+define_destructor!(bool_destroy, bool);
+
+define_destructor!(double_destroy, f64);
+
+define_destructor!(find_spec_kind_destroy, FindSpecKind);
+
+define_destructor!(attribute_iter_destroy, AttributeIterator);
+
+define_destructor!(attribute_values_destroy, AttributeValues);
+
+define_destructor!(attribute_values_list_destroy, AttributeValuesList);
+
+// Dropping an uncommitted builder rolls back its transaction.
+define_destructor!(entity_builder_destroy, InProgressBuilder);
+
+define_destructor!(query_output_destroy, QueryOutput);
+
+define_destructor!(timed_query_output_destroy, TimedQueryOutput);
+
+define_destructor!(query_handle_destroy, QueryHandle);
+
+define_destructor!(recovered_store_destroy, ExternRecoveredStore);
+
 define_destructor!(store_destroy, Store);
 
+define_destructor!(shared_store_destroy, SharedStore);
+
+define_destructor!(store_reader_destroy, StoreReader);
+
+define_destructor!(sync_status_destroy, SyncStatus);
+
+define_destructor!(sync_handle_destroy, BackgroundSync);
+
+define_destructor!(tx_report_destroy, TxReport);
+
+define_destructor!(tx_log_destroy, TxLog);
+
 define_destructor!(typed_value_destroy, TypedValue);
 
 define_destructor!(typed_value_list_destroy, Vec<TypedValue>);
 
 define_destructor!(typed_value_list_iter_destroy, TypedValueIterator);
 
+define_destructor!(optional_typed_value_list_destroy, Vec<Option<TypedValue>>);
+
+define_destructor!(optional_typed_value_result_set_destroy, Vec<Vec<Option<TypedValue>>>);
+
 define_destructor!(typed_value_result_set_destroy, Vec<Vec<TypedValue>>);
 
 define_destructor!(typed_value_result_set_iter_destroy, TypedValueListIterator);
+
+/// Frees an array of `count` results returned by a batch call such as `store_transact_each`,
+/// together with each entry's `err` string and `err_detail`. The `ok` payloads are not freed:
+/// their types aren't known here, so callers must first hand each non-null `ok` to the destructor
+/// for its type, or take ownership of it. `count` must be the length the array was returned with.
+#[no_mangle]
+pub unsafe extern "C" fn extern_result_list_destroy(results: *mut ExternResult, count: usize) {
+    if results.is_null() {
+        return;
+    }
+    let results: Box<[ExternResult]> = Box::from_raw(slice::from_raw_parts_mut(results, count));
+    for result in results.iter() {
+        free_result_error(result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn test_partially_drained_iterators_are_freed() {
+        // Every value shares one `Rc`, so its strong count tells us how many are still alive.
+        let shared = Rc::new("value".to_string());
+        unsafe {
+            let values: Vec<TypedValue> = (0..5).map(|_| TypedValue::String(shared.clone())).collect();
+            let iter = values_iter(Box::into_raw(Box::new(values)));
+            assert_eq!(typed_value_list_iter_remaining(iter), 5);
+            (&mut *iter).next();
+            (&mut *iter).next();
+            assert_eq!(typed_value_list_iter_remaining(iter), 3);
+            assert_eq!(Rc::strong_count(&shared), 4);
+            typed_value_list_iter_destroy(iter);
+            assert_eq!(Rc::strong_count(&shared), 1);
+
+            let rows: Vec<Vec<TypedValue>> = (0..3).map(|_| vec![TypedValue::String(shared.clone())]).collect();
+            let iter = rows_iter(Box::into_raw(Box::new(rows)));
+            let row = rows_iter_next(iter);
+            assert_eq!(rows_iter_remaining(iter), 2);
+            typed_value_list_destroy(row);
+            typed_value_result_set_iter_destroy(iter);
+            assert_eq!(Rc::strong_count(&shared), 1);
+        }
+    }
+
+    #[test]
+    fn test_typed_value_clone_outlives_borrowed_value() {
+        unsafe {
+            let values = Box::into_raw(Box::new(vec![TypedValue::Long(7)]));
+            let copy = typed_value_clone(value_at_index(values, 0));
+            typed_value_list_destroy(values);
+
+            let res = Box::from_raw(typed_value_as_long(copy));
+            assert_eq!(*Box::from_raw(res.ok as *mut i64), 7);
+        }
+    }
+
+    #[test]
+    fn test_typed_value_as_boolean_and_double_are_destroyable() {
+        unsafe {
+            let res = Box::from_raw(typed_value_as_boolean(Box::into_raw(Box::new(TypedValue::Boolean(true)))));
+            assert!(*(res.ok as *const bool));
+            bool_destroy(res.ok as *mut bool);
+
+            let res = Box::from_raw(typed_value_as_double(Box::into_raw(Box::new(TypedValue::from(1.5)))));
+            assert_eq!(*(res.ok as *const f64), 1.5);
+            double_destroy(res.ok as *mut f64);
+        }
+    }
+
+    #[test]
+    fn test_entity_builder_commits_atomically() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let b = store.transact(r#"[[:db/add "b" :foo/name "Bob"]]"#).expect("data").tempids["b"];
+        let attr = |name: &str| CString::new(name).unwrap();
+
+        unsafe {
+            let res = Box::from_raw(store_entity_builder(&mut store));
+            let builder = res.ok as *mut InProgressBuilder;
+            let name = TypedValue::typed_string("Alice");
+            let res = Box::from_raw(entity_builder_add_to_new(builder, attr("a").as_ptr(), attr(":foo/name").as_ptr(), &name));
+            assert!(res.err.is_null());
+
+            // A type mismatch is reported without spoiling the rest of the builder.
+            let res = Box::from_raw(entity_builder_add_long(builder, b, attr(":foo/name").as_ptr(), 30));
+            assert!(!res.err.is_null());
+
+            let res = Box::from_raw(entity_builder_add_long(builder, b, attr(":foo/age").as_ptr(), 30));
+            assert!(res.err.is_null());
+            let res = Box::from_raw(entity_builder_commit(builder));
+            let report = Box::from_raw(res.ok as *mut TxReport);
+            let a = report.tempids["a"];
+
+            let iter = tx_report_tempids_iter(&*report as *const TxReport as *mut TxReport);
+            let tempid = tempid_iter_next(iter);
+            assert_eq!(c_char_to_string((*tempid).tempid), "a");
+            assert_eq!((*tempid).entid, a);
+            extern_tempid_destroy(tempid);
+            assert!(tempid_iter_next(iter).is_null());
+            tempid_iter_destroy(iter);
+
+            let iter = tx_report_changed_entids_iter(&*report as *const TxReport as *mut TxReport);
+            assert_eq!(entid_iter_remaining(iter), 2);
+            for &expected in &[b.min(a), b.max(a)] {
+                let entid = entid_iter_next(iter);
+                assert_eq!(*entid, expected);
+                entid_destroy(entid);
+            }
+            assert_eq!(entid_iter_remaining(iter), 0);
+            assert!(entid_iter_next(iter).is_null());
+            entid_iter_destroy(iter);
+
+            assert_eq!(store.lookup_value_for_attribute(a, &kw_from_string(":foo/name".to_string()).expect("keyword")).expect("name"), Some(name));
+            assert_eq!(store.lookup_value_for_attribute(b, &kw_from_string(":foo/age".to_string()).expect("keyword")).expect("age"), Some(TypedValue::Long(30)));
+        }
+    }
+
+    #[test]
+    fn test_attributes_iter() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/id :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity}
+        ]"#).expect("schema");
+
+        unsafe {
+            let iter = store_attributes_iter(&mut store);
+            let mut found = None;
+            loop {
+                let attribute = attribute_iter_next(iter);
+                if attribute.is_null() {
+                    break;
+                }
+                if c_char_to_string((*attribute).ident) == ":foo/id" {
+                    found = Some(((*attribute).value_type, (*attribute).multival, (*attribute).unique));
+                }
+                extern_attribute_destroy(attribute);
+            }
+            attribute_iter_destroy(iter);
+            assert_eq!(found, Some((ValueType::String, false, 2)));
+        }
+    }
+
+    #[test]
+    fn test_unshared_values_share_no_rc() {
+        let string = Rc::new("value".to_string());
+        let keyword = Rc::new(NamespacedKeyword::new("foo", "bar"));
+        let copies = vec![unshared(TypedValue::String(string.clone())), unshared(TypedValue::Keyword(keyword.clone()))];
+        assert_eq!(Rc::strong_count(&string), 1);
+        assert_eq!(Rc::strong_count(&keyword), 1);
+        assert_eq!(copies, vec![TypedValue::String(string), TypedValue::Keyword(keyword)]);
+    }
+
+    #[test]
+    fn test_shared_store_handles_share_one_store() {
+        unsafe {
+            let uri = CString::new("").expect("uri");
+            let res = Box::from_raw(store_open_shared(uri.as_ptr()));
+            assert!(res.err.is_null());
+            let store = res.ok as *mut SharedStore;
+            let other = shared_store_clone(store);
+
+            let schema = CString::new("[{:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}]").expect("schema");
+            let res = Box::from_raw(shared_store_transact(store, schema.as_ptr()));
+            assert!(res.err.is_null());
+            tx_report_destroy(res.ok as *mut TxReport);
+            let data = CString::new("[[:db/add \"a\" :foo/n 7]]").expect("data");
+            let res = Box::from_raw(shared_store_transact(other, data.as_ptr()));
+            assert!(res.err.is_null());
+            tx_report_destroy(res.ok as *mut TxReport);
+
+            // The first handle can go; the second still sees everything.
+            shared_store_destroy(store);
+            let query = CString::new("[:find ?n :where [_ :foo/n ?n]]").expect("query");
+            let res = Box::from_raw(shared_store_query(other, query.as_ptr()));
+            assert!(res.err.is_null());
+            let rows = Box::from_raw(res.ok as *mut Vec<Vec<TypedValue>>);
+            assert_eq!(*rows, vec![vec![TypedValue::Long(7)]]);
+            shared_store_destroy(other);
+        }
+    }
+
+    #[test]
+    fn test_typed_value_eq_and_cmp() {
+        unsafe {
+            let long = TypedValue::Long(5);
+            let reference = TypedValue::Ref(5);
+            assert!(typed_value_eq(&long, &TypedValue::Long(5)));
+            assert!(!typed_value_eq(&long, &reference));
+            assert_eq!(typed_value_cmp(&long, &TypedValue::Long(5)), 0);
+            assert_eq!(typed_value_cmp(&long, &TypedValue::Long(6)), -1);
+            assert_eq!(typed_value_cmp(&long, &reference), -typed_value_cmp(&reference, &long));
+            assert!(typed_value_cmp(&long, &reference) != 0);
+        }
+    }
+
+    #[test]
+    fn test_typed_value_debug_string() {
+        unsafe {
+            let render = |value: TypedValue| c_char_to_string(typed_value_debug_string(&value));
+            assert_eq!(render(TypedValue::Long(42)), "42");
+            assert_eq!(render(TypedValue::typed_string("say \"hi\"")), "\"say \\\"hi\\\"\"");
+            assert_eq!(render(TypedValue::Keyword(kw_from_string(":foo/bar".to_string()).expect("keyword"))), ":foo/bar");
+            assert_eq!(render(TypedValue::instant(1_500_000_000_000_001)), "#inst \"2017-07-14T02:40:00.000001Z\"");
+        }
+    }
+
+    #[test]
+    fn test_query_builder_bind_typed_value() {
+        unsafe {
+            let mut store = Store::open("").expect("store");
+            store.transact(r#"[
+                {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            let report = store.transact(r#"[[:db/add "a" :foo/n 7] [:db/add "b" :foo/n 8]]"#).expect("data");
+
+            let query = CString::new("[:find ?e . :in ?n :where [?e :foo/n ?n]]").expect("query");
+            let var = CString::new("?n").expect("var");
+            let query_builder = store_query(&mut store, query.as_ptr());
+            query_builder_bind_typed_value(query_builder, var.as_ptr(), typed_value_from_long(8));
+            let res = Box::from_raw(query_builder_execute_scalar(query_builder));
+            assert!(res.err.is_null());
+            let value = Box::from_raw(res.ok as *mut Option<TypedValue>);
+            assert_eq!(*value, Some(TypedValue::Ref(report.tempids["b"])));
+            query_builder_destroy(query_builder);
+        }
+    }
+
+    #[test]
+    fn test_query_builder_execute_count() {
+        unsafe {
+            let mut store = Store::open("").expect("store");
+            store.transact(r#"[
+                {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            store.transact(r#"[[:db/add "a" :foo/n 7] [:db/add "b" :foo/n 8]]"#).expect("data");
+
+            let query = CString::new("[:find ?e :where [?e :foo/n _]]").expect("query");
+            let query_builder = store_query(&mut store, query.as_ptr());
+            let res = Box::from_raw(query_builder_execute_count(query_builder));
+            assert!(res.err.is_null());
+            assert_eq!(*Box::from_raw(res.ok as *mut i64), 2);
+            query_builder_destroy(query_builder);
+
+            let query = CString::new("[:find (max ?n) . :where [_ :foo/n ?n]]").expect("query");
+            let query_builder = store_query(&mut store, query.as_ptr());
+            let res = query_builder_execute_count(query_builder);
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::Query as c_int);
+            extern_result_destroy(res);
+            query_builder_destroy(query_builder);
+        }
+    }
+
+    #[test]
+    fn test_query_builder_bind_from_result() {
+        unsafe {
+            let mut store = Store::open("").expect("store");
+            store.transact(r#"[
+                {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            let report = store.transact(r#"[[:db/add "a" :foo/n 7] [:db/add "b" :foo/n 8]]"#).expect("data");
+
+            let results = Box::into_raw(Box::new(vec![vec![TypedValue::Long(0), TypedValue::Long(8)]]));
+            let query = CString::new("[:find ?e . :in [?n ...] :where [?e :foo/n ?n]]").expect("query");
+            let var = CString::new("?n").expect("var");
+            let query_builder = store_query(&mut store, query.as_ptr());
+
+            for &column in &[-1, 2] {
+                let res = query_builder_bind_from_result(query_builder, var.as_ptr(), results, column);
+                assert_eq!(extern_result_err_code(res), ExternErrorCode::InvalidArgument as c_int);
+                extern_result_destroy(res);
+            }
+
+            let res = query_builder_bind_from_result(query_builder, var.as_ptr(), results, 1);
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::Ok as c_int);
+            extern_result_destroy(res);
+            let res = Box::from_raw(query_builder_execute_scalar(query_builder));
+            assert!(res.err.is_null());
+            let value = Box::from_raw(res.ok as *mut Option<TypedValue>);
+            assert_eq!(*value, Some(TypedValue::Ref(report.tempids["b"])));
+            query_builder_destroy(query_builder);
+            typed_value_result_set_destroy(results);
+        }
+    }
+
+    #[test]
+    fn test_query_builder_column_names() {
+        unsafe {
+            let mut store = Store::open("").expect("store");
+            let query = CString::new("[:find ?ident ?e :where [?e :db/ident ?ident]]").expect("query");
+            let query_builder = store_query(&mut store, query.as_ptr());
+            let res = Box::from_raw(query_builder_find_spec_kind(query_builder));
+            assert!(res.err.is_null());
+            assert_eq!(*Box::from_raw(res.ok as *mut FindSpecKind) as c_int, FindSpecKind::Rel as c_int);
+            let res = Box::from_raw(query_builder_column_names(query_builder));
+            assert!(res.err.is_null());
+            let names = res.ok as *mut StringIterator;
+            assert_eq!(c_char_to_string(string_iter_next(names)), "?ident");
+            assert_eq!(c_char_to_string(string_iter_next(names)), "?e");
+            assert!(string_iter_next(names).is_null());
+            string_iter_destroy(names);
+            query_builder_destroy(query_builder);
+
+            let query = CString::new("[:find ?e :where").expect("query");
+            let query_builder = store_query(&mut store, query.as_ptr());
+            let res = query_builder_find_spec_kind(query_builder);
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::Query as c_int);
+            extern_result_destroy(res);
+            let res = query_builder_column_names(query_builder);
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::Query as c_int);
+            extern_result_destroy(res);
+            query_builder_destroy(query_builder);
+        }
+    }
+
+    #[test]
+    fn test_empty_typed_value_vecs() {
+        unsafe {
+            for &values in &[typed_value_long_vec(std::ptr::null(), 0),
+                             typed_value_ref_vec(std::ptr::null(), 0),
+                             typed_value_string_vec(std::ptr::null(), 0)] {
+                assert!((*values).is_empty());
+                typed_value_list_destroy(values);
+            }
+        }
+    }
+
+    #[test]
+    fn test_kw_from_string_interns() {
+        let first = kw_from_string(":foo/interned".to_string()).expect("keyword");
+        let second = kw_from_string(":foo/interned".to_string()).expect("keyword");
+        assert!(Rc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_malformed_keywords_are_errors() {
+        assert_eq!(*kw_from_string(":foo/bar".to_string()).expect("keyword"), NamespacedKeyword::new("foo", "bar"));
+        for malformed in &["", ":", "foo/bar", ":foo", ":/bar", ":foo/", ":foo/bar/baz"] {
+            assert!(kw_from_string(malformed.to_string()).is_err(), "{} parsed", malformed);
+        }
+
+        let malformed = CString::new("foo").expect("no NULs");
+        unsafe {
+            let res = typed_value_from_keyword(malformed.as_ptr());
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::InvalidArgument as c_int);
+            extern_result_destroy(res);
+        }
+    }
+
+    #[test]
+    fn test_diverged_sync_has_its_own_code() {
+        let diverged: Error = ErrorKind::SyncError(SyncErrorKind::ServerDiverged(Uuid::nil(), Uuid::new_v4())).into();
+        assert_eq!(error_code(diverged.kind()), ExternErrorCode::SyncDiverged);
+        let cancelled: Error = ErrorKind::SyncError(SyncErrorKind::SyncCancelled).into();
+        assert_eq!(error_code(cancelled.kind()), ExternErrorCode::Sync);
+    }
+
+    #[test]
+    fn test_store_transact_each() {
+        let mut store = Store::open("").expect("store");
+        let transactions: Vec<CString> = vec![
+            r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#,
+            r#"[[:db/add "a" :foo/missing "x"]]"#,
+            r#"[[:db/add "a" :foo/name "Alice"]]"#,
+        ].into_iter().map(|t| CString::new(t).expect("no NULs")).collect();
+        let pointers: Vec<*const c_char> = transactions.iter().map(|t| t.as_ptr()).collect();
+        unsafe {
+            let results = store_transact_each(&mut store, pointers.as_ptr(), pointers.len());
+            let (first, second, third) = (&*results, &*results.offset(1), &*results.offset(2));
+            assert_eq!(first.err_code, ExternErrorCode::Ok as c_int);
+            assert!(second.ok.is_null());
+            assert!(!second.err.is_null());
+            // The failure in the middle doesn't undo the transaction after it.
+            let report = third.ok as *mut TxReport;
+            let a = (*report).tempids["a"];
+            assert_eq!(store.lookup_value_for_attribute(a, &kw_from_string(":foo/name".to_string()).expect("keyword")).expect("name"),
+                       Some(TypedValue::typed_string("Alice")));
+            tx_report_destroy(first.ok as *mut TxReport);
+            tx_report_destroy(report);
+            extern_result_list_destroy(results, pointers.len());
+
+            assert!(store_transact_each(&mut store, std::ptr::null(), 0).is_null());
+        }
+    }
+
+    #[test]
+    fn test_store_pull_no_entids() {
+        let mut store = Store::open("").expect("store");
+        unsafe {
+            let res = Box::from_raw(store_pull(&mut store, std::ptr::null(), 0, std::ptr::null(), 0));
+            assert!(res.err.is_null());
+            assert!((*(res.ok as *const Vec<Vec<Option<TypedValue>>>)).is_empty());
+            optional_typed_value_result_set_destroy(res.ok as *mut Vec<Vec<Option<TypedValue>>>);
+
+            let res = Box::from_raw(store_pull_attributes_many(&mut store, std::ptr::null(), 0, std::ptr::null(), 0));
+            assert!(res.err.is_null());
+            assert!((*(res.ok as *const AttributeValuesList)).is_empty());
+            attribute_values_list_destroy(res.ok as *mut AttributeValuesList);
+        }
+    }
+
+    #[test]
+    fn test_uuid_generate_v4() {
+        unsafe {
+            let first = c_char_to_string(uuid_generate_v4());
+            let second = c_char_to_string(uuid_generate_v4());
+            assert_ne!(first, second);
+
+            let uuid = Uuid::parse_str(&first).expect("uuid");
+            assert_eq!(uuid.get_version_num(), 4);
+            assert_eq!(uuid.hyphenated().to_string(), first);
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8_strings() {
+        let invalid = CString::new(vec![b'a', 0xff, b'b']).expect("no NULs");
+        assert_eq!(c_char_to_string(invalid.as_ptr()), "a\u{FFFD}b");
+        assert!(c_char_to_string_checked(invalid.as_ptr()).is_err());
+
+        let mut store = Store::open("").expect("store");
+        let transaction = CString::new(b"[[:db/add \"a\" :db/doc \"\xff\"]]".to_vec()).expect("no NULs");
+        unsafe {
+            let res = Box::from_raw(store_transact(&mut store, transaction.as_ptr()));
+            assert!(res.ok.is_null());
+            assert!(c_char_to_string(res.err).contains("invalid UTF-8"));
+            assert_eq!(res.err_code, ExternErrorCode::InvalidArgument as c_int);
+        }
+    }
+
+    #[test]
+    fn test_string_bytes_keep_nuls() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/text :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let e = store.transact(r#"[[:db/add "e" :foo/text "x"]]"#).expect("data").tempids["e"];
+        let attribute = CString::new(":foo/text").expect("attribute");
+        let text = b"before\0after";
+
+        unsafe {
+            let res = Box::from_raw(store_set_string_bytes_for_attribute_on_entid(&mut store, e, attribute.as_ptr(), text.as_ptr(), text.len()));
+            assert!(res.err.is_null());
+
+            let value = Box::into_raw(Box::new(store.lookup_value_for_attribute(e, &kw_from_string(":foo/text".to_string()).expect("keyword")).expect("value").expect("some")));
+            let res = Box::from_raw(typed_value_as_string_bytes(value));
+            assert!(res.err.is_null());
+            let string = res.ok as *mut ExternString;
+            assert_eq!(slice::from_raw_parts((*string).bytes, (*string).len), &text[..]);
+            extern_string_destroy(string);
+
+            let value = Box::from_raw(typed_value_from_string_bytes(text.as_ptr(), text.len()));
+            assert_eq!(*value, TypedValue::typed_string("before\0after"));
+
+            let query = CString::new("[:find ?t . :where [_ :foo/text ?t]]").expect("query");
+            let query_builder = store_query(&mut store, query.as_ptr());
+            let res = Box::from_raw(query_builder_execute_to_csv(query_builder));
+            assert!(res.err.is_null());
+            let csv = res.ok as *mut ExternString;
+            assert_eq!(slice::from_raw_parts((*csv).bytes, (*csv).len), &b"?t\r\nbefore\0after\r\n"[..]);
+            extern_string_destroy(csv);
+            query_builder_destroy(query_builder);
+
+            let query = CString::new("[:find ?t . :where").expect("query");
+            let query_builder = store_query(&mut store, query.as_ptr());
+            let res = query_builder_execute_to_csv(query_builder);
+            assert!((*res).ok.is_null());
+            assert!(!(*res).err.is_null());
+            extern_result_destroy(res);
+            query_builder_destroy(query_builder);
+        }
+    }
+
+    #[test]
+    fn test_string_destructors() {
+        unsafe {
+            rust_string_destroy(uuid_generate_v4() as *mut c_char);
+            rust_string_destroy(std::ptr::null_mut());
+
+            let value = Box::into_raw(Box::new(TypedValue::Long(1)));
+            let res = typed_value_as_string(value);
+            assert!(!extern_result_is_ok(res));
+            assert!(extern_result_ok(res).is_null());
+            assert_eq!(c_char_to_string(extern_result_err(res)), "expected :db.type/string, got :db.type/long");
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::ValueType as c_int);
+            extern_result_destroy(res);
+            extern_result_destroy(std::ptr::null_mut());
+            typed_value_destroy(value);
+
+            let s = c_char_from_rc(Rc::new("shared".to_string()));
+            assert_eq!(c_char_to_string(s), "shared");
+            rust_string_destroy(s);
+        }
+    }
+
+    #[test]
+    fn test_unique_constraint_violation_details() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :user/email :db/valueType :db.type/string :db/cardinality :db.cardinality/one
+             :db/unique :db.unique/value :db/index true}
+        ]"#).expect("schema");
+        store.transact(r#"[[:db/add "a" :user/email "a@b.com"]]"#).expect("first user");
+        let email = store.conn().current_schema().get_entid(&kw_from_string(":user/email".to_string()).expect("keyword")).expect("entid").0;
+
+        unsafe {
+            let transaction = CString::new(r#"[[:db/add "b" :user/email "a@b.com"]]"#).expect("transaction");
+            let res = store_transact(&mut store, transaction.as_ptr());
+            assert!(!extern_result_is_ok(res));
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::UniqueConstraintViolation as c_int);
+            let violation = extern_result_err_detail(res) as *const UniqueConstraintViolation;
+            assert!(!violation.is_null());
+            assert_eq!(unique_violation_attribute(violation), email);
+            let value = unique_violation_value(violation);
+            assert_eq!(*value, TypedValue::typed_string("a@b.com"));
+            typed_value_destroy(value);
+            extern_result_destroy(res);
+
+            // Other failures carry no detail.
+            let transaction = CString::new("[[:db/add \"b\" :user/unknown 1]]").expect("transaction");
+            let res = store_transact(&mut store, transaction.as_ptr());
+            assert!(!extern_result_is_ok(res));
+            assert!(extern_result_err_detail(res).is_null());
+            extern_result_destroy(res);
+        }
+    }
+
+    #[test]
+    fn test_transact_many_keeps_unique_constraint_violation() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :user/email :db/valueType :db.type/string :db/cardinality :db.cardinality/one
+             :db/unique :db.unique/value :db/index true}
+        ]"#).expect("schema");
+        store.transact(r#"[[:db/add "a" :user/email "a@b.com"]]"#).expect("first user");
+
+        let transactions: Vec<CString> = vec![
+            r#"[[:db/add "b" :user/email "b@b.com"]]"#,
+            r#"[[:db/add "c" :user/email "a@b.com"]]"#,
+        ].into_iter().map(|t| CString::new(t).expect("no NULs")).collect();
+        let pointers: Vec<*const c_char> = transactions.iter().map(|t| t.as_ptr()).collect();
+        unsafe {
+            let res = store_transact_many(&mut store, pointers.as_ptr(), pointers.len());
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::UniqueConstraintViolation as c_int);
+            assert!(c_char_to_string((*res).err).starts_with("transaction 1 of the batch failed"));
+            let violation = extern_result_err_detail(res) as *const UniqueConstraintViolation;
+            assert!(!violation.is_null());
+            let value = unique_violation_value(violation);
+            assert_eq!(*value, TypedValue::typed_string("a@b.com"));
+            typed_value_destroy(value);
+            extern_result_destroy(res);
+
+            let res = store_transact_many(&mut store, std::ptr::null(), 0);
+            assert_eq!(extern_result_err_code(res), ExternErrorCode::InvalidArgument as c_int);
+            extern_result_destroy(res);
+        }
+    }
+
+    #[test]
+    fn test_errors_carry_their_full_message() {
+        let mut store = Store::open("").expect("store");
+        let transaction = r#"[[:db/add "a" :foo/missing 1]]"#;
+        let expected = match store.transact(transaction) {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("expected an unknown attribute"),
+        };
+        let transaction = CString::new(transaction).expect("no NULs");
+        unsafe {
+            let res = store_transact(&mut store, transaction.as_ptr());
+            assert_eq!(c_char_to_string((*res).err), expected);
+            extern_result_destroy(res);
+        }
+    }
+
+    #[test]
+    fn test_typed_value_as_string_truncates_at_nul() {
+        unsafe {
+            let value = Box::into_raw(Box::new(TypedValue::typed_string("before\0after")));
+            let res = Box::from_raw(typed_value_as_string(value));
+            assert!(res.err.is_null());
+            assert_eq!(c_char_to_string(res.ok as *const c_char), "before");
+            rust_string_destroy(res.ok as *mut c_char);
+        }
+    }
+
+    #[test]
+    fn test_typed_value_as_reports_mismatch() {
+        unsafe {
+            let value = Box::into_raw(Box::new(TypedValue::typed_string("hello")));
+
+            // A mismatch leaves the value alive, so another accessor can still claim it.
+            let res = Box::from_raw(typed_value_as_long(value));
+            assert!(res.ok.is_null());
+            assert_eq!(c_char_to_string(res.err), "expected :db.type/long, got :db.type/string");
+
+            let res = Box::from_raw(typed_value_as_string(value));
+            assert!(res.err.is_null());
+            assert_eq!(c_char_to_string(res.ok as *const c_char), "hello");
+        }
+    }
+}
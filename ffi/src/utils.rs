@@ -10,6 +10,8 @@
 
 pub mod strings {
     use std;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
     use std::ffi::{
         CString,
         CStr
@@ -20,30 +22,150 @@ pub mod strings {
     use mentat::{
         NamespacedKeyword,
     };
+    use mentat::errors::{
+        Error,
+        ErrorKind,
+    };
+
+    use super::log;
 
+    /// Copies `cchar` into a `String`. Bytes that aren't valid UTF-8 are replaced with U+FFFD, and
+    /// a warning logged, rather than failing, so that text from a legacy source can't crash the
+    /// boundary. A keyword mangled this way names no attribute, so is still reported as unknown.
+    /// Use `c_char_to_string_checked` where a substitution would silently corrupt data.
     pub fn c_char_to_string(cchar: *const c_char) -> String {
         let c_str = unsafe { CStr::from_ptr(cchar) };
-        let r_str = c_str.to_str().unwrap_or("");
-        r_str.to_string()
+        match c_str.to_str() {
+            Ok(r_str) => r_str.to_string(),
+            Err(e) => {
+                log::d(&format!("Replacing invalid UTF-8 in string from caller: {}", e));
+                c_str.to_string_lossy().into_owned()
+            },
+        }
+    }
+
+    /// Like `c_char_to_string`, but fails on invalid UTF-8 instead of replacing it.
+    pub fn c_char_to_string_checked(cchar: *const c_char) -> Result<String, Error> {
+        let c_str = unsafe { CStr::from_ptr(cchar) };
+        c_str.to_str()
+             .map(|r_str| r_str.to_string())
+             .map_err(|e| ErrorKind::InvalidUtf8(e.to_string()).into())
+    }
+
+    /// Copies the `len` C strings at `array`. If `len` is zero, `array` may be null.
+    pub fn c_char_array_to_strings(array: *const *const c_char, len: usize) -> Vec<String> {
+        if len == 0 {
+            return vec![];
+        }
+        let slice = unsafe { std::slice::from_raw_parts(array, len) };
+        slice.iter().map(|s| c_char_to_string(*s)).collect()
     }
 
+    /// Copies `r_string` into a C string owned by the caller. A C string ends at its first NUL, so
+    /// anything from there on is dropped, and a warning logged, rather than panicking. Hand out an
+    /// `ExternString` where the whole string matters.
     pub fn string_to_c_char<T>(r_string: T) -> *mut c_char where T: Into<String> {
-        CString::new(r_string.into()).unwrap().into_raw()
+        match CString::new(r_string.into()) {
+            Ok(c_string) => c_string.into_raw(),
+            Err(e) => {
+                let nul_position = e.nul_position();
+                log::d(&format!("Truncating string for caller at NUL at byte {}", nul_position));
+                let mut bytes = e.into_vec();
+                bytes.truncate(nul_position);
+                unsafe { CString::from_vec_unchecked(bytes) }.into_raw()
+            },
+        }
     }
 
-    pub fn kw_from_string(mut keyword_string: String) -> NamespacedKeyword {
-        let attr_name = keyword_string.split_off(1);
-        let parts: Vec<&str> = attr_name.split("/").collect();
-        NamespacedKeyword::new(parts[0], parts[1])
+    /// A string handed to the caller as UTF-8 bytes and their length, so that, unlike a C string,
+    /// it can hold NULs. Free it with `extern_string_destroy`.
+    #[repr(C)]
+    #[derive(Debug)]
+    pub struct ExternString {
+        pub bytes: *mut u8,
+        pub len: usize,
     }
 
-    pub fn c_char_from_rc(rc_string: Rc<String>) -> *mut c_char {
-        if let Some(str_ptr) = unsafe { Rc::into_raw(rc_string).as_ref() } {
-            string_to_c_char(str_ptr.clone())
-        } else {
-            std::ptr::null_mut()
+    /// Copies the `len` bytes at `ptr` into a `String`. Unlike `c_char_to_string` this doesn't stop
+    /// at the first NUL, so text containing NULs isn't truncated. Invalid UTF-8 is replaced as
+    /// `c_char_to_string` replaces it.
+    pub fn string_from_bytes(ptr: *const u8, len: usize) -> String {
+        if len == 0 {
+            return String::new();
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+        match std::str::from_utf8(bytes) {
+            Ok(r_str) => r_str.to_string(),
+            Err(e) => {
+                log::d(&format!("Replacing invalid UTF-8 in string from caller: {}", e));
+                String::from_utf8_lossy(bytes).into_owned()
+            },
+        }
+    }
+
+    /// The counterpart of `string_from_bytes`: hands `r_string` to the caller whole, NULs and all.
+    pub fn string_to_bytes<T>(r_string: T) -> *mut ExternString where T: Into<String> {
+        let bytes = r_string.into().into_bytes().into_boxed_slice();
+        let len = bytes.len();
+        Box::into_raw(Box::new(ExternString {
+            bytes: Box::into_raw(bytes) as *mut u8,
+            len: len,
+        }))
+    }
+
+    /// The most keywords `KEYWORDS` holds. Keywords parsed once it's full aren't remembered.
+    const KEYWORD_CACHE_SIZE: usize = 1024;
+
+    thread_local! {
+        // Keywords already parsed by `kw_from_string` on this thread. A bulk load names the same
+        // few attributes thousands of times, so each is parsed once and then shared.
+        static KEYWORDS: RefCell<HashMap<String, Rc<NamespacedKeyword>>> = RefCell::new(HashMap::new());
+    }
+
+    /// Parses `keyword_string`, written as `:namespace/name`. Anything else is an
+    /// `InvalidKeyword` error, not a crash.
+    ///
+    /// Keywords are interned per thread, so naming the same keyword again costs a lookup and a
+    /// reference count rather than a parse and two allocations.
+    pub fn kw_from_string(keyword_string: String) -> Result<Rc<NamespacedKeyword>, Error> {
+        KEYWORDS.with(|keywords| {
+            let mut keywords = keywords.borrow_mut();
+            if let Some(keyword) = keywords.get(&keyword_string) {
+                return Ok(keyword.clone());
+            }
+            let keyword = match parse_kw(&keyword_string) {
+                Some(keyword) => Rc::new(keyword),
+                None => return Err(ErrorKind::InvalidKeyword(keyword_string).into()),
+            };
+            if keywords.len() < KEYWORD_CACHE_SIZE {
+                keywords.insert(keyword_string, keyword.clone());
+            }
+            Ok(keyword)
+        })
+    }
+
+    /// Parses each of `keyword_strings` with `kw_from_string`, failing on the first malformed one.
+    pub fn kws_from_strings(keyword_strings: Vec<String>) -> Result<Vec<NamespacedKeyword>, Error> {
+        keyword_strings.into_iter().map(|s| kw_from_string(s).map(|kw| (*kw).clone())).collect()
+    }
+
+    fn parse_kw(keyword_string: &str) -> Option<NamespacedKeyword> {
+        if !keyword_string.starts_with(':') {
+            return None;
+        }
+        let mut parts = keyword_string[1..].splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(namespace), Some(name)) if !namespace.is_empty() && !name.is_empty() && !name.contains('/') => {
+                Some(NamespacedKeyword::new(namespace, name))
+            },
+            _ => None,
         }
     }
+
+    /// Copies `rc_string` into a C string owned by the caller, releasing this reference to it.
+    pub fn c_char_from_rc(rc_string: Rc<String>) -> *mut c_char {
+        string_to_c_char(Rc::try_unwrap(rc_string).unwrap_or_else(|rc_string| (*rc_string).clone()))
+    }
 }
 
 pub mod log {
@@ -147,6 +147,7 @@ pub struct AlgebraicQuery {
     pub named_projection: BTreeSet<Variable>,
     pub order: Option<Vec<OrderBy>>,
     pub limit: Limit,
+    pub offset: Option<u64>,
     pub cc: clauses::ConjoiningClauses,
 }
 
@@ -300,6 +301,7 @@ pub fn algebrize_with_inputs(known: Known,
         named_projection: extra_vars,
         order: order,
         limit: limit,
+        offset: parsed.offset,
         cc: cc,
     };
 
@@ -539,6 +539,7 @@ def_parser!(Find, query, FindQuery, {
                 in_sources: BTreeSet::default(),    // TODO
                 in_vars: in_vars,
                 limit: limit,
+                offset: None,
                 order: order_clauses,
                 where_clauses: where_clauses.ok_or(combine::primitives::Error::Unexpected("expected :where".into()))?,
                 with: with_vars.unwrap_or(BTreeSet::default()),
@@ -1011,6 +1012,7 @@ mod test {
                 in_vars: Default::default(),
                 in_sources: Default::default(),
                 limit: Limit::None,
+                offset: None,
                 order: None,
             };
         assert_edn_parses_to!(Find::query,
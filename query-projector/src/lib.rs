@@ -312,6 +312,13 @@ impl TypedIndex {
 
 pub trait Projector {
     fn project<'stmt>(&self, rows: Rows<'stmt>) -> Result<QueryOutput>;
+
+    /// Like `project`, but reserves room for `capacity` results up front, avoiding repeated
+    /// reallocation when the caller knows roughly how many rows to expect.
+    fn project_with_capacity<'stmt>(&self, rows: Rows<'stmt>, _capacity: usize) -> Result<QueryOutput> {
+        self.project(rows)
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's>;
 }
 
@@ -508,8 +515,12 @@ impl RelProjector {
 }
 
 impl Projector for RelProjector {
-    fn project<'stmt>(&self, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
-        let mut out: Vec<Vec<TypedValue>> = vec![];
+    fn project<'stmt>(&self, rows: Rows<'stmt>) -> Result<QueryOutput> {
+        self.project_with_capacity(rows, 0)
+    }
+
+    fn project_with_capacity<'stmt>(&self, mut rows: Rows<'stmt>, capacity: usize) -> Result<QueryOutput> {
+        let mut out: Vec<Vec<TypedValue>> = Vec::with_capacity(capacity);
         while let Some(r) = rows.next() {
             let row = r?;
             let bindings = self.collect_bindings(row)?;
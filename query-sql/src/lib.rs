@@ -219,6 +219,7 @@ pub struct SelectQuery {
     pub group_by: Vec<GroupBy>,
     pub order: Vec<OrderBy>,
     pub limit: Limit,
+    pub offset: Option<u64>,
 }
 
 fn push_variable_column(qb: &mut QueryBuilder, vc: &VariableColumn) -> BuildQueryResult {
@@ -638,6 +639,15 @@ impl QueryFragment for SelectQuery {
             },
         }
 
+        if let Some(offset) = self.offset {
+            // SQLite only accepts OFFSET after a LIMIT; a negative limit means none.
+            if self.limit == Limit::None {
+                out.push_sql(" LIMIT -1");
+            }
+            out.push_sql(" OFFSET ");
+            out.push_sql(offset.to_string().as_str());
+        }
+
         Ok(())
     }
 }
@@ -815,6 +825,7 @@ mod tests {
             group_by: vec![],
             order: vec![],
             limit: Limit::None,
+            offset: None,
         };
 
         let SQLQuery { sql, args } = query.to_sql_query().unwrap();
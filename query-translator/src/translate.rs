@@ -322,7 +322,7 @@ fn table_for_computed(computed: ComputedTable, alias: TableAlias) -> TableOrSubq
                         // Each arm simply turns into a subquery.
                         // The SQL translation will stuff "UNION" between each arm.
                         let projection = Projection::Columns(columns);
-                        cc_to_select_query(projection, cc, false, vec![], None, Limit::None)
+                        cc_to_select_query(projection, cc, false, vec![], None, Limit::None, None)
                   }).collect(),
                 alias)
         },
@@ -347,6 +347,7 @@ fn empty_query() -> SelectQuery {
         constraints: vec![],
         order: vec![],
         limit: Limit::None,
+        offset: None,
     }
 }
 
@@ -358,7 +359,8 @@ fn cc_to_select_query(projection: Projection,
                       distinct: bool,
                       group_by: Vec<GroupBy>,
                       order: Option<Vec<OrderBy>>,
-                      limit: Limit) -> SelectQuery {
+                      limit: Limit,
+                      offset: Option<u64>) -> SelectQuery {
     let from = if cc.from.is_empty() {
         FromClause::Nothing
     } else {
@@ -399,6 +401,7 @@ fn cc_to_select_query(projection: Projection,
                        .collect(),
         order: order,
         limit: limit,
+        offset: offset,
     }
 }
 
@@ -409,7 +412,7 @@ pub fn cc_to_exists(cc: ConjoiningClauses) -> SelectQuery {
         // In this case we can produce a very simple query that returns no results.
         empty_query()
     } else {
-        cc_to_select_query(Projection::One, cc, false, vec![], None, Limit::None)
+        cc_to_select_query(Projection::One, cc, false, vec![], None, Limit::None, None)
     }
 }
 
@@ -425,6 +428,7 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
     inner.order = vec![];
     let limit = inner.limit;
     inner.limit = Limit::None;
+    let offset = inner.offset.take();
 
     SelectQuery {
         distinct: outer_distinct,
@@ -434,6 +438,7 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
         group_by: group_by,
         order: order_by,
         limit: limit,
+        offset: offset,
     }
 }
 
@@ -460,12 +465,13 @@ pub fn query_to_select(query: AlgebraicQuery) -> Result<ProjectedSelect> {
                                                        distinct,
                                                        group_by_cols,
                                                        query.order,
-                                                       query.limit);
+                                                       query.limit,
+                                                       query.offset);
                         let outer = re_project(inner, sql_projection);
                         outer
                     },
                     None => {
-                        cc_to_select_query(sql_projection, query.cc, distinct, group_by_cols, query.order, query.limit)
+                        cc_to_select_query(sql_projection, query.cc, distinct, group_by_cols, query.order, query.limit, query.offset)
                     },
                 },
                 projector: datalog_projector,
@@ -196,6 +196,27 @@ fn test_limit() {
     assert_eq!(args, vec![make_arg("$v0", "yyy")]);
 }
 
+#[test]
+fn test_offset() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    // There's no syntax for an offset, so set it on the parsed query.
+    let mut parsed = parse_find_string(r#"[:find ?x :where [?x :foo/bar "yyy"] :limit 5]"#).expect("parse to succeed");
+    parsed.offset = Some(10);
+    let algebrized = algebrize_with_inputs(known, parsed, 0, QueryInputs::default()).expect("algebrize to succeed");
+    let SQLQuery { sql, args } = query_to_sql(query_to_select(algebrized).expect("translate to succeed"));
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT 5 OFFSET 10");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+
+    // SQLite needs a LIMIT to accompany an OFFSET.
+    let mut parsed = parse_find_string(r#"[:find ?x :where [?x :foo/bar "yyy"]]"#).expect("parse to succeed");
+    parsed.offset = Some(10);
+    let algebrized = algebrize_with_inputs(known, parsed, 0, QueryInputs::default()).expect("algebrize to succeed");
+    let SQLQuery { sql, .. } = query_to_sql(query_to_select(algebrized).expect("translate to succeed"));
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT -1 OFFSET 10");
+}
+
 #[test]
 fn test_unbound_variable_limit() {
     let schema = prepopulated_schema();
@@ -461,13 +461,13 @@ pub struct Pull {
 }
 */
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Aggregate {
     pub func: QueryFunction,
     pub args: Vec<FnArg>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Element {
     Variable(Variable),
     Aggregate(Aggregate),
@@ -556,7 +556,7 @@ pub enum Limit {
 /// # }
 /// ```
 ///
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum FindSpec {
     /// Returns an array of arrays.
     FindRel(Vec<Element>),
@@ -628,6 +628,14 @@ impl FindSpec {
             &FindRel(ref v)    => Box::new(v.iter()),
         }
     }
+
+    /// Returns the index of the column that projects `var`, if any. Aggregates of `var` don't count.
+    pub fn column_index(&self, var: &Variable) -> Option<usize> {
+        self.columns().position(|e| match e {
+            &Element::Variable(ref v) | &Element::Corresponding(ref v) => v == var,
+            &Element::Aggregate(_) => false,
+        })
+    }
 }
 
 // Datomic accepts variable or placeholder.  DataScript accepts recursive bindings.  Mentat sticks
@@ -874,7 +882,7 @@ pub enum WhereClause {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct FindQuery {
     pub find_spec: FindSpec,
     pub default_source: SrcVar,
@@ -882,6 +890,9 @@ pub struct FindQuery {
     pub in_vars: BTreeSet<Variable>,
     pub in_sources: BTreeSet<SrcVar>,
     pub limit: Limit,
+    /// How many rows to skip before the first one returned. This has no query syntax; callers
+    /// set it on a parsed query.
+    pub offset: Option<u64>,
     pub where_clauses: Vec<WhereClause>,
     pub order: Option<Vec<Order>>,
     // TODO: in_rules;
@@ -896,6 +907,7 @@ impl FindQuery {
             in_vars: BTreeSet::default(),
             in_sources: BTreeSet::default(),
             limit: Limit::None,
+            offset: None,
             where_clauses: where_clauses,
             order: None,
         }
@@ -10,16 +10,27 @@
 
 #![allow(dead_code)]
 
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
 use std::fs::{
+    self,
     File,
 };
 
 use std::io::{
+    BufRead,
+    BufReader,
+    BufWriter,
     Read,
+    Write,
 };
 
 use std::path::{
     Path,
+    PathBuf,
 };
 
 use std::sync::{
@@ -27,25 +38,41 @@ use std::sync::{
     Mutex,
 };
 
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+
+use std::time::{
+    Duration,
+};
+
 use rusqlite;
 use rusqlite::{
     TransactionBehavior,
 };
 
+use serde_json;
+
 use edn;
 
 use mentat_core::{
     Attribute,
+    DateTime,
     Entid,
+    FromMicros,
     HasSchema,
     KnownEntid,
     NamespacedKeyword,
     Schema,
+    ToMicros,
     TypedValue,
+    Utc,
     ValueType,
 };
 
 use mentat_core::intern_set::InternSet;
+use mentat_core::util::Either;
 
 use mentat_db::cache::{
     InProgressCacheTransactWatcher,
@@ -54,15 +81,24 @@ use mentat_db::cache::{
 };
 
 use mentat_db::db;
+use mentat_db::db::TypedSQLValue;
+use mentat_db::entids::DB_TX_INSTANT;
 use mentat_db::{
     transact,
     transact_terms,
+    AttributeSet,
+    DB_SCHEMA_CORE,
+    TX0,
+    USER0,
     InProgressObserverTransactWatcher,
     PartitionMap,
+    SchemaObserver,
     TransactWatcher,
     TxObservationService,
     TxObserver,
     TxReport,
+    ValueChange,
+    ValueObserver,
 };
 
 use mentat_db::internal_types::TermWithTempIds;
@@ -76,10 +112,24 @@ use mentat_tx::entities::{
 
 use mentat_tx_parser;
 
-use mentat_tolstoy::Syncer;
+use mentat_query::{
+    FindQuery,
+    Variable,
+};
+
+use mentat_tolstoy::{
+    SyncPhase,
+    SyncStatus,
+    Syncer,
+};
 
 use uuid::Uuid;
 
+use vocabulary::{
+    HasVocabularies,
+    Version,
+};
+
 use entity_builder::{
     BuildTerms,
     InProgressBuilder,
@@ -94,14 +144,35 @@ use query::{
     QueryExplanation,
     QueryInputs,
     QueryOutput,
+    QueryPlan,
+    QueryTiming,
     lookup_value_for_attribute,
     lookup_values_for_attribute,
     q_explain,
+    q_count_parsed,
     q_once,
+    q_once_parsed,
+    q_once_parsed_hinted,
+    q_once_parsed_timed,
+    q_plan,
     q_prepare,
     q_uncached,
 };
 
+/// `datoms AS d`, joined to `fulltext_values AS f` so that `coalesce(f.text, d.v)` is a value
+/// `TypedValue::from_sql_value_pair` accepts: fulltext values are stored as rowids into
+/// `fulltext_values`, and this resolves them to their text.
+const DATOMS_WITH_FULLTEXT: &'static str =
+    "datoms AS d \
+     LEFT JOIN fulltext_values AS f \
+     ON d.value_type_tag = 10 AND typeof(d.v) = 'integer' AND f.rowid = d.v";
+
+/// Like `DATOMS_WITH_FULLTEXT`, but for `transactions AS t`: select `coalesce(f.text, t.v)`.
+const TRANSACTIONS_WITH_FULLTEXT: &'static str =
+    "transactions AS t \
+     LEFT JOIN fulltext_values AS f \
+     ON t.value_type_tag = 10 AND typeof(t.v) = 'integer' AND f.rowid = t.v";
+
 /// Connection metadata required to query from, or apply transactions to, a Mentat store.
 ///
 /// Owned data for the volatile parts (generation and partition map), and `Arc` for the infrequently
@@ -153,11 +224,230 @@ pub struct Conn {
     tx_observer_service: Mutex<TxObservationService>,
 }
 
+/// The SQLite journal modes a store can be switched between. See `Store::set_journal_mode`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum JournalMode {
+    /// A rollback journal deleted after each transaction; best when several processes share
+    /// the file.
+    Delete,
+    /// A rollback journal kept in memory. A crash mid-transaction can corrupt the database.
+    Memory,
+    /// Write-ahead logging, the default; best for write-heavy use from a single process.
+    Wal,
+}
+
+impl JournalMode {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            JournalMode::Delete => "delete",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+        }
+    }
+
+    fn from_str(mode: &str) -> Option<JournalMode> {
+        match mode.to_lowercase().as_str() {
+            "delete" => Some(JournalMode::Delete),
+            "memory" => Some(JournalMode::Memory),
+            "wal" => Some(JournalMode::Wal),
+            _ => None,
+        }
+    }
+}
+
+/// A read-only handle onto a store's database, with its own SQLite connection, that can be moved
+/// to another thread and queried there while the owning `Store` keeps writing. Obtain one with
+/// `Store::reader`.
+///
+/// Each query runs in its own implicit read transaction: it sees everything committed before it
+/// started and nothing the writer does while it runs, nor any uncommitted writes. Two queries may
+/// see different data. The schema is the one current when the reader was made; make a new reader
+/// to see attributes added since. The attribute cache isn't consulted.
+pub struct StoreReader {
+    sqlite: rusqlite::Connection,
+    schema: Arc<Schema>,
+}
+
+impl StoreReader {
+    pub fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        q_once(&self.sqlite, Known::for_schema(&*self.schema), query, inputs)
+    }
+
+    pub fn lookup_value_for_attribute(&self, entity: Entid, attribute: &NamespacedKeyword) -> Result<Option<TypedValue>> {
+        lookup_value_for_attribute(&self.sqlite, Known::for_schema(&*self.schema), entity, attribute)
+    }
+}
+
+/// The SQLite handle of a `QueryHandle`'s connection, for `sqlite3_interrupt`, which may be
+/// called from any thread.
+struct InterruptibleConnection(*mut rusqlite::ffi::sqlite3);
+
+unsafe impl Send for InterruptibleConnection {}
+
+/// Cancels the query of a `QueryHandle` from any thread. See `QueryHandle::interrupt`.
+pub struct QueryInterrupt {
+    cancelled: AtomicBool,
+    // Cleared when the handle, and so the connection, is dropped.
+    connection: Mutex<Option<InterruptibleConnection>>,
+}
+
+impl QueryInterrupt {
+    /// Ask the query to stop. A running statement is abandoned at SQLite's next opportunity; a
+    /// query not yet started won't start. Either way it fails with `QueryCancelled`. Cancelling a
+    /// query that has already finished does nothing.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(InterruptibleConnection(db)) = *self.connection.lock().unwrap() {
+            unsafe { rusqlite::ffi::sqlite3_interrupt(db) };
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// A query, on its own `StoreReader`, that another thread can cancel while it runs. See
+/// `Store::q_cancellable`.
+pub struct QueryHandle {
+    reader: StoreReader,
+    query: String,
+    inputs: Option<QueryInputs>,
+    interrupt: Arc<QueryInterrupt>,
+}
+
+impl QueryHandle {
+    /// A token with which to cancel this query from another thread.
+    pub fn interrupt(&self) -> Arc<QueryInterrupt> {
+        self.interrupt.clone()
+    }
+
+    pub fn cancel(&self) {
+        self.interrupt.cancel();
+    }
+
+    /// Run the query, blocking until it finishes or is cancelled.
+    pub fn run(&self) -> Result<QueryOutput> {
+        if self.interrupt.is_cancelled() {
+            bail!(ErrorKind::QueryCancelled);
+        }
+        match self.reader.q_once(self.query.as_str(), self.inputs.clone()) {
+            Err(_) if self.interrupt.is_cancelled() => bail!(ErrorKind::QueryCancelled),
+            result => result,
+        }
+    }
+}
+
+impl Drop for QueryHandle {
+    fn drop(&mut self) {
+        *self.interrupt.connection.lock().unwrap() = None;
+    }
+}
+
+/// Cancels the sync of a `SyncHandle` from any thread. See `SyncHandle::interrupt`.
+pub struct SyncInterrupt {
+    cancelled: AtomicBool,
+}
+
+impl SyncInterrupt {
+    /// Ask the sync to stop at its next safe point. It then fails, having committed nothing
+    /// locally. Cancelling a sync that has already finished does nothing.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// A sync, on its own connection to the store's database, that another thread can cancel while
+/// it runs. See `Store::sync_cancellable`.
+pub struct SyncHandle {
+    sqlite: rusqlite::Connection,
+    server_uri: String,
+    user_uuid: Uuid,
+    interrupt: Arc<SyncInterrupt>,
+}
+
+impl SyncHandle {
+    /// A token with which to cancel this sync from another thread.
+    pub fn interrupt(&self) -> Arc<SyncInterrupt> {
+        self.interrupt.clone()
+    }
+
+    pub fn cancel(&self) {
+        self.interrupt.cancel();
+    }
+
+    /// Run the sync, blocking until it finishes or is cancelled.
+    pub fn run(&mut self) -> Result<()> {
+        Ok(Syncer::flow_cancellable(&mut self.sqlite, &self.server_uri, &self.user_uuid, &mut |_, _, _| {}, &self.interrupt.cancelled)?)
+    }
+}
+
+/// Problems found by `Store::integrity_check`. An empty report means none were found.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IntegrityReport {
+    /// Messages from SQLite's `PRAGMA integrity_check`, other than its `ok`.
+    pub sqlite_errors: Vec<String>,
+    /// `[e a v]` datoms whose ref value `v` names an entity with no datoms of its own.
+    pub dangling_refs: Vec<(Entid, Entid, Entid)>,
+    /// Attributes used in datoms that the schema doesn't define.
+    pub unknown_attributes: Vec<Entid>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.sqlite_errors.is_empty() && self.dangling_refs.is_empty() && self.unknown_attributes.is_empty()
+    }
+
+    /// One human-readable line per problem.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self.sqlite_errors.iter().map(|e| format!("sqlite: {}", e)).collect();
+        problems.extend(self.dangling_refs.iter().map(|&(e, a, v)| format!("dangling ref: [{} {} {}]", e, a, v)));
+        problems.extend(self.unknown_attributes.iter().map(|a| format!("unknown attribute: {}", a)));
+        problems
+    }
+}
+
+/// Where a store lives on disk, and how much space it takes. See `Store::file_info`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoreFileInfo {
+    /// The resolved path of the main database file, or `None` for an in-memory store.
+    pub path: Option<PathBuf>,
+    /// Sizes in bytes of the main database file and of its `-wal`, `-shm`, and `-journal`
+    /// companions. Files that don't exist count as zero.
+    pub db_size: u64,
+    pub wal_size: u64,
+    pub shm_size: u64,
+    pub journal_size: u64,
+}
+
+/// One committed transaction, as read back from the log by `Store::transactions_since`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedTransaction {
+    pub tx: Entid,
+    /// The transaction's `:db/txInstant`.
+    pub instant: DateTime<Utc>,
+    /// `(e, a, v, added)` for each datom the transaction asserted or retracted, its own
+    /// `:db/txInstant` included.
+    pub datoms: Vec<(Entid, Entid, TypedValue, bool)>,
+}
+
 /// A convenience wrapper around a single SQLite connection and a Conn. This is suitable
 /// for applications that don't require complex connection management.
 pub struct Store {
     conn: Conn,
     sqlite: rusqlite::Connection,
+
+    /// Read-time aliases from old attribute keywords to their current names.
+    /// See `Store::alias_attribute`.
+    attribute_aliases: BTreeMap<NamespacedKeyword, NamespacedKeyword>,
+
+    /// Consulted before `Store::transact` commits. See `Store::set_transaction_validator`.
+    transaction_validator: Option<Box<Fn(&TxReport) -> bool + Send>>,
 }
 
 impl Store {
@@ -173,6 +463,8 @@ impl Store {
         Ok(Store {
             conn: conn,
             sqlite: connection,
+            attribute_aliases: BTreeMap::new(),
+            transaction_validator: None,
         })
     }
 
@@ -182,15 +474,182 @@ impl Store {
         Ok(Store {
             conn: conn,
             sqlite: connection,
+            attribute_aliases: BTreeMap::new(),
+            transaction_validator: None,
         })
     }
 
+    /// Open the store at `path`. If the file there fails SQLite's integrity check, first move it,
+    /// and any `-wal`, `-shm`, or `-journal` companions, to `backup_path` (plus the same suffix),
+    /// then open a fresh store in its place. The returned flag says whether that happened.
+    ///
+    /// Use this only when starting over (and perhaps re-syncing) beats failing to open:
+    /// `Store::open` never discards anything. A database that can't be checked, because it's
+    /// locked or can't be read, is left where it is and the error returned.
+    pub fn open_or_recover(path: &str, backup_path: &str) -> Result<(Store, bool)> {
+        let recovered = !path.is_empty() && Path::new(path).exists() && !database_is_intact(path)?;
+        if recovered {
+            fs::rename(path, backup_path)?;
+            for suffix in &["-wal", "-shm", "-journal"] {
+                let companion = format!("{}{}", path, suffix);
+                if Path::new(&companion).exists() {
+                    fs::rename(&companion, format!("{}{}", backup_path, suffix))?;
+                }
+            }
+        }
+        Ok((Store::open(path)?, recovered))
+    }
+
+    /// Open the store at `path`, refusing with `UnsupportedVocabularyVersion` if any vocabulary
+    /// other than the core schema is installed at a version outside `min..=max`. This guards
+    /// against a build opening data written by a newer — or much older — build.
+    pub fn open_with_version(path: &str, min: Version, max: Version) -> Result<Store> {
+        let mut store = Store::open(path)?;
+        {
+            let vocabularies = store.begin_read()?.read_vocabularies()?;
+            for (name, vocabulary) in vocabularies.iter() {
+                if name == &kw!(:db.schema/core) {
+                    continue;
+                }
+                if vocabulary.version < min || vocabulary.version > max {
+                    bail!(ErrorKind::UnsupportedVocabularyVersion(name.to_string(), vocabulary.version, min, max));
+                }
+            }
+        }
+        Ok(store)
+    }
+
     pub fn transact(&mut self, transaction: &str) -> Result<TxReport> {
-        let mut ip = self.begin_transaction()?;
+        let mut ip = self.conn.begin_transaction(&mut self.sqlite)?;
         let report = ip.transact(transaction)?;
+        if let Some(ref validator) = self.transaction_validator {
+            if !validator(&report) {
+                ip.rollback()?;
+                bail!(ErrorKind::TransactionVetoed);
+            }
+        }
         ip.commit()?;
         Ok(report)
     }
+
+    /// Transact each of `transactions` in order, as separate Mentat transactions that are
+    /// committed together, and return the report of the last. If any fails, or the validator
+    /// rejects any, none are committed and the error is `BatchTransactionFailed`, naming the
+    /// index of the failing transaction and holding its error. An empty batch is an
+    /// `EmptyTransactionBatch` error, as there's no report to return.
+    pub fn transact_many<T>(&mut self, transactions: &[T]) -> Result<TxReport> where T: AsRef<str> {
+        self.transact_all(transactions.iter().map(|t| Ok(t)))
+    }
+
+    /// `transact_many` over transactions that are produced, or read, one at a time. If one can't
+    /// be produced, nothing is committed and that error is returned.
+    fn transact_all<I, T>(&mut self, transactions: I) -> Result<TxReport> where I: Iterator<Item=Result<T>>, T: AsRef<str> {
+        let mut ip = self.conn.begin_transaction(&mut self.sqlite)?;
+        let mut last = None;
+        for (index, transaction) in transactions.enumerate() {
+            let transaction = match transaction {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    ip.rollback()?;
+                    return Err(e);
+                },
+            };
+            let report = match ip.transact(transaction.as_ref()) {
+                Ok(report) => report,
+                Err(Error(kind, _)) => {
+                    ip.rollback()?;
+                    bail!(ErrorKind::BatchTransactionFailed(index, Box::new(kind)));
+                },
+            };
+            if let Some(ref validator) = self.transaction_validator {
+                if !validator(&report) {
+                    ip.rollback()?;
+                    bail!(ErrorKind::BatchTransactionFailed(index, Box::new(ErrorKind::TransactionVetoed)));
+                }
+            }
+            last = Some(report);
+        }
+        match last {
+            Some(report) => {
+                ip.commit()?;
+                Ok(report)
+            },
+            None => {
+                ip.rollback()?;
+                bail!(ErrorKind::EmptyTransactionBatch)
+            },
+        }
+    }
+
+    /// Check that `transaction` would transact, parsing it and applying it against the schema
+    /// exactly as `transact` does, and then roll it back. Nothing is committed and no observer
+    /// is notified, whatever the outcome. The error is the one `transact` would have returned.
+    pub fn validate_transaction(&mut self, transaction: &str) -> Result<()> {
+        let mut ip = self.conn.begin_transaction(&mut self.sqlite)?;
+        let report = ip.transact(transaction)?;
+        let vetoed = self.transaction_validator.as_ref().map_or(false, |validator| !validator(&report));
+        ip.rollback()?;
+        if vetoed {
+            bail!(ErrorKind::TransactionVetoed);
+        }
+        Ok(())
+    }
+
+    /// Transact the EDN read from `reader`, a sequence of transactions such as `[...] [...]`.
+    /// Each is read, parsed and applied before the next is read, so only one is in memory at a
+    /// time; a large payload should therefore be split into several transactions, as a single
+    /// one is read whole. Tempids are scoped to the transaction that names them. All are
+    /// committed together, as by `transact_many`, and the report of the last is returned.
+    pub fn transact_reader<R>(&mut self, reader: R) -> Result<TxReport> where R: Read {
+        self.transact_all(EdnForms::new(BufReader::new(reader)))
+    }
+
+    /// Transact the file at `path`, a sequence of EDN transactions such as `[...] [...]`, without
+    /// reading it all into memory. Transactions are committed `IMPORT_FORMS_PER_COMMIT` at a time,
+    /// and the number applied is returned. If one fails, the error is `BatchTransactionFailed`
+    /// naming its index in the file; those in earlier commits stay applied.
+    pub fn import_edn_file<P>(&mut self, path: P) -> Result<usize> where P: AsRef<Path> {
+        self.import_edn_forms(BufReader::new(File::open(path)?), IMPORT_FORMS_PER_COMMIT)
+    }
+
+    /// Like `import_edn_file`, but reads from `reader` and commits every `forms_per_commit`
+    /// transactions.
+    pub fn import_edn_forms<R>(&mut self, reader: R, forms_per_commit: usize) -> Result<usize> where R: BufRead {
+        let forms_per_commit = forms_per_commit.max(1);
+        let mut applied = 0;
+        let mut batch = Vec::with_capacity(forms_per_commit);
+        for form in EdnForms::new(reader) {
+            batch.push(form?);
+            if batch.len() == forms_per_commit {
+                applied += self.transact_batch_from(&batch, applied)?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            applied += self.transact_batch_from(&batch, applied)?;
+        }
+        Ok(applied)
+    }
+
+    /// `transact_many`, reporting failures by their index counted from `first_index`.
+    fn transact_batch_from(&mut self, batch: &[String], first_index: usize) -> Result<usize> {
+        match self.transact_many(batch) {
+            Ok(_) => Ok(batch.len()),
+            Err(Error(ErrorKind::BatchTransactionFailed(index, cause), _)) => {
+                bail!(ErrorKind::BatchTransactionFailed(first_index + index, cause))
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Run `validator` on the report of each `Store::transact` after the transaction has been
+    /// applied but before it is committed. If it returns false, the transaction is rolled back and
+    /// `transact` fails with `TransactionVetoed`. The validator must not write to the store: the
+    /// write lock is held while it runs. Other ways of writing, such as `begin_transaction`,
+    /// don't consult the validator.
+    pub fn set_transaction_validator<F>(&mut self, validator: Option<F>) where F: Fn(&TxReport) -> bool + Send + 'static {
+        self.transaction_validator = validator.map(|v| Box::new(v) as Box<Fn(&TxReport) -> bool + Send>);
+    }
 }
 
 pub trait Queryable {
@@ -208,6 +667,13 @@ pub trait Queryable {
 
 pub trait Syncable {
     fn sync(&mut self, server_uri: &String, user_uuid: &String) -> Result<()>;
+
+    /// Like `sync`, but calls `progress` with each phase, how much of it is done, and how much
+    /// there is in total, as the sync proceeds.
+    fn sync_with_progress(&mut self, server_uri: &String, user_uuid: &String, progress: &mut FnMut(SyncPhase, usize, usize)) -> Result<()>;
+
+    /// Report the sync metadata recorded by previous syncs, without contacting the server.
+    fn sync_status(&mut self) -> Result<SyncStatus>;
 }
 
 /// Represents an in-progress, not yet committed, set of changes to the store.
@@ -224,6 +690,8 @@ pub struct InProgress<'a, 'c> {
     use_caching: bool,
     tx_observer: &'a Mutex<TxObservationService>,
     tx_observer_watcher: InProgressObserverTransactWatcher,
+    value_attributes: AttributeSet,
+    value_changes: BTreeMap<Entid, Vec<ValueChange>>,
 }
 
 /// Represents an in-progress set of reads to the store. Just like `InProgress`,
@@ -381,11 +849,136 @@ impl<'a, 'c> HasSchema for InProgress<'a, 'c> {
 }
 
 
+/// Whether SQLite can open the database at `path` and finds nothing wrong with it. Only SQLite
+/// saying the file is corrupt, or not a database at all, counts as damage: any other failure,
+/// such as the file being locked or unreadable, is returned as an error.
+fn database_is_intact(path: &str) -> Result<bool> {
+    let check = rusqlite::Connection::open(path)
+        .and_then(|c| c.query_row("PRAGMA quick_check", &[], |row| row.get::<_, String>(0)));
+    match check {
+        Ok(result) => Ok(result == "ok"),
+        Err(rusqlite::Error::SqliteFailure(rusqlite::ffi::Error { code: rusqlite::ErrorCode::DatabaseCorrupt, .. }, _)) |
+        Err(rusqlite::Error::SqliteFailure(rusqlite::ffi::Error { code: rusqlite::ErrorCode::NotADatabase, .. }, _)) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The idents of attributes that were added, altered, or removed between `old` and `new`.
+fn changed_attributes(old: &Schema, new: &Schema) -> BTreeSet<NamespacedKeyword> {
+    let mut changed = BTreeSet::new();
+    for (entid, attribute) in new.attribute_map.iter() {
+        let ident = new.entid_map.get(entid);
+        if old.attribute_map.get(entid) != Some(attribute) || old.entid_map.get(entid) != ident {
+            changed.extend(ident.cloned());
+        }
+    }
+    for entid in old.attribute_map.keys() {
+        if !new.attribute_map.contains_key(entid) {
+            changed.extend(old.entid_map.get(entid).cloned());
+        }
+    }
+    changed
+}
+
+impl<'a, 'c> InProgressRead<'a, 'c> {
+    /// Run a query that has already been parsed. See `InProgress::q_once_parsed`.
+    pub fn q_once_parsed<T>(&self, query: FindQuery, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        self.0.q_once_parsed(query, inputs)
+    }
+
+    /// See `InProgress::q_once_parsed_hinted`.
+    pub fn q_once_parsed_hinted<T>(&self, query: FindQuery, inputs: T, expected_rows: usize) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        self.0.q_once_parsed_hinted(query, inputs, expected_rows)
+    }
+
+    /// See `InProgress::q_once_parsed_timed`.
+    pub fn q_once_parsed_timed<T>(&self, query: FindQuery, inputs: T, expected_rows: usize, timing: &mut QueryTiming) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        self.0.q_once_parsed_timed(query, inputs, expected_rows, timing)
+    }
+
+    /// See `InProgress::q_count_parsed`.
+    pub fn q_count_parsed<T>(&self, query: FindQuery, inputs: T) -> Result<i64>
+        where T: Into<Option<QueryInputs>> {
+        self.0.q_count_parsed(query, inputs)
+    }
+
+    /// See `InProgress::q_plan_parsed`.
+    pub fn q_plan_parsed<T>(&self, query: FindQuery, inputs: T) -> Result<QueryPlan>
+        where T: Into<Option<QueryInputs>> {
+        self.0.q_plan_parsed(query, inputs)
+    }
+
+    /// See `InProgress::run_plan`.
+    pub fn run_plan(&self, plan: &QueryPlan) -> Result<QueryOutput> {
+        self.0.run_plan(plan)
+    }
+}
+
 impl<'a, 'c> InProgress<'a, 'c> {
     pub fn builder(self) -> InProgressBuilder<'a, 'c> {
         InProgressBuilder::new(self)
     }
 
+    /// Run a query that has already been parsed, and perhaps rewritten, by the caller.
+    pub fn q_once_parsed<T>(&self, query: FindQuery, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        if self.use_caching {
+            let known = Known::new(&self.schema, Some(&self.cache));
+            q_once_parsed(&*(self.transaction), known, query, inputs)
+        } else {
+            q_once_parsed(&*(self.transaction), Known::for_schema(&self.schema), query, inputs)
+        }
+    }
+
+    /// Like `q_once_parsed`, but reserves room for `expected_rows` rows of a relation result.
+    pub fn q_once_parsed_hinted<T>(&self, query: FindQuery, inputs: T, expected_rows: usize) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        if self.use_caching {
+            let known = Known::new(&self.schema, Some(&self.cache));
+            q_once_parsed_hinted(&*(self.transaction), known, query, inputs, expected_rows)
+        } else {
+            q_once_parsed_hinted(&*(self.transaction), Known::for_schema(&self.schema), query, inputs, expected_rows)
+        }
+    }
+
+    /// Like `q_once_parsed_hinted`, but records how long each phase of the query took in `timing`.
+    pub fn q_once_parsed_timed<T>(&self, query: FindQuery, inputs: T, expected_rows: usize, timing: &mut QueryTiming) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        if self.use_caching {
+            let known = Known::new(&self.schema, Some(&self.cache));
+            q_once_parsed_timed(&*(self.transaction), known, query, inputs, expected_rows, timing)
+        } else {
+            q_once_parsed_timed(&*(self.transaction), Known::for_schema(&self.schema), query, inputs, expected_rows, timing)
+        }
+    }
+
+    /// Count the rows a parsed query would return, without fetching them.
+    pub fn q_count_parsed<T>(&self, query: FindQuery, inputs: T) -> Result<i64>
+        where T: Into<Option<QueryInputs>> {
+        if self.use_caching {
+            let known = Known::new(&self.schema, Some(&self.cache));
+            q_count_parsed(&*(self.transaction), known, query, inputs)
+        } else {
+            q_count_parsed(&*(self.transaction), Known::for_schema(&self.schema), query, inputs)
+        }
+    }
+
+    /// Algebrize and translate a parsed query into a plan that `run_plan` can run repeatedly.
+    /// The plan depends only on the schema and `inputs`, not on the attribute caches, whose values
+    /// would be stale by the time a later run used them.
+    pub fn q_plan_parsed<T>(&self, query: FindQuery, inputs: T) -> Result<QueryPlan>
+        where T: Into<Option<QueryInputs>> {
+        q_plan(Known::for_schema(&self.schema), query, inputs)
+    }
+
+    /// Run a plan made by `q_plan_parsed` against the same schema.
+    pub fn run_plan(&self, plan: &QueryPlan) -> Result<QueryOutput> {
+        plan.run(&*(self.transaction))
+    }
+
     /// Choose whether to use in-memory caches for running queries.
     pub fn use_caching(&mut self, yesno: bool) {
         self.use_caching = yesno;
@@ -416,6 +1009,7 @@ impl<'a, 'c> InProgress<'a, 'c> {
         if let Some(schema) = next_schema {
             self.schema = schema;
         }
+        self.collect_value_changes(report.tx_id)?;
         Ok(report)
     }
 
@@ -442,9 +1036,54 @@ impl<'a, 'c> InProgress<'a, 'c> {
         if let Some(schema) = next_schema {
             self.schema = schema;
         }
+        self.collect_value_changes(report.tx_id)?;
         Ok(report)
     }
 
+    /// Record the old and new values of attributes watched by value observers, as written by
+    /// transaction `tx_id`. These are read back from the `transactions` table, which, unlike the
+    /// transact watcher, also sees the implicit retractions of cardinality-one attributes.
+    fn collect_value_changes(&mut self, tx_id: Entid) -> Result<()> {
+        if self.value_attributes.is_empty() {
+            return Ok(());
+        }
+
+        let datoms: Vec<(Entid, Entid, TypedValue, bool)> = {
+            let mut stmt = self.transaction.prepare(
+                &format!("SELECT t.e, t.a, coalesce(f.text, t.v), t.value_type_tag, t.added \
+                          FROM {} \
+                          WHERE t.tx = ?", TRANSACTIONS_WITH_FULLTEXT))?;
+            let datoms: Result<Vec<(Entid, Entid, TypedValue, bool)>> = stmt.query_and_then(&[&tx_id], |row| {
+                let e: Entid = row.get_checked(0)?;
+                let a: Entid = row.get_checked(1)?;
+                let v: rusqlite::types::Value = row.get_checked(2)?;
+                let value_type_tag: i32 = row.get_checked(3)?;
+                let added: bool = row.get_checked(4)?;
+                let typed_value = TypedValue::from_sql_value_pair(v, value_type_tag)?;
+                Ok((e, a, typed_value, added))
+            })?.collect();
+            datoms?
+        };
+
+        let mut changes: BTreeMap<(Entid, Entid), (Option<TypedValue>, Option<TypedValue>)> = BTreeMap::new();
+        for (e, a, v, added) in datoms {
+            if !self.value_attributes.contains(&a) {
+                continue;
+            }
+            let change = changes.entry((a, e)).or_insert((None, None));
+            if added {
+                change.1 = Some(v);
+            } else {
+                change.0 = Some(v);
+            }
+        }
+
+        for ((a, e), (old, new)) in changes {
+            self.value_changes.entry(a).or_insert_with(Vec::new).push((e, old, new));
+        }
+        Ok(())
+    }
+
     pub fn transact(&mut self, transaction: &str) -> Result<TxReport> {
         let assertion_vector = edn::parse::value(transaction)?;
         let entities = mentat_tx_parser::Tx::parse(&assertion_vector)?;
@@ -453,10 +1092,172 @@ impl<'a, 'c> InProgress<'a, 'c> {
 
     pub fn import<P>(&mut self, path: P) -> Result<TxReport>
     where P: AsRef<Path> {
-        let mut file = File::open(path)?;
-        let mut text: String = String::new();
-        file.read_to_string(&mut text)?;
-        self.transact(text.as_str())
+        self.transact_reader(File::open(path)?)
+    }
+
+    /// Transact the EDN read from `reader`, a sequence of transactions such as `[...] [...]`,
+    /// reading and applying one at a time. See `Store::transact_reader`. Returns the report of
+    /// the last.
+    pub fn transact_reader<R>(&mut self, reader: R) -> Result<TxReport> where R: Read {
+        let mut last = None;
+        for transaction in EdnForms::new(BufReader::new(reader)) {
+            last = Some(self.transact(transaction?.as_str())?);
+        }
+        last.ok_or_else(|| ErrorKind::EmptyTransactionBatch.into())
+    }
+
+    /// Transact `json`, an array of objects `{"op": "add", "e": …, "a": …, "v": …}`, for callers
+    /// that can build JSON more safely than EDN. `op` is `"add"` (the default) or `"retract"`; `a`
+    /// is an attribute keyword such as `":foo/bar"`; `e` is an entid or a tempid string. `v` is
+    /// read according to the attribute's value type: instants are RFC 3339 strings or
+    /// microseconds, keywords and UUIDs are strings, and refs are entids, idents such as
+    /// `":foo/baz"`, or tempid strings. A malformed row fails the whole transaction with an
+    /// `InvalidJsonTransaction` error naming the row's index.
+    pub fn transact_json(&mut self, json: &str) -> Result<TxReport> {
+        let rows = match serde_json::from_str(json) {
+            Ok(serde_json::Value::Array(rows)) => rows,
+            Ok(_) => bail!(ErrorKind::InvalidJsonTransaction("expected an array of datoms".to_string())),
+            Err(e) => bail!(ErrorKind::InvalidJsonTransaction(e.to_string())),
+        };
+
+        let mut builder = TermBuilder::new();
+        for (index, row) in rows.iter().enumerate() {
+            self.add_json_datom(&mut builder, row)
+                .map_err(|message| ErrorKind::InvalidJsonTransaction(format!("row {}: {}", index, message)))?;
+        }
+        self.transact_builder(builder)
+    }
+
+    fn add_json_datom(&self, builder: &mut TermBuilder, row: &serde_json::Value) -> ::std::result::Result<(), String> {
+        let field = |name: &str| row.get(name).ok_or_else(|| format!("missing \"{}\"", name));
+
+        let retract = match row.get("op").map(|op| op.as_str()) {
+            None | Some(Some("add")) => false,
+            Some(Some("retract")) => true,
+            Some(_) => return Err("\"op\" must be \"add\" or \"retract\"".to_string()),
+        };
+
+        let attribute = match field("a")?.as_str().map(edn::parse::value) {
+            Some(Ok(edn::ValueAndSpan { inner: edn::SpannedValue::NamespacedKeyword(kw), .. })) => kw,
+            _ => return Err("\"a\" must be an attribute keyword".to_string()),
+        };
+        let (value_type, a) = self.schema
+                                  .attribute_for_ident(&attribute)
+                                  .map(|(attr, a)| (attr.value_type, a))
+                                  .ok_or_else(|| format!("unknown attribute {}", attribute))?;
+
+        let e = match field("e")? {
+            &serde_json::Value::Number(ref n) if n.is_i64() => Either::Left(KnownEntid(n.as_i64().unwrap())),
+            &serde_json::Value::String(ref tempid) => Either::Right(builder.named_tempid(tempid.clone())),
+            _ => return Err("\"e\" must be an entid or a tempid string".to_string()),
+        };
+
+        let v = field("v")?;
+        let mismatch = || format!("\"v\" is not a valid {}", value_type);
+        let v = match (value_type, v) {
+            (ValueType::Ref, &serde_json::Value::Number(ref n)) => Either::Left(TypedValue::Ref(n.as_i64().ok_or_else(&mismatch)?)),
+            (ValueType::Ref, &serde_json::Value::String(ref s)) if s.starts_with(':') => {
+                let ident = match edn::parse::value(s) {
+                    Ok(edn::ValueAndSpan { inner: edn::SpannedValue::NamespacedKeyword(kw), .. }) => kw,
+                    _ => return Err(mismatch()),
+                };
+                let entid = self.schema.get_entid(&ident).ok_or_else(|| format!("unknown ident {}", ident))?;
+                Either::Left(TypedValue::Ref(entid.0))
+            },
+            (ValueType::Ref, &serde_json::Value::String(ref tempid)) => Either::Right(builder.named_tempid(tempid.clone())),
+            (ValueType::Boolean, &serde_json::Value::Bool(b)) => Either::Left(TypedValue::Boolean(b)),
+            (ValueType::Long, &serde_json::Value::Number(ref n)) => Either::Left(TypedValue::Long(n.as_i64().ok_or_else(&mismatch)?)),
+            (ValueType::Double, &serde_json::Value::Number(ref n)) => Either::Left(TypedValue::Double(n.as_f64().ok_or_else(&mismatch)?.into())),
+            (ValueType::Instant, &serde_json::Value::Number(ref n)) => Either::Left(TypedValue::instant(n.as_i64().ok_or_else(&mismatch)?)),
+            (ValueType::Instant, &serde_json::Value::String(ref s)) => {
+                let instant = DateTime::parse_from_rfc3339(s).map_err(|_| mismatch())?.with_timezone(&Utc);
+                Either::Left(TypedValue::instant(instant.to_micros()))
+            },
+            (ValueType::String, &serde_json::Value::String(ref s)) => Either::Left(TypedValue::typed_string(s)),
+            (ValueType::Keyword, &serde_json::Value::String(ref s)) => match edn::parse::value(s) {
+                Ok(edn::ValueAndSpan { inner: edn::SpannedValue::NamespacedKeyword(kw), .. }) => Either::Left(kw.into()),
+                _ => return Err(mismatch()),
+            },
+            (ValueType::Uuid, &serde_json::Value::String(ref s)) => Either::Left(TypedValue::Uuid(Uuid::parse_str(s).map_err(|_| mismatch())?)),
+            _ => return Err(mismatch()),
+        };
+
+        let result = if retract { builder.retract(e, a, v) } else { builder.add(e, a, v) };
+        result.map_err(|e| e.to_string())
+    }
+
+    /// Retract every datom whose entity lies in the `:db.part/user` partition. Schema, which lives
+    /// in `:db.part/db`, and transaction metadata in `:db.part/tx` are left untouched.
+    pub fn retract_user_data(&mut self) -> Result<TxReport> {
+        let (start, end) = {
+            let user = &self.partition_map[":db.part/user"];
+            (user.start, user.index)
+        };
+
+        let datoms: Vec<(Entid, Entid, TypedValue)> = {
+            let mut stmt = self.transaction.prepare(&format!("SELECT d.e, d.a, coalesce(f.text, d.v), d.value_type_tag \
+                                                              FROM {} \
+                                                              WHERE d.e >= ? AND d.e < ?", DATOMS_WITH_FULLTEXT))?;
+            let datoms: Result<Vec<(Entid, Entid, TypedValue)>> = stmt.query_and_then(&[&start, &end], |row| {
+                let e: Entid = row.get_checked(0)?;
+                let a: Entid = row.get_checked(1)?;
+                let v: rusqlite::types::Value = row.get_checked(2)?;
+                let value_type_tag: i32 = row.get_checked(3)?;
+                let typed_value = TypedValue::from_sql_value_pair(v, value_type_tag)?;
+                Ok((e, a, typed_value))
+            })?.collect();
+            datoms?
+        };
+
+        let mut builder = TermBuilder::new();
+        for (e, a, v) in datoms {
+            builder.retract(KnownEntid(e), KnownEntid(a), v)?;
+        }
+        self.transact_builder(builder)
+    }
+
+    /// Retract every datom whose entity is `entid`, including each value of cardinality-many
+    /// attributes. Datoms on other entities that refer to `entid` are left in place: callers that
+    /// don't want dangling refs must retract those themselves.
+    pub fn retract_entity(&mut self, entid: Entid) -> Result<TxReport> {
+        let datoms: Vec<(Entid, TypedValue)> = {
+            let mut stmt = self.transaction.prepare(&format!("SELECT d.a, coalesce(f.text, d.v), d.value_type_tag \
+                                                              FROM {} \
+                                                              WHERE d.e = ?", DATOMS_WITH_FULLTEXT))?;
+            let datoms: Result<Vec<(Entid, TypedValue)>> = stmt.query_and_then(&[&entid], |row| {
+                let a: Entid = row.get_checked(0)?;
+                let v: rusqlite::types::Value = row.get_checked(1)?;
+                let value_type_tag: i32 = row.get_checked(2)?;
+                let typed_value = TypedValue::from_sql_value_pair(v, value_type_tag)?;
+                Ok((a, typed_value))
+            })?.collect();
+            datoms?
+        };
+
+        let mut builder = TermBuilder::new();
+        for (a, v) in datoms {
+            builder.retract(KnownEntid(entid), KnownEntid(a), v)?;
+        }
+        self.transact_builder(builder)
+    }
+
+    /// Retract every current value of `attribute` on `entid`, returning how many datoms were
+    /// retracted. Reading and retracting happen in this transaction, so no concurrent write can
+    /// slip a value in between.
+    pub fn retract_attribute(&mut self, entid: Entid, attribute: &NamespacedKeyword) -> Result<usize> {
+        let a = self.schema.get_entid(attribute).ok_or_else(|| ErrorKind::UnknownAttribute(attribute.to_string()))?;
+        let values = self.lookup_values_for_attribute(entid, attribute)?;
+        if values.is_empty() {
+            return Ok(0);
+        }
+
+        let count = values.len();
+        let mut builder = TermBuilder::new();
+        for v in values {
+            builder.retract(KnownEntid(entid), a, v)?;
+        }
+        self.transact_builder(builder)?;
+        Ok(count)
     }
 
     pub fn rollback(self) -> Result<()> {
@@ -484,16 +1285,23 @@ impl<'a, 'c> InProgress<'a, 'c> {
         // Update the conn's cache if we made any changes.
         self.cache.commit_to(&mut metadata.attribute_cache);
 
-        if self.schema != *(metadata.schema) {
+        let changed_attributes = if self.schema != *(metadata.schema) {
+            let changed = changed_attributes(&metadata.schema, &self.schema);
             metadata.schema = Arc::new(self.schema);
 
             // TODO: rebuild vocabularies and notify consumers that they've changed -- it's possible
             // that a change has arrived over the wire and invalidated some local module.
             // TODO: consider making vocabulary lookup lazy -- we won't need it much of the time.
-        }
+            changed
+        } else {
+            BTreeSet::new()
+        };
 
         let txes = self.tx_observer_watcher.txes;
-        self.tx_observer.lock().unwrap().in_progress_did_commit(txes);
+        let mut tx_observer = self.tx_observer.lock().unwrap();
+        tx_observer.in_progress_did_commit(txes);
+        tx_observer.in_progress_did_change_schema(changed_attributes);
+        tx_observer.in_progress_did_change_values(self.value_changes);
 
         Ok(())
     }
@@ -569,37 +1377,767 @@ impl Store {
         (self.sqlite, self.conn)
     }
 
-    pub fn conn(&self) -> &Conn {
-        &self.conn
+    /// Close the underlying SQLite connection before returning, releasing any locks it holds on
+    /// the database file. Once this returns successfully, the same path can be opened again.
+    pub fn close(self) -> Result<()> {
+        let (sqlite, _conn) = self.dismantle();
+        sqlite.close().map_err(|(_, e)| e.into())
+    }
+
+    /// Checkpoint the write-ahead log into the database file and truncate it, so that every
+    /// committed write is in the main file. Fails if another connection's reads or writes
+    /// prevent the checkpoint from completing; it's safe to try again. Does nothing for a store
+    /// that isn't in WAL mode.
+    pub fn flush(&self) -> Result<()> {
+        let busy: i64 = self.sqlite.query_row("PRAGMA wal_checkpoint(TRUNCATE)", &[], |row| row.get(0))?;
+        if busy != 0 {
+            bail!(ErrorKind::CheckpointBlocked);
+        }
+        Ok(())
+    }
+
+    /// Report where this store's database lives, as resolved by SQLite, and the size of it and
+    /// its journal files.
+    pub fn file_info(&self) -> Result<StoreFileInfo> {
+        let file: String = {
+            let mut stmt = self.sqlite.prepare("PRAGMA database_list")?;
+            let databases: Vec<(String, Option<String>)> = stmt.query_map(&[], |row| (row.get(1), row.get(2)))?
+                                                               .collect::<::std::result::Result<_, _>>()?;
+            databases.into_iter()
+                     .find(|&(ref name, _)| name == "main")
+                     .and_then(|(_, file)| file)
+                     .unwrap_or_default()
+        };
+        if file.is_empty() {
+            return Ok(StoreFileInfo { path: None, db_size: 0, wal_size: 0, shm_size: 0, journal_size: 0 });
+        }
+
+        let size = |suffix: &str| fs::metadata(format!("{}{}", file, suffix)).map(|m| m.len()).unwrap_or(0);
+        Ok(StoreFileInfo {
+            db_size: size(""),
+            wal_size: size("-wal"),
+            shm_size: size("-shm"),
+            journal_size: size("-journal"),
+            path: Some(PathBuf::from(file.clone())),
+        })
+    }
+
+    /// Open a `StoreReader` onto this store's database file. In-memory stores can't be shared
+    /// this way and fail with `InMemoryStore`.
+    pub fn reader(&self) -> Result<StoreReader> {
+        let path = self.file_info()?.path.ok_or(ErrorKind::InMemoryStore)?;
+        let sqlite = ::new_connection(path)?;
+        sqlite.execute_batch("PRAGMA query_only=1;")?;
+        Ok(StoreReader {
+            sqlite,
+            schema: self.conn.current_schema(),
+        })
+    }
+
+    /// Prepare `query` to run on a fresh `StoreReader`, in a way that can be cancelled from
+    /// another thread: pass `QueryHandle::interrupt` to that thread and call `QueryHandle::run`.
+    /// Fails with `InMemoryStore` for an in-memory store; sees data as a `StoreReader` does.
+    pub fn q_cancellable(&self, query: &str, inputs: Option<QueryInputs>) -> Result<QueryHandle> {
+        let reader = self.reader()?;
+        let connection = InterruptibleConnection(unsafe { reader.sqlite.handle() });
+        Ok(QueryHandle {
+            reader,
+            query: query.to_string(),
+            inputs,
+            interrupt: Arc::new(QueryInterrupt {
+                cancelled: AtomicBool::new(false),
+                connection: Mutex::new(Some(connection)),
+            }),
+        })
+    }
+
+    /// Prepare a sync on a fresh connection to this store's database, in a way that can be
+    /// cancelled from another thread: pass `SyncHandle::interrupt` to that thread and call
+    /// `SyncHandle::run`. Fails with `InMemoryStore` for an in-memory store.
+    pub fn sync_cancellable(&self, server_uri: &String, user_uuid: &String) -> Result<SyncHandle> {
+        let user_uuid = Uuid::parse_str(&user_uuid)?;
+        let path = self.file_info()?.path.ok_or(ErrorKind::InMemoryStore)?;
+        let sqlite = ::new_connection(path)?;
+        Ok(SyncHandle {
+            sqlite,
+            server_uri: server_uri.clone(),
+            user_uuid,
+            interrupt: Arc::new(SyncInterrupt {
+                cancelled: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// Check the database for corruption: SQLite's own `PRAGMA integrity_check`, then a scan for
+    /// refs to entities that don't exist and for datoms whose attribute isn't in the schema.
+    /// The scans read every datom, so this is slow on large stores.
+    pub fn integrity_check(&self) -> Result<IntegrityReport> {
+        let sqlite_errors: Vec<String> = {
+            let mut stmt = self.sqlite.prepare("PRAGMA integrity_check")?;
+            let messages: Vec<String> = stmt.query_map(&[], |row| row.get(0))?
+                                            .collect::<::std::result::Result<_, _>>()?;
+            messages.into_iter().filter(|m| m != "ok").collect()
+        };
+        if !sqlite_errors.is_empty() {
+            // Scanning a corrupt file could fail or mislead; report what SQLite found.
+            return Ok(IntegrityReport { sqlite_errors, ..Default::default() });
+        }
+
+        let dangling_refs: Vec<(Entid, Entid, Entid)> = {
+            let mut stmt = self.sqlite.prepare(
+                "SELECT d.e, d.a, d.v FROM datoms AS d \
+                 WHERE d.value_type_tag = 0 AND NOT EXISTS (SELECT 1 FROM datoms AS t WHERE t.e = d.v)")?;
+            let refs = stmt.query_map(&[], |row| (row.get(0), row.get(1), row.get(2)))?
+                           .collect::<::std::result::Result<_, _>>()?;
+            refs
+        };
+
+        let unknown_attributes: Vec<Entid> = {
+            let schema = self.conn.current_schema();
+            let mut stmt = self.sqlite.prepare("SELECT DISTINCT a FROM datoms")?;
+            let attributes: Vec<Entid> = stmt.query_map(&[], |row| row.get(0))?
+                                             .collect::<::std::result::Result<_, _>>()?;
+            attributes.into_iter().filter(|a| schema.attribute_for_entid(*a).is_none()).collect()
+        };
+
+        Ok(IntegrityReport { sqlite_errors, dangling_refs, unknown_attributes })
+    }
+
+    /// Make writes that find the database locked retry for up to `timeout` before failing with
+    /// `SQLITE_BUSY`. A newly opened store waits up to five seconds, rusqlite's default; a zero
+    /// timeout removes the wait, so a locked database fails at once.
+    pub fn set_busy_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.sqlite.busy_timeout(timeout).map_err(|e| e.into())
+    }
+
+    /// Switch the database to journal `mode`, returning the mode in effect afterwards. SQLite may
+    /// decline: an in-memory store, for one, always reports `Memory`.
+    pub fn set_journal_mode(&mut self, mode: JournalMode) -> Result<JournalMode> {
+        let effective: String = self.sqlite.query_row(&format!("PRAGMA journal_mode={}", mode.as_str()), &[], |row| row.get(0))?;
+        JournalMode::from_str(&effective).ok_or_else(|| ErrorKind::InvalidJournalMode(effective).into())
+    }
+
+    pub fn conn(&self) -> &Conn {
+        &self.conn
+    }
+
+    pub fn begin_read<'m>(&'m mut self) -> Result<InProgressRead<'m, 'm>> {
+        self.conn.begin_read(&mut self.sqlite)
+    }
+
+    pub fn begin_transaction<'m>(&'m mut self) -> Result<InProgress<'m, 'm>> {
+        self.conn.begin_transaction(&mut self.sqlite)
+    }
+
+    pub fn cache(&mut self, attr: &NamespacedKeyword, direction: CacheDirection) -> Result<()> {
+        let schema = &self.conn.current_schema();
+        self.conn.cache(&mut self.sqlite,
+                        schema,
+                        attr,
+                        direction,
+                        CacheAction::Register)
+    }
+
+    pub fn register_observer(&mut self, key: String, observer: Arc<TxObserver>) {
+        self.conn.register_observer(key, observer);
+    }
+
+    pub fn unregister_observer(&mut self, key: &String) {
+        self.conn.unregister_observer(key);
+    }
+
+    pub fn register_schema_observer(&mut self, key: String, observer: Arc<SchemaObserver>) {
+        self.conn.register_schema_observer(key, observer);
+    }
+
+    pub fn unregister_schema_observer(&mut self, key: &String) {
+        self.conn.unregister_schema_observer(key);
+    }
+
+    pub fn register_value_observer(&mut self, key: String, observer: Arc<ValueObserver>) {
+        self.conn.register_value_observer(key, observer);
+    }
+
+    /// Unregister every transaction, schema, and value observer.
+    pub fn unregister_all_observers(&mut self) {
+        self.conn.unregister_all_observers();
+    }
+
+    /// The keys of every registered transaction, schema, and value observer. A key that stays
+    /// here after its consumer has gone points to an observer that was never unregistered.
+    pub fn observer_keys(&self) -> Vec<String> {
+        self.conn.observer_keys()
+    }
+
+    pub fn unregister_value_observer(&mut self, key: &String) {
+        self.conn.unregister_value_observer(key);
+    }
+
+    pub fn assert_datom<T>(&mut self, entid: T, attribute: NamespacedKeyword, value: TypedValue) -> Result<()> where T: Into<KnownEntid> {
+        self.conn.assert_datom(&mut self.sqlite, entid, attribute, value)
+    }
+
+    pub fn retract_datom<T>(&mut self, entid: T, attribute: NamespacedKeyword, value: TypedValue) -> Result<()> where T: Into<KnownEntid> {
+        self.conn.retract_datom(&mut self.sqlite, entid, attribute, value)
+    }
+
+    /// Assert that `attribute` of `entid` refers to the entity whose ident is `value`, as is
+    /// usual for enum-valued attributes. Fails with `UnknownAttribute` or `UnknownIdent`
+    /// depending on which keyword isn't known.
+    pub fn assert_kw_ref<T>(&mut self, entid: T, attribute: NamespacedKeyword, value: &NamespacedKeyword) -> Result<()> where T: Into<KnownEntid> {
+        let value = self.resolve_kw_ref(&attribute, value)?;
+        self.assert_datom(entid, attribute, value)
+    }
+
+    /// The retraction counterpart of `assert_kw_ref`.
+    pub fn retract_kw_ref<T>(&mut self, entid: T, attribute: NamespacedKeyword, value: &NamespacedKeyword) -> Result<()> where T: Into<KnownEntid> {
+        let value = self.resolve_kw_ref(&attribute, value)?;
+        self.retract_datom(entid, attribute, value)
+    }
+
+    fn resolve_kw_ref(&self, attribute: &NamespacedKeyword, value: &NamespacedKeyword) -> Result<TypedValue> {
+        let schema = self.conn.current_schema();
+        if !schema.identifies_attribute(attribute) {
+            bail!(ErrorKind::UnknownAttribute(attribute.to_string()));
+        }
+        schema.get_entid(value)
+              .map(|entid| TypedValue::Ref(entid.into()))
+              .ok_or_else(|| ErrorKind::UnknownIdent(value.to_string()).into())
+    }
+
+    /// Resolve the lookup ref `[unique_attribute value]` to the entity it names, or `None` if no
+    /// entity has that value. Fails with `UnknownAttribute` if `unique_attribute` isn't in the
+    /// schema, with `NotUniqueAttribute` if it isn't `:db/unique`, and with `ValueTypeMismatch` if
+    /// `value` isn't of its type.
+    pub fn resolve_lookup_ref(&self, unique_attribute: &NamespacedKeyword, value: &TypedValue) -> Result<Option<Entid>> {
+        let unique_attribute = self.resolve_attribute_alias(unique_attribute);
+        let schema = self.conn.current_schema();
+        let (attribute, entid) = schema.attribute_for_ident(unique_attribute)
+                                       .ok_or_else(|| ErrorKind::UnknownAttribute(unique_attribute.to_string()))?;
+        if attribute.unique.is_none() {
+            bail!(ErrorKind::NotUniqueAttribute(unique_attribute.to_string()));
+        }
+        if value.value_type() != attribute.value_type {
+            bail!(ErrorKind::ValueTypeMismatch(value.value_type(), attribute.value_type));
+        }
+        let (v, value_type_tag) = value.to_sql_value_pair();
+        let mut stmt = self.sqlite.prepare("SELECT e FROM datoms WHERE a = ? AND v = ? AND value_type_tag = ? LIMIT 1")?;
+        let mut rows = stmt.query(&[&entid.0, &v, &value_type_tag])?;
+        match rows.next() {
+            Some(row) => Ok(Some(row?.get_checked(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Assert `value` of `attribute` on the entity whose `unique_attribute` is `lookup_value`, the
+    /// counterpart of `retract_by_lookup`. Fails if the lookup matches no entity or
+    /// `unique_attribute` isn't `:db/unique`.
+    pub fn assert_by_lookup(&mut self, unique_attribute: &NamespacedKeyword, lookup_value: &TypedValue, attribute: &NamespacedKeyword, value: &TypedValue) -> Result<TxReport> {
+        let transaction = format!("[[:db/add (lookup-ref {} {}) {} {}]]",
+                                  unique_attribute,
+                                  typed_value_to_edn_string(lookup_value),
+                                  attribute,
+                                  typed_value_to_edn_string(value));
+        self.transact(transaction.as_str())
+    }
+
+    /// Retract `value` of `attribute` from the entity whose `unique_attribute` is `lookup_value`,
+    /// so that callers keyed on an external id needn't resolve it to an entid first. Fails if the
+    /// lookup matches no entity or `unique_attribute` isn't `:db/unique`.
+    pub fn retract_by_lookup(&mut self, unique_attribute: &NamespacedKeyword, lookup_value: &TypedValue, attribute: &NamespacedKeyword, value: &TypedValue) -> Result<TxReport> {
+        let transaction = format!("[[:db/retract (lookup-ref {} {}) {} {}]]",
+                                  unique_attribute,
+                                  typed_value_to_edn_string(lookup_value),
+                                  attribute,
+                                  typed_value_to_edn_string(value));
+        self.transact(transaction.as_str())
+    }
+
+    /// Remove all user data from the store in a single transaction, keeping the installed schema.
+    /// See `InProgress::retract_user_data`.
+    pub fn clear_user_data(&mut self) -> Result<TxReport> {
+        let mut in_progress = self.begin_transaction()?;
+        let report = in_progress.retract_user_data()?;
+        in_progress.commit()?;
+        Ok(report)
+    }
+
+    /// Transact a JSON description of datoms. See `InProgress::transact_json`.
+    pub fn transact_json(&mut self, json: &str) -> Result<TxReport> {
+        let mut in_progress = self.begin_transaction()?;
+        let report = in_progress.transact_json(json)?;
+        in_progress.commit()?;
+        Ok(report)
+    }
+
+    /// Retract every datom of `entid` in a single transaction. See `InProgress::retract_entity`.
+    pub fn retract_entity(&mut self, entid: Entid) -> Result<TxReport> {
+        let mut in_progress = self.begin_transaction()?;
+        let report = in_progress.retract_entity(entid)?;
+        in_progress.commit()?;
+        Ok(report)
+    }
+
+    /// Retract every value of `attribute` on `entid` in a single transaction.
+    /// See `InProgress::retract_attribute`.
+    pub fn retract_attribute(&mut self, entid: Entid, attribute: &NamespacedKeyword) -> Result<usize> {
+        let mut in_progress = self.begin_transaction()?;
+        let count = in_progress.retract_attribute(entid, attribute)?;
+        in_progress.commit()?;
+        Ok(count)
+    }
+
+    /// Return the entities whose value for `attribute` lies between `low` and `high`, which must
+    /// be longs, doubles, or instants. The bounds are included if `inclusive` is true.
+    pub fn entities_in_range(&self, attribute: &NamespacedKeyword, low: TypedValue, high: TypedValue, inclusive: bool) -> Result<Vec<Entid>> {
+        let attribute = self.resolve_attribute_alias(attribute);
+        let (above, below) = if inclusive { (">=", "<=") } else { (">", "<") };
+        let query = format!("[:find [?e ...] :in ?low ?high :where [?e {} ?v] [({} ?v ?low)] [({} ?v ?high)]]",
+                            attribute, above, below);
+        let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?low"), low),
+                                                           (Variable::from_valid_name("?high"), high)]);
+        let results = self.q_once(query.as_str(), inputs)?.into_coll()?;
+        Ok(results.into_iter().filter_map(|v| v.into_entid()).collect())
+    }
+
+    /// Whether `entid` has any value for `attribute`. Fails with `UnknownAttribute` if `attribute`
+    /// isn't in the schema, so that a typo doesn't read as absence.
+    pub fn entid_has_attribute(&self, entid: Entid, attribute: &NamespacedKeyword) -> Result<bool> {
+        let attribute = self.resolve_attribute_alias(attribute);
+        if !self.conn.current_schema().identifies_attribute(attribute) {
+            bail!(ErrorKind::UnknownAttribute(attribute.to_string()));
+        }
+        let query = format!("[:find ?e . :in ?e :where [?e {} _]]", attribute);
+        let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?e"), TypedValue::Ref(entid))]);
+        Ok(self.q_once(query.as_str(), inputs)?.into_scalar()?.is_some())
+    }
+
+    /// Register `old` as a read-time alias for the attribute `new`, so that lookups naming `old`
+    /// resolve to `new`. This is useful when an attribute has been renamed but not every caller
+    /// has caught up. Aliases only affect reads made through this `Store`: they are not persisted,
+    /// are not visible to queries, and don't change the stored schema.
+    pub fn alias_attribute(&mut self, old: NamespacedKeyword, new: NamespacedKeyword) -> Result<()> {
+        if !self.conn.current_schema().identifies_attribute(&new) {
+            bail!(ErrorKind::UnknownAttribute(new.to_string()));
+        }
+        self.attribute_aliases.insert(old, new);
+        Ok(())
+    }
+
+    /// Return the attribute that `attribute` is an alias for, or `attribute` itself.
+    fn resolve_attribute_alias<'k>(&'k self, attribute: &'k NamespacedKeyword) -> &'k NamespacedKeyword {
+        self.attribute_aliases.get(attribute).unwrap_or(attribute)
+    }
+
+    /// Transact `transaction` and, before committing, read back the values of `attributes` for
+    /// the entity to which `tempid` resolved. Values are returned in the order of `attributes`;
+    /// `None` marks an attribute with no value on that entity.
+    pub fn transact_and_fetch(&mut self, transaction: &str, tempid: &str, attributes: &[NamespacedKeyword]) -> Result<Vec<Option<TypedValue>>> {
+        let attributes: Vec<NamespacedKeyword> = attributes.iter().map(|a| self.resolve_attribute_alias(a).clone()).collect();
+        let mut in_progress = self.begin_transaction()?;
+        let report = in_progress.transact(transaction)?;
+        let entid = report.tempids
+                          .get(tempid)
+                          .cloned()
+                          .ok_or_else(|| ErrorKind::UnknownTempId(tempid.to_string()))?;
+        let values = attributes.iter()
+                               .map(|attribute| in_progress.lookup_value_for_attribute(entid, attribute))
+                               .collect::<Result<Vec<Option<TypedValue>>>>()?;
+        in_progress.commit()?;
+        Ok(values)
+    }
+
+    /// For each of `entids`, look up the values of `attributes` against a single read
+    /// transaction. Each row holds one entry per attribute, in order; `None` marks an
+    /// attribute with no value on that entity.
+    pub fn pull(&mut self, entids: &[Entid], attributes: &[NamespacedKeyword]) -> Result<Vec<Vec<Option<TypedValue>>>> {
+        let attributes: Vec<NamespacedKeyword> = attributes.iter().map(|a| self.resolve_attribute_alias(a).clone()).collect();
+        let read = self.begin_read()?;
+        let rows = entids.iter()
+                         .map(|entid| {
+                             attributes.iter()
+                                       .map(|attribute| read.lookup_value_for_attribute(*entid, attribute))
+                                       .collect::<Result<Vec<Option<TypedValue>>>>()
+                         })
+                         .collect();
+        rows
+    }
+
+    /// Read the values of several cardinality-one `attributes` of one entity with a single query.
+    /// Values are returned in the order of `attributes`; `None` marks an attribute with no value.
+    /// For a cardinality-many attribute, an arbitrary one of its values is returned.
+    pub fn entity_attributes(&self, entid: Entid, attributes: &[NamespacedKeyword]) -> Result<Vec<Option<TypedValue>>> {
+        let attribute_entids = self.attribute_entids(attributes)?;
+        if attribute_entids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let placeholders: Vec<&str> = attribute_entids.iter().map(|_| "?").collect();
+        let sql = format!("SELECT d.a, coalesce(f.text, d.v), d.value_type_tag \
+                           FROM {} \
+                           WHERE d.e = ? AND d.a IN ({})", DATOMS_WITH_FULLTEXT, placeholders.join(", "));
+        let mut args: Vec<&rusqlite::types::ToSql> = vec![&entid];
+        args.extend(attribute_entids.iter().map(|a| a as &rusqlite::types::ToSql));
+
+        let mut found: BTreeMap<Entid, TypedValue> = BTreeMap::new();
+        let mut stmt = self.sqlite.prepare(sql.as_str())?;
+        let mut rows = stmt.query(&args)?;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let a: Entid = row.get_checked(0)?;
+            let v: rusqlite::types::Value = row.get_checked(1)?;
+            let value_type_tag: i32 = row.get_checked(2)?;
+            let value = TypedValue::from_sql_value_pair(v, value_type_tag)?;
+            found.entry(a).or_insert(value);
+        }
+        Ok(attribute_entids.iter().map(|a| found.get(a).cloned()).collect())
+    }
+
+    /// Every value that `entid` has for any of `attributes`, as `(attribute, value)` pairs ordered
+    /// by attribute. A cardinality-many attribute contributes one pair per value; an attribute with
+    /// no value contributes none.
+    pub fn pull_attributes(&self, entid: Entid, attributes: &[NamespacedKeyword]) -> Result<Vec<(NamespacedKeyword, TypedValue)>> {
+        let attribute_entids = self.attribute_entids(attributes)?;
+        if attribute_entids.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(self.pull_datoms(&[entid], Some(&attribute_entids))?.pop().unwrap_or_default())
+    }
+
+    /// Every value of every attribute of `entid`, as for `pull_attributes`.
+    pub fn pull_all_attributes(&self, entid: Entid) -> Result<Vec<(NamespacedKeyword, TypedValue)>> {
+        Ok(self.pull_datoms(&[entid], None)?.pop().unwrap_or_default())
+    }
+
+    /// `pull_attributes` for each of `entids`, with a single query. The result has one entry per
+    /// entid, in order.
+    pub fn pull_attributes_many(&self, entids: &[Entid], attributes: &[NamespacedKeyword]) -> Result<Vec<Vec<(NamespacedKeyword, TypedValue)>>> {
+        let attribute_entids = self.attribute_entids(attributes)?;
+        if attribute_entids.is_empty() || entids.is_empty() {
+            return Ok(entids.iter().map(|_| vec![]).collect());
+        }
+        self.pull_datoms(entids, Some(&attribute_entids))
+    }
+
+    /// Resolve `attributes`, following aliases, to their entids, failing with `UnknownAttribute`
+    /// for any that isn't in the schema.
+    fn attribute_entids(&self, attributes: &[NamespacedKeyword]) -> Result<Vec<Entid>> {
+        let schema = self.conn.current_schema();
+        attributes.iter()
+                  .map(|a| {
+                      let a = self.resolve_attribute_alias(a);
+                      schema.get_entid(a)
+                            .map(|e| e.0)
+                            .and_then(|e| schema.attribute_for_entid(e).map(|_| e))
+                            .ok_or_else(|| ErrorKind::UnknownAttribute(a.to_string()).into())
+                  })
+                  .collect()
+    }
+
+    fn pull_datoms(&self, entids: &[Entid], attribute_entids: Option<&[Entid]>) -> Result<Vec<Vec<(NamespacedKeyword, TypedValue)>>> {
+        let schema = self.conn.current_schema();
+        let placeholders: Vec<&str> = entids.iter().map(|_| "?").collect();
+        let mut sql = format!("SELECT d.e, d.a, coalesce(f.text, d.v), d.value_type_tag \
+                               FROM {} \
+                               WHERE d.e IN ({})", DATOMS_WITH_FULLTEXT, placeholders.join(", "));
+        let mut args: Vec<&rusqlite::types::ToSql> = entids.iter().map(|e| e as &rusqlite::types::ToSql).collect();
+        if let Some(attribute_entids) = attribute_entids {
+            let placeholders: Vec<&str> = attribute_entids.iter().map(|_| "?").collect();
+            sql.push_str(&format!(" AND d.a IN ({})", placeholders.join(", ")));
+            args.extend(attribute_entids.iter().map(|a| a as &rusqlite::types::ToSql));
+        }
+        sql.push_str(" ORDER BY d.e, d.a");
+
+        let mut found: BTreeMap<Entid, Vec<(NamespacedKeyword, TypedValue)>> = BTreeMap::new();
+        let mut stmt = self.sqlite.prepare(sql.as_str())?;
+        let mut rows = stmt.query(&args)?;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let e: Entid = row.get_checked(0)?;
+            let a: Entid = row.get_checked(1)?;
+            let v: rusqlite::types::Value = row.get_checked(2)?;
+            let value_type_tag: i32 = row.get_checked(3)?;
+            let attribute = schema.get_ident(a).ok_or_else(|| ErrorKind::UnknownAttribute(a.to_string()))?;
+            let value = TypedValue::from_sql_value_pair(v, value_type_tag)?;
+            found.entry(e).or_insert_with(Vec::new).push((attribute.clone(), value));
+        }
+        Ok(entids.iter().map(|e| found.get(e).cloned().unwrap_or_default()).collect())
+    }
+
+    /// Serialize `entid`, and the entities it refers to up to `depth` refs away, as EDN that can be
+    /// transacted into another store to recreate them. Exported entities are given the tempids
+    /// `"e0"`, `"e1"`, and so on. Refs to entities with an ident are written as that ident; other
+    /// refs beyond `depth` are dropped, because their entids mean nothing elsewhere.
+    pub fn export_entity(&self, entid: Entid, depth: usize) -> Result<String> {
+        let schema = self.conn.current_schema();
+        let mut tempids = BTreeMap::new();
+        let mut assertions = vec![];
+        self.export_entity_into(&*schema, entid, depth, &mut tempids, &mut assertions)?;
+        Ok(format!("[{}]", assertions.join("\n ")))
+    }
+
+    /// Write the whole store to `writer` as EDN that `import_edn_file` can load into a fresh store,
+    /// without building it up in memory. The schema comes first, as one transaction, followed by
+    /// the data as another; each entity is given the tempid `"e<entid>"`. Transaction metadata
+    /// isn't written. Mentat's own bootstrap entities are left out unless `include_system` is set;
+    /// a dump that includes them is for inspection, and won't transact cleanly. Returns the number
+    /// of datoms written.
+    pub fn export_edn<W>(&self, mut writer: W, include_system: bool) -> Result<usize> where W: Write {
+        let schema = self.conn.current_schema();
+        let first = if include_system { 0 } else { DB_SCHEMA_CORE + 1 };
+        let mut written = 0;
+        for &(start, end) in [(first, USER0), (USER0, TX0)].iter() {
+            let mut stmt = self.sqlite.prepare(&format!("SELECT d.e, d.a, coalesce(f.text, d.v), d.value_type_tag \
+                                                         FROM {} \
+                                                         WHERE d.e >= ? AND d.e < ? \
+                                                         ORDER BY d.e, d.a", DATOMS_WITH_FULLTEXT))?;
+            let mut rows = stmt.query(&[&start, &end])?;
+            let mut opened = false;
+            while let Some(row) = rows.next() {
+                let row = row?;
+                let e: Entid = row.get_checked(0)?;
+                let a: Entid = row.get_checked(1)?;
+                let v: rusqlite::types::Value = row.get_checked(2)?;
+                let value_type_tag: i32 = row.get_checked(3)?;
+                let attribute = schema.get_ident(a).ok_or_else(|| ErrorKind::UnknownAttribute(a.to_string()))?;
+                let value = match TypedValue::from_sql_value_pair(v, value_type_tag)? {
+                    // An ident defined in this same transaction can't be used yet, so refer to
+                    // entities written alongside this one by tempid.
+                    TypedValue::Ref(r) if r >= start && r < end => format!("\"e{}\"", r),
+                    TypedValue::Ref(r) => schema.get_ident(r).map_or_else(|| format!("\"e{}\"", r), |ident| ident.to_string()),
+                    v => typed_value_to_edn_string(&v),
+                };
+                writer.write_all(if opened { b"\n " } else { b"[" })?;
+                opened = true;
+                write!(writer, "[:db/add \"e{}\" {} {}]", e, attribute, value)?;
+                written += 1;
+            }
+            if opened {
+                writer.write_all(b"]\n")?;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Like `export_edn`, but writes to a new file at `path`, replacing any that exists.
+    pub fn export_edn_file<P>(&self, path: P, include_system: bool) -> Result<usize> where P: AsRef<Path> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let written = self.export_edn(&mut writer, include_system)?;
+        writer.flush()?;
+        Ok(written)
     }
 
-    pub fn begin_read<'m>(&'m mut self) -> Result<InProgressRead<'m, 'm>> {
-        self.conn.begin_read(&mut self.sqlite)
+    /// Read every transaction committed after `tx` from the log, oldest first, with the datoms
+    /// each one added and retracted. Pass `0` for the whole history, bootstrap included.
+    pub fn transactions_since(&self, tx: Entid) -> Result<Vec<LoggedTransaction>> {
+        let mut stmt = self.sqlite.prepare(&format!("SELECT t.tx, t.e, t.a, coalesce(f.text, t.v), t.value_type_tag, t.added \
+                                                     FROM {} \
+                                                     WHERE t.tx > ? \
+                                                     ORDER BY t.tx, t.e, t.a, t.added", TRANSACTIONS_WITH_FULLTEXT))?;
+        let mut rows = stmt.query(&[&tx])?;
+        let mut transactions: Vec<LoggedTransaction> = vec![];
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let tx: Entid = row.get_checked(0)?;
+            let e: Entid = row.get_checked(1)?;
+            let a: Entid = row.get_checked(2)?;
+            let v: rusqlite::types::Value = row.get_checked(3)?;
+            let value_type_tag: i32 = row.get_checked(4)?;
+            let added: bool = row.get_checked(5)?;
+            let value = TypedValue::from_sql_value_pair(v, value_type_tag)?;
+
+            if transactions.last().map_or(true, |last| last.tx != tx) {
+                // Every transaction asserts its own `:db/txInstant`, which fills this in.
+                transactions.push(LoggedTransaction {
+                    tx: tx,
+                    instant: DateTime::<Utc>::from_micros(0),
+                    datoms: vec![],
+                });
+            }
+            let current = transactions.last_mut().unwrap();
+            if e == tx && a == DB_TX_INSTANT && added {
+                if let TypedValue::Instant(instant) = value {
+                    current.instant = instant;
+                }
+            }
+            current.datoms.push((e, a, value, added));
+        }
+        Ok(transactions)
     }
 
-    pub fn begin_transaction<'m>(&'m mut self) -> Result<InProgress<'m, 'm>> {
-        self.conn.begin_transaction(&mut self.sqlite)
-    }
+    fn export_entity_into(&self,
+                          schema: &Schema,
+                          entid: Entid,
+                          depth: usize,
+                          tempids: &mut BTreeMap<Entid, String>,
+                          assertions: &mut Vec<String>) -> Result<String> {
+        if let Some(tempid) = tempids.get(&entid) {
+            return Ok(tempid.clone());
+        }
+        let tempid = format!("\"e{}\"", tempids.len());
+        tempids.insert(entid, tempid.clone());
+
+        let datoms: Vec<(Entid, TypedValue)> = {
+            let mut stmt = self.sqlite.prepare(&format!("SELECT d.a, coalesce(f.text, d.v), d.value_type_tag \
+                                                         FROM {} \
+                                                         WHERE d.e = ? \
+                                                         ORDER BY d.a", DATOMS_WITH_FULLTEXT))?;
+            let datoms: Result<Vec<(Entid, TypedValue)>> = stmt.query_and_then(&[&entid], |row| {
+                let a: Entid = row.get_checked(0)?;
+                let v: rusqlite::types::Value = row.get_checked(1)?;
+                let value_type_tag: i32 = row.get_checked(2)?;
+                let typed_value = TypedValue::from_sql_value_pair(v, value_type_tag)?;
+                Ok((a, typed_value))
+            })?.collect();
+            datoms?
+        };
 
-    pub fn cache(&mut self, attr: &NamespacedKeyword, direction: CacheDirection) -> Result<()> {
-        let schema = &self.conn.current_schema();
-        self.conn.cache(&mut self.sqlite,
-                        schema,
-                        attr,
-                        direction,
-                        CacheAction::Register)
+        for (a, v) in datoms {
+            let attribute = schema.get_ident(a).ok_or_else(|| ErrorKind::UnknownAttribute(a.to_string()))?;
+            let value = match v {
+                TypedValue::Ref(r) => {
+                    if let Some(ident) = schema.get_ident(r) {
+                        ident.to_string()
+                    } else if depth > 0 {
+                        self.export_entity_into(schema, r, depth - 1, tempids, assertions)?
+                    } else {
+                        continue;
+                    }
+                },
+                v => typed_value_to_edn_string(&v),
+            };
+            assertions.push(format!("[:db/add {} {} {}]", tempid, attribute, value));
+        }
+        Ok(tempid)
     }
+}
 
-    pub fn register_observer(&mut self, key: String, observer: Arc<TxObserver>) {
-        self.conn.register_observer(key, observer);
+/// How many transactions `Store::import_edn_file` commits at a time.
+pub const IMPORT_FORMS_PER_COMMIT: usize = 500;
+
+/// Splits EDN text read from a `BufRead` into its top-level forms, a line at a time, so that a
+/// long sequence of transactions needn't be held in memory at once. This only tracks enough
+/// syntax to find where each form ends: brackets, strings, and comments. The forms themselves
+/// are parsed later.
+struct EdnForms<R> {
+    reader: R,
+    line: String,
+    offset: usize,
+}
+
+impl<R> EdnForms<R> where R: BufRead {
+    fn new(reader: R) -> EdnForms<R> {
+        EdnForms { reader, line: String::new(), offset: 0 }
     }
+}
 
-    pub fn unregister_observer(&mut self, key: &String) {
-        self.conn.unregister_observer(key);
+impl<R> Iterator for EdnForms<R> where R: BufRead {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        let mut form = String::new();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut in_comment = false;
+        loop {
+            if self.offset >= self.line.len() {
+                self.line.clear();
+                self.offset = 0;
+                match self.reader.read_line(&mut self.line) {
+                    Ok(0) if depth > 0 || in_string => return Some(Err("unexpected end of EDN input".into())),
+                    Ok(0) if form.is_empty() => return None,
+                    Ok(0) => return Some(Ok(form)),
+                    Ok(_) => {},
+                    Err(e) => return Some(Err(e.into())),
+                }
+            }
+
+            let mut end = None;
+            for (i, c) in self.line[self.offset..].char_indices() {
+                let next = self.offset + i + c.len_utf8();
+                if in_comment {
+                    in_comment = c != '\n';
+                    continue;
+                }
+                if in_string {
+                    form.push(c);
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+                match c {
+                    ';' if depth == 0 && !form.is_empty() => {
+                        end = Some(next - 1);
+                        break;
+                    },
+                    ';' => in_comment = true,
+                    '"' => {
+                        form.push(c);
+                        in_string = true;
+                    },
+                    '[' | '(' | '{' => {
+                        form.push(c);
+                        depth += 1;
+                    },
+                    ']' | ')' | '}' => {
+                        if depth == 0 {
+                            return Some(Err(format!("unbalanced '{}' in EDN input", c).into()));
+                        }
+                        form.push(c);
+                        depth -= 1;
+                        if depth == 0 {
+                            end = Some(next);
+                            break;
+                        }
+                    },
+                    c if c.is_whitespace() || c == ',' => {
+                        if depth == 0 && !form.is_empty() {
+                            end = Some(next);
+                            break;
+                        }
+                        if depth > 0 {
+                            form.push(c);
+                        }
+                    },
+                    c => form.push(c),
+                }
+            }
+            match end {
+                Some(end) => {
+                    self.offset = end;
+                    return Some(Ok(form));
+                },
+                None => self.offset = self.line.len(),
+            }
+        }
     }
+}
 
-    pub fn assert_datom<T>(&mut self, entid: T, attribute: NamespacedKeyword, value: TypedValue) -> Result<()> where T: Into<KnownEntid> {
-        self.conn.assert_datom(&mut self.sqlite, entid, attribute, value)
+/// Format a `TypedValue` as EDN that the transaction parser reads back losslessly.
+fn typed_value_to_edn_string(value: &TypedValue) -> String {
+    match value {
+        &TypedValue::Ref(x) => x.to_string(),
+        &TypedValue::Long(x) => x.to_string(),
+        &TypedValue::Boolean(x) => x.to_string(),
+        &TypedValue::Double(x) if x.0.is_finite() => format!("{:?}", x.0),
+        &TypedValue::Double(x) => edn::Value::Float(x).to_string(),
+        &TypedValue::Instant(ref x) => format!("#instmicros {}", x.to_micros()),
+        &TypedValue::String(ref x) => {
+            let escaped = x.replace('\\', "\\\\")
+                           .replace('"', "\\\"")
+                           .replace('\n', "\\n")
+                           .replace('\r', "\\r")
+                           .replace('\t', "\\t");
+            format!("\"{}\"", escaped)
+        },
+        &TypedValue::Keyword(ref x) => x.to_string(),
+        &TypedValue::Uuid(ref x) => format!("#uuid \"{}\"", x.hyphenated()),
     }
 }
 
@@ -621,11 +2159,13 @@ impl Queryable for Store {
 
     fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::NamespacedKeyword) -> Result<Vec<TypedValue>>
         where E: Into<Entid> {
+        let attribute = self.resolve_attribute_alias(attribute);
         self.conn.lookup_values_for_attribute(&self.sqlite, entity.into(), attribute)
     }
 
     fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::NamespacedKeyword) -> Result<Option<TypedValue>>
         where E: Into<Entid> {
+        let attribute = self.resolve_attribute_alias(attribute);
         self.conn.lookup_value_for_attribute(&self.sqlite, entity.into(), attribute)
     }
 }
@@ -648,6 +2188,15 @@ impl Syncable for Store {
         let uuid = Uuid::parse_str(&user_uuid)?;
         Ok(Syncer::flow(&mut self.sqlite, server_uri, &uuid)?)
     }
+
+    fn sync_with_progress(&mut self, server_uri: &String, user_uuid: &String, progress: &mut FnMut(SyncPhase, usize, usize)) -> Result<()> {
+        let uuid = Uuid::parse_str(&user_uuid)?;
+        Ok(Syncer::flow_with_progress(&mut self.sqlite, server_uri, &uuid, progress)?)
+    }
+
+    fn sync_status(&mut self) -> Result<SyncStatus> {
+        Ok(Syncer::status(&mut self.sqlite)?)
+    }
 }
 
 impl Conn {
@@ -788,6 +2337,7 @@ impl Conn {
              current.schema.clone(),
              current.attribute_cache.clone())
         };
+        let value_attributes = self.tx_observer_service.lock().unwrap().value_observed_attributes();
 
         Ok(InProgress {
             mutex: &self.metadata,
@@ -799,6 +2349,8 @@ impl Conn {
             use_caching: true,
             tx_observer: &self.tx_observer_service,
             tx_observer_watcher: InProgressObserverTransactWatcher::new(),
+            value_attributes,
+            value_changes: BTreeMap::new(),
         })
     }
 
@@ -889,6 +2441,30 @@ impl Conn {
         self.tx_observer_service.lock().unwrap().deregister(key);
     }
 
+    pub fn register_schema_observer(&mut self, key: String, observer: Arc<SchemaObserver>) {
+        self.tx_observer_service.lock().unwrap().register_schema_observer(key, observer);
+    }
+
+    pub fn unregister_schema_observer(&mut self, key: &String) {
+        self.tx_observer_service.lock().unwrap().deregister_schema_observer(key);
+    }
+
+    pub fn register_value_observer(&mut self, key: String, observer: Arc<ValueObserver>) {
+        self.tx_observer_service.lock().unwrap().register_value_observer(key, observer);
+    }
+
+    pub fn unregister_all_observers(&mut self) {
+        self.tx_observer_service.lock().unwrap().deregister_all();
+    }
+
+    pub fn observer_keys(&self) -> Vec<String> {
+        self.tx_observer_service.lock().unwrap().observer_keys()
+    }
+
+    pub fn unregister_value_observer(&mut self, key: &String) {
+        self.tx_observer_service.lock().unwrap().deregister_value_observer(key);
+    }
+
     // TODO: expose the entity builder over FFI and remove the need for this function entirely
     // It's really only here in order to keep the FFI layer as thin as possible.
     // Once the entity builder is exposed, we can perform all of these functions over FFI from the client.
@@ -900,6 +2476,15 @@ impl Conn {
                .map_err(|e| e.into())
                .and(Ok(()))
     }
+
+    pub fn retract_datom<T>(&mut self, sqlite: &mut rusqlite::Connection, entid: T, attribute: NamespacedKeyword, value: TypedValue) -> Result<()> where T: Into<KnownEntid> {
+        let in_progress = self.begin_transaction(sqlite)?;
+        let mut builder = in_progress.builder().describe(entid.into());
+        builder.retract_kw(&attribute, value)?;
+        builder.commit()
+               .map_err(|e| e.into())
+               .and(Ok(()))
+    }
 }
 
 #[cfg(test)]
@@ -1538,6 +3123,1022 @@ mod tests {
         assert!(!conn.is_registered_as_observer(&key));
     }
 
+    #[test]
+    fn test_transact_and_fetch() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+
+        let values = store.transact_and_fetch(r#"[[:db/add "t" :foo/name "Alice"]]"#,
+                                              "t",
+                                              &[kw!(:foo/name), kw!(:foo/age)])
+                          .expect("transacted and fetched");
+        assert_eq!(values, vec![Some(TypedValue::typed_string("Alice")), None]);
+
+        match store.transact_and_fetch(r#"[[:db/add "t" :foo/name "Bob"]]"#, "x", &[kw!(:foo/name)]).unwrap_err() {
+            Error(ErrorKind::UnknownTempId(name), _) => assert_eq!(name, "x"),
+            x => panic!("expected UnknownTempId error, got {:?}", x),
+        }
+
+        // The failed transaction was rolled back.
+        let bobs = store.q_once(r#"[:find ?e . :where [?e :foo/name "Bob"]]"#, None)
+                        .expect("query")
+                        .into_scalar()
+                        .expect("scalar");
+        assert_eq!(bobs, None);
+    }
+
+    #[test]
+    fn test_pull() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/name "Alice"]
+            [:db/add "a" :foo/age 30]
+            [:db/add "b" :foo/name "Bob"]
+        ]"#).expect("data");
+        let a = report.tempids["a"];
+        let b = report.tempids["b"];
+
+        let rows = store.pull(&[a, b], &[kw!(:foo/name), kw!(:foo/age)]).expect("pulled");
+        assert_eq!(rows, vec![
+            vec![Some(TypedValue::typed_string("Alice")), Some(TypedValue::Long(30))],
+            vec![Some(TypedValue::typed_string("Bob")), None],
+        ]);
+    }
+
+    #[test]
+    fn test_export_entity() {
+        let schema = r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/colour :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+            {:db/ident :foo/red}
+        ]"#;
+        let mut store = Store::open("").expect("store");
+        store.transact(schema).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/name "Alice \"A\""]
+            [:db/add "a" :foo/bio "Likes long walks"]
+            [:db/add "a" :foo/colour :foo/red]
+            [:db/add "a" :foo/friend "b"]
+            [:db/add "b" :foo/name "Bob"]
+            [:db/add "b" :foo/friend "c"]
+            [:db/add "c" :foo/name "Carol"]
+        ]"#).expect("data");
+        let a = report.tempids["a"];
+
+        let exported = store.export_entity(a, 1).expect("exported");
+
+        let mut other = Store::open("").expect("store");
+        other.transact(schema).expect("schema");
+        let report = other.transact(exported.as_str()).expect("imported");
+        let a = report.tempids["e0"];
+        let b = report.tempids["e1"];
+        assert_eq!(report.tempids.len(), 2);
+
+        assert_eq!(other.lookup_value_for_attribute(a, &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Alice \"A\"")));
+        assert_eq!(other.lookup_value_for_attribute(a, &kw!(:foo/bio)).expect("value"),
+                   Some(TypedValue::typed_string("Likes long walks")));
+        assert_eq!(other.lookup_value_for_attribute(a, &kw!(:foo/colour)).expect("value"),
+                   other.conn().current_schema().get_entid(&kw!(:foo/red)).map(|e| TypedValue::Ref(e.0)));
+        assert_eq!(other.lookup_value_for_attribute(a, &kw!(:foo/friend)).expect("value"),
+                   Some(TypedValue::Ref(b)));
+        assert_eq!(other.lookup_value_for_attribute(b, &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Bob")));
+        // Carol is two refs away, so Bob's friend was dropped.
+        assert_eq!(other.lookup_value_for_attribute(b, &kw!(:foo/friend)).expect("value"), None);
+    }
+
+    #[test]
+    fn test_kw_ref() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/colour :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/red}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[[:db/add "a" :foo/colour :foo/red]]"#).expect("data");
+        let a = report.tempids["a"];
+
+        match store.assert_kw_ref(KnownEntid(a), kw!(:foo/shade), &kw!(:foo/red)).unwrap_err() {
+            Error(ErrorKind::UnknownAttribute(name), _) => assert_eq!(name, ":foo/shade"),
+            x => panic!("expected UnknownAttribute error, got {:?}", x),
+        }
+        match store.assert_kw_ref(KnownEntid(a), kw!(:foo/colour), &kw!(:foo/blue)).unwrap_err() {
+            Error(ErrorKind::UnknownIdent(name), _) => assert_eq!(name, ":foo/blue"),
+            x => panic!("expected UnknownIdent error, got {:?}", x),
+        }
+
+        store.retract_kw_ref(KnownEntid(a), kw!(:foo/colour), &kw!(:foo/red)).expect("retracted");
+        assert_eq!(store.lookup_value_for_attribute(a, &kw!(:foo/colour)).expect("value"), None);
+    }
+
+    #[test]
+    fn test_retract_attribute() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/tag :db/valueType :db.type/string :db/cardinality :db.cardinality/many}
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/name "Alice"]
+            [:db/add "a" :foo/tag "x"]
+            [:db/add "a" :foo/tag "y"]
+            [:db/add "a" :foo/tag "z"]
+        ]"#).expect("data");
+        let a = report.tempids["a"];
+
+        assert_eq!(store.retract_attribute(a, &kw!(:foo/tag)).expect("retracted"), 3);
+        assert_eq!(store.lookup_values_for_attribute(a, &kw!(:foo/tag)).expect("values"), vec![]);
+        assert_eq!(store.lookup_value_for_attribute(a, &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Alice")));
+
+        assert_eq!(store.retract_attribute(a, &kw!(:foo/tag)).expect("retracted"), 0);
+    }
+
+    #[test]
+    fn test_entities_in_range() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/priority :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/priority 3]
+            [:db/add "b" :foo/priority 5]
+            [:db/add "c" :foo/priority 7]
+            [:db/add "d" :foo/priority 9]
+        ]"#).expect("data");
+        let (a, b, c) = (report.tempids["a"], report.tempids["b"], report.tempids["c"]);
+
+        let mut inclusive = store.entities_in_range(&kw!(:foo/priority), TypedValue::Long(3), TypedValue::Long(7), true).expect("entities");
+        inclusive.sort();
+        assert_eq!(inclusive, vec![a, b, c]);
+
+        let exclusive = store.entities_in_range(&kw!(:foo/priority), TypedValue::Long(3), TypedValue::Long(7), false).expect("entities");
+        assert_eq!(exclusive, vec![b]);
+    }
+
+    #[test]
+    fn test_entid_has_attribute() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :place/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "p" :person/name "Alice"]
+            [:db/add "q" :place/name "Paris"]
+        ]"#).expect("data");
+        let (p, q) = (report.tempids["p"], report.tempids["q"]);
+
+        assert!(store.entid_has_attribute(p, &kw!(:person/name)).expect("checked"));
+        assert!(!store.entid_has_attribute(q, &kw!(:person/name)).expect("checked"));
+        match store.entid_has_attribute(p, &kw!(:person/nmae)).unwrap_err() {
+            Error(ErrorKind::UnknownAttribute(name), _) => assert_eq!(name, ":person/nmae"),
+            x => panic!("expected UnknownAttribute error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_file_info() {
+        let store = Store::open("").expect("store");
+        let info = store.file_info().expect("info");
+        assert_eq!(info.path, None);
+        assert_eq!(info.db_size, 0);
+
+        let path = ::std::env::temp_dir().join(format!("mentat-file-info-{}.db", Uuid::new_v4()));
+        let path_str = path.to_str().expect("path").to_string();
+        {
+            let store = Store::open(&path_str).expect("store");
+            let info = store.file_info().expect("info");
+            assert_eq!(info.path.and_then(|p| p.file_name().map(|n| n.to_owned())),
+                       path.file_name().map(|n| n.to_owned()));
+            assert!(info.db_size > 0);
+        }
+        for suffix in &["", "-wal", "-shm"] {
+            let _ = ::std::fs::remove_file(format!("{}{}", path_str, suffix));
+        }
+    }
+
+    #[test]
+    fn test_transaction_validator() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+
+        // Allow at most one tempid per transaction.
+        store.set_transaction_validator(Some(|report: &TxReport| report.tempids.len() <= 1));
+        store.transact(r#"[[:db/add "a" :foo/name "Alice"]]"#).expect("accepted");
+        match store.transact(r#"[[:db/add "b" :foo/name "Bob"] [:db/add "c" :foo/name "Carol"]]"#).unwrap_err() {
+            Error(ErrorKind::TransactionVetoed, _) => {},
+            x => panic!("expected TransactionVetoed error, got {:?}", x),
+        }
+
+        let names = store.q_once("[:find [?n ...] :where [_ :foo/name ?n]]", None).expect("names").into_coll().expect("coll");
+        assert_eq!(names, vec![TypedValue::typed_string("Alice")]);
+
+        store.set_transaction_validator(None::<fn(&TxReport) -> bool>);
+        store.transact(r#"[[:db/add "b" :foo/name "Bob"] [:db/add "c" :foo/name "Carol"]]"#).expect("accepted");
+    }
+
+    #[test]
+    fn test_open_with_version() {
+        let path = ::std::env::temp_dir().join(format!("mentat-version-{}.db", Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+
+        {
+            let mut store = Store::open(&path).expect("opened");
+            let mut in_progress = store.begin_transaction().expect("began");
+            in_progress.ensure_vocabulary(&Definition::new(
+                kw!(:org.mozilla/foo),
+                3,
+                vec![
+                    (kw!(:org.mozilla/bar),
+                    AttributeBuilder::helpful()
+                        .value_type(ValueType::Long)
+                        .multival(false)
+                        .build()),
+                ])).expect("ensured");
+            in_progress.commit().expect("committed");
+            store.close().expect("closed");
+        }
+
+        Store::open_with_version(&path, 1, 3).expect("supported").close().expect("closed");
+        match Store::open_with_version(&path, 1, 2) {
+            Err(Error(ErrorKind::UnsupportedVocabularyVersion(name, version, min, max), _)) => {
+                assert_eq!((name.as_str(), version, min, max), (":org.mozilla/foo", 3, 1, 2));
+            },
+            Err(e) => panic!("expected UnsupportedVocabularyVersion error, got {:?}", e),
+            Ok(_) => panic!("expected UnsupportedVocabularyVersion error"),
+        }
+
+        ::std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn test_schema_observer() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+
+        let (tx, rx): (mpsc::Sender<BTreeSet<NamespacedKeyword>>, mpsc::Receiver<BTreeSet<NamespacedKeyword>>) = mpsc::channel();
+        let thread_tx = Mutex::new(tx);
+        let observer = Arc::new(SchemaObserver::new(move |_key, attributes| {
+            thread_tx.lock().unwrap().send(attributes.clone()).unwrap();
+        }));
+        store.register_schema_observer("schema".to_string(), observer);
+
+        // Data-only transactions don't notify.
+        store.transact(r#"[[:db/add "a" :foo/name "Alice"]]"#).expect("data");
+        store.transact(r#"[
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            [:db/add :foo/name :db/index true]
+        ]"#).expect("schema");
+
+        let attributes = rx.recv_timeout(Duration::from_secs(1)).expect("notified");
+        assert_eq!(attributes, vec![kw!(:foo/age), kw!(:foo/name)].into_iter().collect());
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_open_or_recover() {
+        let dir = ::std::env::temp_dir();
+        let path = dir.join(format!("mentat-recover-{}.db", Uuid::new_v4())).to_str().expect("path").to_string();
+        let backup = format!("{}.bak", path);
+
+        // An intact store is opened as usual.
+        {
+            let mut store = Store::open(&path).expect("opened");
+            store.transact(r#"[
+                {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            store.close().expect("closed");
+        }
+        let (store, recovered) = Store::open_or_recover(&path, &backup).expect("opened");
+        assert!(!recovered);
+        assert!(store.conn().current_schema().identifies_attribute(&kw!(:foo/name)));
+        store.close().expect("closed");
+
+        // One that can't be checked because another connection holds it exclusively is an error,
+        // and is left alone.
+        {
+            let holder = rusqlite::Connection::open(&path).expect("opened");
+            holder.execute_batch("PRAGMA locking_mode = EXCLUSIVE; BEGIN EXCLUSIVE;").expect("locked");
+            assert!(Store::open_or_recover(&path, &backup).is_err());
+            assert!(Path::new(&path).exists());
+            assert!(!Path::new(&backup).exists());
+        }
+        let (store, recovered) = Store::open_or_recover(&path, &backup).expect("opened");
+        assert!(!recovered);
+        assert!(store.conn().current_schema().identifies_attribute(&kw!(:foo/name)));
+        store.close().expect("closed");
+
+        // A corrupt one is moved aside and replaced.
+        {
+            use std::io::Write;
+            let mut file = File::create(&path).expect("created");
+            file.write_all(b"this is not a database, just some bytes that are long enough").expect("written");
+        }
+        assert!(Store::open(&path).is_err());
+        let (store, recovered) = Store::open_or_recover(&path, &backup).expect("recovered");
+        assert!(recovered);
+        assert!(!store.conn().current_schema().identifies_attribute(&kw!(:foo/name)));
+        assert!(Path::new(&backup).exists());
+        store.close().expect("closed");
+
+        ::std::fs::remove_file(&path).expect("removed");
+        ::std::fs::remove_file(&backup).expect("removed");
+    }
+
+    #[test]
+    fn test_value_observer() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let a = store.transact(r#"[[:db/add "a" :foo/name "Alice"]]"#).expect("data").tempids["a"];
+        let name = store.conn().current_schema().get_entid(&kw!(:foo/name)).expect("entid").0;
+
+        let (tx, rx): (mpsc::Sender<Vec<ValueChange>>, mpsc::Receiver<Vec<ValueChange>>) = mpsc::channel();
+        let thread_tx = Mutex::new(tx);
+        let observer = Arc::new(ValueObserver::new(name, move |_key, changes| {
+            thread_tx.lock().unwrap().send(changes.to_vec()).unwrap();
+        }));
+        store.register_value_observer("name".to_string(), observer);
+
+        // Changes to other attributes don't notify.
+        store.transact(&format!("[[:db/add {} :foo/age 30]]", a)).expect("age");
+        store.transact(&format!("[[:db/add {} :foo/name \"Alicia\"]]", a)).expect("renamed");
+
+        let changes = rx.recv_timeout(Duration::from_secs(1)).expect("notified");
+        assert_eq!(changes, vec![(a, Some(TypedValue::typed_string("Alice")), Some(TypedValue::typed_string("Alicia")))]);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        store.unregister_value_observer(&"name".to_string());
+        store.transact(&format!("[[:db/retract {} :foo/name \"Alicia\"]]", a)).expect("retracted");
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_transact_reader() {
+        let mut store = Store::open("").expect("store");
+        let edn = r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#;
+        store.transact_reader(::std::io::Cursor::new(edn.as_bytes())).expect("schema");
+        let report = store.transact_reader(&br#"[[:db/add "a" :foo/name "Alice"]]"#[..]).expect("data");
+        assert_eq!(store.lookup_value_for_attribute(report.tempids["a"], &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Alice")));
+
+        // Not UTF-8.
+        assert!(store.transact_reader(&[0xffu8, 0xfe][..]).is_err());
+
+        // Several transactions are applied one after another, and committed together.
+        let edn = r#"[[:db/add "b" :foo/name "Bob"]]
+                     [[:db/add "c" :foo/name "Carol"]]"#;
+        let report = store.transact_reader(edn.as_bytes()).expect("data");
+        assert_eq!(store.lookup_value_for_attribute(report.tempids["c"], &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Carol")));
+        let names = store.q_once(r#"[:find [?n ...] :where [_ :foo/name ?n]]"#, None)
+                         .expect("query")
+                         .into_coll()
+                         .expect("coll");
+        assert_eq!(names.len(), 3);
+
+        // If one fails, none are committed.
+        let edn = r#"[[:db/add "d" :foo/name "Dave"]]
+                     [[:db/add "e" :foo/unknown "Eve"]]"#;
+        match store.transact_reader(edn.as_bytes()) {
+            Err(Error(ErrorKind::BatchTransactionFailed(1, _), _)) => {},
+            x => panic!("expected BatchTransactionFailed, got {:?}", x),
+        }
+        let names = store.q_once(r#"[:find [?n ...] :where [_ :foo/name ?n]]"#, None)
+                         .expect("query")
+                         .into_coll()
+                         .expect("coll");
+        assert_eq!(names.len(), 3);
+
+        assert!(store.transact_reader(&b""[..]).is_err());
+    }
+
+    #[test]
+    fn test_transact_many() {
+        let mut store = Store::open("").expect("store");
+        let report = store.transact_many(&[
+            r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#,
+            r#"[[:db/add "a" :foo/name "Alice"]]"#,
+        ]).expect("batch");
+        assert_eq!(store.lookup_value_for_attribute(report.tempids["a"], &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Alice")));
+
+        // A failure anywhere rolls back the whole batch.
+        match store.transact_many(&[
+            r#"[[:db/add "b" :foo/name "Bob"]]"#,
+            r#"[[:db/add "c" :foo/unknown "Carol"]]"#,
+        ]).unwrap_err() {
+            Error(ErrorKind::BatchTransactionFailed(1, cause), _) => {
+                match *cause {
+                    ErrorKind::DbError(_) => {},
+                    x => panic!("expected the failing transaction's DbError, got {:?}", x),
+                }
+            },
+            x => panic!("expected BatchTransactionFailed error, got {:?}", x),
+        }
+        let names = store.q_once("[:find [?n ...] :where [_ :foo/name ?n]]", None).expect("query")
+                         .into_coll().expect("coll");
+        assert_eq!(names, vec![TypedValue::typed_string("Alice")]);
+
+        match store.transact_many::<&str>(&[]).unwrap_err() {
+            Error(ErrorKind::EmptyTransactionBatch, _) => {},
+            x => panic!("expected EmptyTransactionBatch error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_validate_transaction() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+
+        store.validate_transaction(r#"[[:db/add "a" :foo/name "Alice"]]"#).expect("valid");
+        let names = store.q_once("[:find [?n ...] :where [_ :foo/name ?n]]", None).expect("query")
+                         .into_coll().expect("coll");
+        assert!(names.is_empty());
+
+        assert!(store.validate_transaction(r#"[[:db/add "a" :foo/name 5]]"#).is_err());
+        assert!(store.validate_transaction(r#"[[:db/add "a" :foo/unknown "Alice"]]"#).is_err());
+        assert!(store.validate_transaction(r#"[[:db/add "a" :foo/name"#).is_err());
+
+        store.set_transaction_validator(Some(|_: &TxReport| false));
+        match store.validate_transaction(r#"[[:db/add "a" :foo/name "Alice"]]"#).unwrap_err() {
+            Error(ErrorKind::TransactionVetoed, _) => (),
+            x => panic!("expected TransactionVetoed error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_import_edn_forms() {
+        let mut store = Store::open("").expect("store");
+        let edn = r#"
+            ; The schema, then one transaction per person.
+            [{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]
+            [[:db/add "a" :foo/name "Alice ]"]] [[:db/add "b" :foo/name "Bob \" ;"]]
+            [[:db/add "c"
+              :foo/name "Carol"]]
+        "#;
+        assert_eq!(store.import_edn_forms(edn.as_bytes(), 2).expect("imported"), 4);
+        let mut names = store.q_once("[:find [?n ...] :where [_ :foo/name ?n]]", None).expect("query")
+                             .into_coll().expect("coll");
+        names.sort();
+        assert_eq!(names, vec![TypedValue::typed_string("Alice ]"),
+                               TypedValue::typed_string("Bob \" ;"),
+                               TypedValue::typed_string("Carol")]);
+
+        // Earlier commits stay; the error counts from the start of the input.
+        let edn = r#"[[:db/add "d" :foo/name "Dan"]] [[:db/add "e" :foo/name "Eve"]] [[:db/add "f" :foo/unknown "Fay"]]"#;
+        match store.import_edn_forms(edn.as_bytes(), 2).unwrap_err() {
+            Error(ErrorKind::BatchTransactionFailed(index, _), _) => assert_eq!(index, 2),
+            x => panic!("expected BatchTransactionFailed error, got {:?}", x),
+        }
+        assert!(store.q_once(r#"[:find ?e . :where [?e :foo/name "Eve"]]"#, None).expect("query")
+                     .into_scalar().expect("scalar").is_some());
+
+        assert!(store.import_edn_forms("[[:db/add".as_bytes(), 2).is_err());
+    }
+
+    #[test]
+    fn test_export_edn() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+            {:db/ident :foo/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/colour :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        store.transact(r#"[
+            [:db/add "r" :db/ident :foo/red]
+            [:db/add "a" :foo/name "Alice"]
+            [:db/add "a" :foo/colour "r"]
+            [:db/add "a" :foo/friend "b"]
+            [:db/add "b" :foo/name "Bob"]
+        ]"#).expect("data");
+
+        let mut exported = vec![];
+        let written = store.export_edn(&mut exported, false).expect("exported");
+        let exported = String::from_utf8(exported).expect("UTF-8");
+        assert_eq!(written, exported.matches("[:db/add").count());
+        assert!(!exported.contains(":db/ident :db/ident"));
+
+        let mut other = Store::open("").expect("store");
+        assert_eq!(other.import_edn_forms(exported.as_bytes(), 1).expect("imported"), 2);
+        let results = other.q_once(r#"[:find [?name ?friend ?colour]
+                                       :where [?a :foo/colour ?c]
+                                              [?c :db/ident ?colour]
+                                              [?a :foo/name ?name]
+                                              [?a :foo/friend ?b]
+                                              [?b :foo/name ?friend]]"#, None)
+                           .expect("query").into_tuple().expect("tuple");
+        assert_eq!(results, Some(vec![TypedValue::typed_string("Alice"),
+                                      TypedValue::typed_string("Bob"),
+                                      TypedValue::Keyword(kw!(:foo/red).into())]));
+    }
+
+    #[test]
+    fn test_transactions_since() {
+        let mut store = Store::open("").expect("store");
+        let schema = store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+        ]"#).expect("schema");
+        let first = store.transact(r#"[[:db/add "a" :foo/name "Alice"]]"#).expect("first");
+        let alice = first.tempids.get("a").cloned().expect("a");
+        let second = store.transact(&format!("[[:db/add {} :foo/name \"Alicia\"]]", alice)).expect("second");
+
+        let log = store.transactions_since(schema.tx_id).expect("log");
+        assert_eq!(log.iter().map(|t| t.tx).collect::<Vec<_>>(), vec![first.tx_id, second.tx_id]);
+        assert_eq!(log[0].instant, first.tx_instant);
+        assert_eq!(log[1].instant, second.tx_instant);
+
+        let name = store.conn.current_schema().get_entid(&kw!(:foo/name)).expect("foo/name").0;
+        let changes: Vec<(TypedValue, bool)> = log[1].datoms.iter()
+                                                           .filter(|&&(e, a, _, _)| e == alice && a == name)
+                                                           .map(|&(_, _, ref v, added)| (v.clone(), added))
+                                                           .collect();
+        assert_eq!(changes, vec![(TypedValue::typed_string("Alice"), false),
+                                 (TypedValue::typed_string("Alicia"), true)]);
+
+        assert!(store.transactions_since(second.tx_id).expect("log").is_empty());
+    }
+
+    #[test]
+    fn test_set_journal_mode() {
+        let path = ::std::env::temp_dir().join(format!("mentat-journal-{}.db", Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+        {
+            let mut store = Store::open(&path).expect("store");
+            assert_eq!(store.set_journal_mode(JournalMode::Delete).expect("delete"), JournalMode::Delete);
+            store.transact(r#"[
+                {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            assert_eq!(store.set_journal_mode(JournalMode::Wal).expect("wal"), JournalMode::Wal);
+        }
+        ::std::fs::remove_file(&path).expect("removed");
+
+        let mut store = Store::open("").expect("store");
+        assert_eq!(store.set_journal_mode(JournalMode::Wal).expect("wal"), JournalMode::Memory);
+    }
+
+    #[test]
+    fn test_set_busy_timeout() {
+        let path = ::std::env::temp_dir().join(format!("mentat-busy-{}.db", Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+        {
+            let mut store = Store::open(&path).expect("store");
+            store.transact(r#"[
+                {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+
+            // Another connection takes the write lock and holds it for a while.
+            let (locked_tx, locked_rx) = mpsc::channel();
+            let holder_path = path.clone();
+            let holder = ::std::thread::spawn(move || {
+                let conn = rusqlite::Connection::open(&holder_path).expect("connection");
+                conn.execute_batch("BEGIN IMMEDIATE").expect("locked");
+                locked_tx.send(()).expect("sent");
+                ::std::thread::sleep(Duration::from_millis(200));
+                conn.execute_batch("COMMIT").expect("unlocked");
+            });
+            locked_rx.recv().expect("received");
+
+            // Without a timeout the write fails at once, long before the lock is released.
+            store.set_busy_timeout(Duration::from_millis(0)).expect("timeout");
+            let start = Instant::now();
+            assert!(store.transact(r#"[[:db/add "e" :foo/n 1]]"#).is_err());
+            assert!(start.elapsed() < Duration::from_millis(100));
+
+            // With one, it waits for the lock to be released and then succeeds.
+            store.set_busy_timeout(Duration::from_secs(5)).expect("timeout");
+            store.transact(r#"[[:db/add "e" :foo/n 2]]"#).expect("waited");
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            holder.join().expect("joined");
+        }
+        ::std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn test_retract_by_lookup() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/id :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity}
+            {:db/ident :foo/tag :db/valueType :db.type/string :db/cardinality :db.cardinality/many}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/id "ext-1"]
+            [:db/add "a" :foo/tag "red"]
+            [:db/add "a" :foo/tag "blue"]
+        ]"#).expect("data");
+        let a = report.tempids["a"];
+
+        store.retract_by_lookup(&kw!(:foo/id), &TypedValue::typed_string("ext-1"),
+                                &kw!(:foo/tag), &TypedValue::typed_string("red")).expect("retracted");
+        assert_eq!(store.lookup_values_for_attribute(a, &kw!(:foo/tag)).expect("values"),
+                   vec![TypedValue::typed_string("blue")]);
+
+        // No such entity.
+        assert!(store.retract_by_lookup(&kw!(:foo/id), &TypedValue::typed_string("ext-2"),
+                                        &kw!(:foo/tag), &TypedValue::typed_string("blue")).is_err());
+        // Not a unique attribute.
+        assert!(store.retract_by_lookup(&kw!(:foo/tag), &TypedValue::typed_string("blue"),
+                                        &kw!(:foo/tag), &TypedValue::typed_string("blue")).is_err());
+
+        assert_eq!(store.resolve_lookup_ref(&kw!(:foo/id), &TypedValue::typed_string("ext-1")).expect("resolved"), Some(a));
+        assert_eq!(store.resolve_lookup_ref(&kw!(:foo/id), &TypedValue::typed_string("ext-2")).expect("resolved"), None);
+        match store.resolve_lookup_ref(&kw!(:foo/tag), &TypedValue::typed_string("blue")).unwrap_err() {
+            Error(ErrorKind::NotUniqueAttribute(name), _) => assert_eq!(name, ":foo/tag"),
+            x => panic!("expected NotUniqueAttribute error, got {:?}", x),
+        }
+        assert!(store.resolve_lookup_ref(&kw!(:foo/id), &TypedValue::Long(1)).is_err());
+
+        store.assert_by_lookup(&kw!(:foo/id), &TypedValue::typed_string("ext-1"),
+                               &kw!(:foo/tag), &TypedValue::typed_string("green")).expect("asserted");
+        let mut tags = store.lookup_values_for_attribute(a, &kw!(:foo/tag)).expect("values");
+        tags.sort();
+        assert_eq!(tags, vec![TypedValue::typed_string("blue"), TypedValue::typed_string("green")]);
+        assert!(store.assert_by_lookup(&kw!(:foo/id), &TypedValue::typed_string("ext-2"),
+                                       &kw!(:foo/tag), &TypedValue::typed_string("green")).is_err());
+    }
+
+    #[test]
+    fn test_integrity_check() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/friend "b"]
+            [:db/add "b" :db/doc "b"]
+        ]"#).expect("data");
+        let (a, b) = (report.tempids["a"], report.tempids["b"]);
+        let friend = store.conn().current_schema().get_entid(&kw!(:foo/friend)).expect("entid").0;
+
+        let check = store.integrity_check().expect("checked");
+        assert!(check.is_ok(), "{:?}", check.problems());
+
+        // Simulate a ref whose target was lost.
+        store.sqlite.execute("DELETE FROM datoms WHERE e = ?", &[&b]).expect("deleted");
+        let check = store.integrity_check().expect("checked");
+        assert!(!check.is_ok());
+        assert_eq!(check.dangling_refs, vec![(a, friend, b)]);
+        assert!(check.unknown_attributes.is_empty());
+        assert_eq!(check.problems().len(), 1);
+    }
+
+    #[test]
+    fn test_store_reader() {
+        assert!(Store::open("").expect("store").reader().is_err());
+
+        let path = ::std::env::temp_dir().join(format!("mentat-reader-{}.db", Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+        {
+            let mut store = Store::open(&path).expect("store");
+            store.transact(r#"[
+                {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            store.transact(r#"[[:db/add "a" :foo/n 1]]"#).expect("data");
+
+            let reader = store.reader().expect("reader");
+            let (read_tx, read_rx) = mpsc::channel();
+            let (wrote_tx, wrote_rx) = mpsc::channel::<()>();
+            let handle = ::std::thread::spawn(move || {
+                // `TypedValue` isn't `Send`, so hand back plain numbers.
+                let count = |reader: &StoreReader| reader.q_once("[:find (count ?e) . :where [?e :foo/n _]]", None)
+                                                         .expect("results").into_scalar().expect("scalar")
+                                                         .and_then(|v| v.into_long());
+                read_tx.send(count(&reader)).unwrap();
+                wrote_rx.recv().unwrap();
+                count(&reader)
+            });
+
+            assert_eq!(read_rx.recv().expect("read"), Some(1));
+            store.transact(r#"[[:db/add "b" :foo/n 2]]"#).expect("data");
+            wrote_tx.send(()).unwrap();
+            assert_eq!(handle.join().expect("joined"), Some(2));
+
+            // The reader refuses to write.
+            let reader = store.reader().expect("reader");
+            assert!(reader.sqlite.execute("DELETE FROM datoms", &[]).is_err());
+        }
+        ::std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn test_q_cancellable() {
+        let path = ::std::env::temp_dir().join(format!("mentat-cancel-{}.db", Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+        {
+            let mut store = Store::open(&path).expect("store");
+            store.transact(r#"[
+                {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            let data: Vec<String> = (0..1000).map(|i| format!("[:db/add \"e{}\" :foo/n {}]", i, i)).collect();
+            store.transact(&format!("[{}]", data.join(" "))).expect("data");
+
+            let quick = store.q_cancellable("[:find (count ?e) . :where [?e :foo/n _]]", None).expect("prepared");
+            assert_eq!(quick.run().expect("results").into_scalar().expect("scalar"), Some(TypedValue::Long(1000)));
+
+            // A billion-row join won't finish before it's cancelled.
+            let slow = store.q_cancellable("[:find (count ?a) . :with ?b ?c :where [?a :foo/n _] [?b :foo/n _] [?c :foo/n _]]", None).expect("prepared");
+            let interrupt = slow.interrupt();
+            let canceller = ::std::thread::spawn(move || {
+                ::std::thread::sleep(Duration::from_millis(50));
+                interrupt.cancel();
+            });
+            match slow.run().unwrap_err() {
+                Error(ErrorKind::QueryCancelled, _) => {},
+                x => panic!("expected QueryCancelled error, got {:?}", x),
+            }
+            canceller.join().expect("joined");
+
+            // A cancelled query stays cancelled.
+            assert!(slow.run().is_err());
+        }
+        ::std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn test_sync_cancellable() {
+        let store = Store::open("").expect("store");
+        match store.sync_cancellable(&"http://localhost:1".to_string(), &Uuid::new_v4().hyphenated().to_string()) {
+            Err(Error(ErrorKind::InMemoryStore, _)) => {},
+            x => panic!("expected InMemoryStore error, got {:?}", x.map(|_| ())),
+        }
+
+        let path = ::std::env::temp_dir().join(format!("mentat-sync-cancel-{}.db", Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+        {
+            let store = Store::open(&path).expect("store");
+            let mut sync = store.sync_cancellable(&"http://localhost:1".to_string(), &Uuid::new_v4().hyphenated().to_string())
+                                .expect("prepared");
+
+            // A sync cancelled before it runs never contacts the server.
+            sync.interrupt().cancel();
+            match sync.run().unwrap_err() {
+                Error(ErrorKind::SyncError(::mentat_tolstoy::ErrorKind::SyncCancelled), _) => {},
+                x => panic!("expected SyncCancelled error, got {:?}", x),
+            }
+        }
+        ::std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn test_entity_attributes() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/email :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/name "Alice"]
+            [:db/add "a" :foo/age 30]
+        ]"#).expect("data");
+        let a = report.tempids["a"];
+
+        let values = store.entity_attributes(a, &[kw!(:foo/age), kw!(:foo/email), kw!(:foo/name)]).expect("values");
+        assert_eq!(values, vec![Some(TypedValue::Long(30)), None, Some(TypedValue::typed_string("Alice"))]);
+
+        match store.entity_attributes(a, &[kw!(:foo/age), kw!(:foo/nmae)]).unwrap_err() {
+            Error(ErrorKind::UnknownAttribute(name), _) => assert_eq!(name, ":foo/nmae"),
+            x => panic!("expected UnknownAttribute error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_pull_attributes() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+            {:db/ident :foo/tag :db/valueType :db.type/string :db/cardinality :db.cardinality/many}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/name "Alice"]
+            [:db/add "a" :foo/tag "red"]
+            [:db/add "a" :foo/tag "blue"]
+        ]"#).expect("data");
+        let a = report.tempids["a"];
+
+        let mut pulled = store.pull_attributes(a, &[kw!(:foo/tag), kw!(:foo/age)]).expect("pulled");
+        pulled.sort();
+        assert_eq!(pulled, vec![(kw!(:foo/tag), TypedValue::typed_string("blue")),
+                                (kw!(:foo/tag), TypedValue::typed_string("red"))]);
+
+        let all = store.pull_all_attributes(a).expect("pulled");
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0], (kw!(:foo/name), TypedValue::typed_string("Alice")));
+
+        assert!(store.pull_attributes(a, &[kw!(:foo/nmae)]).is_err());
+
+        let b = store.transact(r#"[[:db/add "b" :foo/age 40]]"#).expect("data").tempids["b"];
+        let many = store.pull_attributes_many(&[b, a, b], &[kw!(:foo/name), kw!(:foo/age)]).expect("pulled");
+        assert_eq!(many, vec![vec![(kw!(:foo/age), TypedValue::Long(40))],
+                              vec![(kw!(:foo/name), TypedValue::typed_string("Alice"))],
+                              vec![(kw!(:foo/age), TypedValue::Long(40))]]);
+    }
+
+    #[test]
+    fn test_transact_json() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/born :db/valueType :db.type/instant :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+
+        let report = store.transact_json(r#"[
+            {"e": "a", "a": ":foo/name", "v": "Alice \"Al\" [:db/add]"},
+            {"e": "a", "a": ":foo/friend", "v": "b"},
+            {"e": "b", "a": ":foo/born", "v": "2018-01-01T00:00:00Z"}
+        ]"#).expect("transacted");
+        let (a, b) = (report.tempids["a"], report.tempids["b"]);
+        assert_eq!(store.lookup_value_for_attribute(a, &kw!(:foo/name)).expect("name"),
+                   Some(TypedValue::typed_string("Alice \"Al\" [:db/add]")));
+        assert_eq!(store.lookup_value_for_attribute(a, &kw!(:foo/friend)).expect("friend"), Some(TypedValue::Ref(b)));
+
+        store.transact_json(&format!(r#"[{{"op": "retract", "e": {}, "a": ":foo/friend", "v": {}}}]"#, a, b)).expect("retracted");
+        assert_eq!(store.lookup_value_for_attribute(a, &kw!(:foo/friend)).expect("friend"), None);
+
+        match store.transact_json(r#"[{"e": "c", "a": ":foo/name", "v": "ok"}, {"e": "c", "a": ":foo/born", "v": true}]"#).unwrap_err() {
+            Error(ErrorKind::InvalidJsonTransaction(message), _) => assert!(message.starts_with("row 1:"), "{}", message),
+            x => panic!("expected InvalidJsonTransaction error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_sync_status_before_first_sync() {
+        let mut store = Store::open("").expect("store connection");
+        let status = store.sync_status().expect("status");
+        assert_eq!(status.last_sync, None);
+        assert_eq!(status.remote_head, Uuid::nil());
+
+        // The bootstrap transaction has never been uploaded.
+        assert_eq!(status.local_head, None);
+        assert!(status.has_local_changes);
+    }
+
+    #[test]
+    fn test_retract_entity() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+            {:db/ident :foo/tag :db/valueType :db.type/string :db/cardinality :db.cardinality/many}
+            {:db/ident :foo/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[
+            [:db/add "a" :foo/name "Alice"]
+            [:db/add "a" :foo/tag "red"]
+            [:db/add "a" :foo/tag "blue"]
+            [:db/add "b" :foo/friend "a"]
+        ]"#).expect("data");
+        let (a, b) = (report.tempids["a"], report.tempids["b"]);
+
+        store.retract_entity(a).expect("retracted");
+        assert_eq!(store.entity_attributes(a, &[kw!(:foo/name), kw!(:foo/tag)]).expect("values"), vec![None, None]);
+
+        // Inbound refs are the caller's business.
+        assert_eq!(store.lookup_value_for_attribute(b, &kw!(:foo/friend)).expect("value"), Some(TypedValue::Ref(a)));
+    }
+
+    #[test]
+    fn test_flush_and_unregister_all_observers() {
+        let path = ::std::env::temp_dir().join(format!("mentat-flush-{}.db", Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+        {
+            let mut store = Store::open(&path).expect("store");
+            store.transact(r#"[
+                {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            assert!(store.file_info().expect("info").wal_size > 0);
+
+            // A reader in the middle of a transaction keeps the log from being truncated.
+            {
+                let reader = rusqlite::Connection::open(&path).expect("reader");
+                reader.execute_batch("BEGIN; SELECT count(*) FROM datoms;").expect("reading");
+                store.set_busy_timeout(Duration::from_millis(0)).expect("timeout");
+                match store.flush().unwrap_err() {
+                    Error(ErrorKind::CheckpointBlocked, _) => {},
+                    x => panic!("expected CheckpointBlocked error, got {:?}", x),
+                }
+            }
+            store.flush().expect("flushed");
+            assert_eq!(store.file_info().expect("info").wal_size, 0);
+
+            let observer = Arc::new(TxObserver::new(BTreeSet::new(), |_key, _batch| {}));
+            store.register_observer("tx".to_string(), observer.clone());
+            store.register_schema_observer("schema".to_string(), Arc::new(SchemaObserver::new(|_key, _attributes| {})));
+            assert_eq!(store.observer_keys(), vec!["tx".to_string(), "schema".to_string()]);
+            assert_eq!(Arc::strong_count(&observer), 2);
+            store.unregister_all_observers();
+            assert!(!store.is_registered_as_observer(&"tx".to_string()));
+            assert!(store.observer_keys().is_empty());
+            assert_eq!(Arc::strong_count(&observer), 1);
+            store.close().expect("closed");
+        }
+        ::std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn test_alias_attribute() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/title :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = store.transact(r#"[[:db/add "a" :foo/title "Hello"]]"#).expect("data");
+        let a = report.tempids["a"];
+
+        assert!(store.lookup_value_for_attribute(a, &kw!(:foo/name)).is_err());
+
+        match store.alias_attribute(kw!(:foo/name), kw!(:foo/heading)).unwrap_err() {
+            Error(ErrorKind::UnknownAttribute(name), _) => assert_eq!(name, ":foo/heading"),
+            x => panic!("expected UnknownAttribute error, got {:?}", x),
+        }
+
+        store.alias_attribute(kw!(:foo/name), kw!(:foo/title)).expect("aliased");
+        assert_eq!(store.lookup_value_for_attribute(a, &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Hello")));
+
+        // The schema itself is untouched.
+        assert!(store.conn().current_schema().get_entid(&kw!(:foo/name)).is_none());
+    }
+
+    #[test]
+    fn test_close_and_reopen() {
+        let path = ::std::env::temp_dir().join(format!("mentat-close-{}.db", Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+
+        let entid = {
+            let mut store = Store::open(&path).expect("opened");
+            store.transact(r#"[
+                {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            ]"#).expect("schema");
+            let report = store.transact(r#"[[:db/add "a" :foo/name "Alice"]]"#).expect("data");
+            store.close().expect("closed");
+            report.tempids["a"]
+        };
+
+        let store = Store::open(&path).expect("reopened");
+        assert_eq!(store.lookup_value_for_attribute(entid, &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Alice")));
+        store.close().expect("closed");
+
+        ::std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn test_clear_user_data() {
+        let mut store = Store::open("").expect("store");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}
+            {:db/ident :foo/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+        ]"#).expect("schema");
+        store.transact(r#"[
+            [:db/add "a" :foo/name "Alice"]
+            [:db/add "b" :foo/name "Bob"]
+            [:db/add "a" :foo/friend "b"]
+            [:db/add "a" :foo/bio "Likes long walks"]
+        ]"#).expect("data");
+
+        store.clear_user_data().expect("cleared");
+
+        let bios = store.q_once(r#"[:find [?b ...] :where [_ :foo/bio ?b]]"#, None)
+                        .expect("query")
+                        .into_coll()
+                        .expect("coll");
+        assert_eq!(bios, vec![]);
+
+        let names = store.q_once(r#"[:find [?n ...] :where [_ :foo/name ?n]]"#, None)
+                         .expect("query")
+                         .into_coll()
+                         .expect("coll");
+        assert_eq!(names, vec![]);
+
+        // The schema survives, so we can keep using it.
+        assert!(store.conn().current_schema().identifies_attribute(&kw!(:foo/friend)));
+        let report = store.transact(r#"[[:db/add "c" :foo/name "Carol"]]"#).expect("transacted after clearing");
+        assert_eq!(store.lookup_value_for_attribute(report.tempids["c"], &kw!(:foo/name)).expect("value"),
+                   Some(TypedValue::typed_string("Carol")));
+    }
+
     fn add_schema(conn: &mut Store) {
         // transact some schema
         let mut in_progress = conn.begin_transaction().expect("expected in progress");
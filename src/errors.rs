@@ -31,6 +31,9 @@ use mentat_sql;
 use mentat_tolstoy;
 use mentat_tx_parser;
 
+pub use mentat_db::ErrorKind as DbErrorKind;
+pub use mentat_tolstoy::ErrorKind as SyncErrorKind;
+
 error_chain! {
     types {
         Error, ErrorKind, ResultExt, Result;
@@ -70,11 +73,91 @@ error_chain! {
             display("invalid argument name: '{}'", name)
         }
 
+        NoSuchColumn(column: i64) {
+            description("no such column")
+            display("column {} is not in every row", column)
+        }
+
+        OrderByUnknownVariable(name: String) {
+            description("cannot order by a variable that isn't in :find")
+            display("cannot order by {}: it isn't a variable in :find", name)
+        }
+
+        InvalidUuid(input: String) {
+            description("invalid uuid")
+            display("invalid uuid: {}", input)
+        }
+
+        InvalidKeyword(input: String) {
+            description("invalid keyword")
+            display("invalid keyword: '{}'; expected :namespace/name", input)
+        }
+
+        NotUniqueAttribute(name: String) {
+            description("attribute is not :db/unique")
+            display("{} is not :db/unique", name)
+        }
+
+        InvalidUtf8(message: String) {
+            description("invalid UTF-8")
+            display("invalid UTF-8 in string from caller: {}", message)
+        }
+
+        InvalidJsonTransaction(message: String) {
+            description("invalid JSON transaction")
+            display("invalid JSON transaction: {}", message)
+        }
+
         UnknownAttribute(name: String) {
             description("unknown attribute")
             display("unknown attribute: '{}'", name)
         }
 
+        InvalidJournalMode(mode: String) {
+            description("invalid journal mode")
+            display("invalid journal mode: '{}'", mode)
+        }
+
+        QueryCancelled {
+            description("query cancelled")
+            display("query cancelled before it finished")
+        }
+
+        InMemoryStore {
+            description("operation requires an on-disk store")
+            display("this operation isn't possible for an in-memory store")
+        }
+
+        TransactionVetoed {
+            description("transaction vetoed")
+            display("transaction rejected by the store's validator")
+        }
+
+        CheckpointBlocked {
+            description("checkpoint blocked")
+            display("checkpoint blocked by another connection")
+        }
+
+        BatchTransactionFailed(index: usize, cause: Box<ErrorKind>) {
+            description("transaction in batch failed")
+            display("transaction {} of the batch failed: {}", index, cause)
+        }
+
+        EmptyTransactionBatch {
+            description("empty transaction batch")
+            display("no transactions to transact")
+        }
+
+        UnknownIdent(name: String) {
+            description("unknown ident")
+            display("unknown ident: '{}'", name)
+        }
+
+        UnknownTempId(name: String) {
+            description("unknown tempid")
+            display("tempid '{}' was not resolved by the transaction", name)
+        }
+
         InvalidVocabularyVersion {
             description("invalid vocabulary version")
             display("invalid vocabulary version")
@@ -90,6 +173,11 @@ error_chain! {
             display("existing vocabulary too new: wanted {}, got {}", ours, existing)
         }
 
+        UnsupportedVocabularyVersion(name: String, version: ::vocabulary::Version, min: ::vocabulary::Version, max: ::vocabulary::Version) {
+            description("unsupported vocabulary version")
+            display("vocabulary {} is at version {}, outside the supported range {}..={}", name, version, min, max)
+        }
+
         UnexpectedCoreSchema(version: Option<::vocabulary::Version>) {
             description("unexpected core schema version")
             display("core schema: wanted {}, got {:?}", mentat_db::CORE_SCHEMA_VERSION, version)
@@ -100,11 +188,26 @@ error_chain! {
             display("missing core attribute {}", kw)
         }
 
+        CannotCountAggregateQuery {
+            description("cannot count the rows of an aggregate query")
+            display("cannot count the rows of a query that aggregates; use (count ...) in :find instead")
+        }
+
         PreparedQuerySchemaMismatch {
             description("schema changed since query was prepared")
             display("schema changed since query was prepared")
         }
 
+        UnexpectedValueType(expected: ValueType, provided: ValueType) {
+            description("unexpected value type")
+            display("expected {}, got {}", expected, provided)
+        }
+
+        CannotCoerce(provided: ValueType, requested: ValueType) {
+            description("value cannot be coerced to the requested type")
+            display("value of type {} cannot be coerced to type {}", provided, requested)
+        }
+
         ValueTypeMismatch(provided: ValueType, expected: ValueType) {
             description("provided value doesn't match value type")
             display("provided value of type {} doesn't match attribute value type {}", provided, expected)
@@ -18,6 +18,8 @@ extern crate lazy_static;
 
 extern crate rusqlite;
 
+extern crate serde_json;
+
 extern crate uuid;
 
 pub extern crate edn;
@@ -54,8 +56,11 @@ pub use mentat_query::{
 pub use mentat_db::{
     CORE_SCHEMA_VERSION,
     DB_SCHEMA_CORE,
+    SchemaObserver,
     TxObserver,
     TxReport,
+    ValueChange,
+    ValueObserver,
     new_connection,
 };
 
@@ -106,13 +111,18 @@ pub use query::{
     QueryExplanation,
     QueryInputs,
     QueryOutput,
+    QueryPlan,
     QueryPlanStep,
     QueryResults,
+    QueryTiming,
     Variable,
     q_once,
 };
 
 pub use query_builder::{
+    BindInputs,
+    CachedQuery,
+    QueryBindings,
     QueryBuilder,
 };
 
@@ -121,10 +131,25 @@ pub use conn::{
     CacheDirection,
     Conn,
     InProgress,
+    InProgressRead,
+    IntegrityReport,
+    JournalMode,
+    LoggedTransaction,
     Metadata,
+    QueryHandle,
+    QueryInterrupt,
     Queryable,
     Syncable,
     Store,
+    StoreFileInfo,
+    StoreReader,
+    SyncHandle,
+    SyncInterrupt,
+};
+
+pub use mentat_tolstoy::{
+    SyncPhase,
+    SyncStatus,
 };
 
 #[cfg(test)]
@@ -11,8 +11,14 @@
 use rusqlite;
 use rusqlite::types::ToSql;
 
+use std::fmt;
 use std::rc::Rc;
 
+use std::time::{
+    Duration,
+    Instant,
+};
+
 use mentat_core::{
     Entid,
     HasSchema,
@@ -38,13 +44,17 @@ pub use mentat_query::{
 };
 
 use mentat_query::{
+    Binding,
     Element,
     FindQuery,
     FindSpec,
+    FnArg,
+    NonIntegerConstant,
     Pattern,
     PatternNonValuePlace,
     PatternValuePlace,
     WhereClause,
+    WhereFn,
 };
 
 use mentat_query_parser::{
@@ -115,6 +125,41 @@ impl<'sqlite> PreparedQuery<'sqlite> {
     }
 }
 
+/// A query that has been algebrized and translated to SQL for one set of bindings, ready to run
+/// any number of times. Unlike `PreparedQuery` it holds no statement, so it can outlive the read
+/// it was made in; each run takes the statement from the connection's statement cache.
+pub enum QueryPlan {
+    Empty {
+        find_spec: Rc<FindSpec>,
+    },
+    Constant {
+        select: ConstantProjector,
+    },
+    Bound {
+        sql: String,
+        args: Vec<(String, Rc<rusqlite::types::Value>)>,
+        projector: Box<Projector>,
+    },
+}
+
+impl QueryPlan {
+    pub fn run(&self, sqlite: &rusqlite::Connection) -> QueryExecutionResult {
+        match self {
+            &QueryPlan::Empty { ref find_spec } => {
+                Ok(QueryOutput::empty(find_spec))
+            },
+            &QueryPlan::Constant { ref select } => {
+                select.project_without_rows().map_err(|e| e.into())
+            },
+            &QueryPlan::Bound { ref sql, ref args, ref projector } => {
+                let mut statement = sqlite.prepare_cached(sql.as_str())?;
+                let rows = run_statement(&mut statement, args)?;
+                projector.project(rows).map_err(|e| e.into())
+            },
+        }
+    }
+}
+
 pub trait IntoResult {
     fn into_scalar_result(self) -> Result<Option<TypedValue>>;
     fn into_coll_result(self) -> Result<Vec<TypedValue>>;
@@ -157,6 +202,58 @@ pub enum QueryExplanation {
     },
 }
 
+/// Wall-clock time spent in each phase of running a query, as measured by
+/// `q_once_parsed_timed`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QueryTiming {
+    /// Parsing, algebrizing, translating to SQL, and preparing the SQLite statement.
+    pub prepare: Duration,
+    /// Running the statement and projecting its rows into results.
+    pub execute: Duration,
+    /// The number of results produced.
+    pub rows: usize,
+}
+
+impl fmt::Display for QueryExplanation {
+    /// Render the explanation as text: the SQL, its bindings, and the plan with its columns
+    /// aligned.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &QueryExplanation::KnownConstant => write!(f, "Query is known constant!"),
+            &QueryExplanation::KnownEmpty(ref empty_because) => write!(f, "Query is known empty: {:?}", empty_because),
+            &QueryExplanation::ExecutionPlan { ref query, ref steps } => {
+                writeln!(f, "SQL: {}", query.sql)?;
+                if !query.args.is_empty() {
+                    writeln!(f, "  Bindings:")?;
+                    for &(ref arg_name, ref value) in query.args.iter() {
+                        writeln!(f, "    {} = {:?}", arg_name, **value)?;
+                    }
+                }
+
+                write!(f, "Plan: select id | order | from | detail")?;
+                // Compute the number of columns we need for order, select id, and from,
+                // so that longer query plans don't become misaligned.
+                let (max_select_id, max_order, max_from) = steps.iter().fold((0, 0, 0), |acc, step|
+                    (acc.0.max(step.select_id), acc.1.max(step.order), acc.2.max(step.from)));
+                // This is less efficient than computing it via the logarithm base 10,
+                // but it's clearer and doesn't have require special casing "0"
+                let max_select_digits = max_select_id.to_string().len();
+                let max_order_digits = max_order.to_string().len();
+                let max_from_digits = max_from.to_string().len();
+                for step in steps {
+                    // Note: > is right align.
+                    write!(f, "\n  {:>sel_cols$}|{:>ord_cols$}|{:>from_cols$}|{}",
+                           step.select_id, step.order, step.from, step.detail,
+                           sel_cols = max_select_digits,
+                           ord_cols = max_order_digits,
+                           from_cols = max_from_digits)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
 /// A single row in the output of SQLite's `EXPLAIN QUERY PLAN`.
 /// See https://www.sqlite.org/eqp.html for an explanation of each field.
 pub struct QueryPlanStep {
@@ -206,7 +303,7 @@ fn fetch_values<'sqlite>
 
     let algebrized = algebrize_query(known, query, None)?;
 
-    run_algebrized_query(sqlite, algebrized)
+    run_algebrized_query(sqlite, algebrized, 0, None)
 }
 
 fn lookup_attribute(schema: &Schema, attribute: &NamespacedKeyword) -> Result<KnownEntid> {
@@ -319,7 +416,9 @@ fn algebrize_query_str<'query, T>
     algebrize_query(known, parsed, inputs)
 }
 
-fn run_algebrized_query<'sqlite>(sqlite: &'sqlite rusqlite::Connection, algebrized: AlgebraicQuery) -> QueryExecutionResult {
+/// If `prepared` is given, it's set to the moment the SQL statement was ready to run; it's left
+/// alone if the query needed no SQL.
+fn run_algebrized_query<'sqlite>(sqlite: &'sqlite rusqlite::Connection, algebrized: AlgebraicQuery, expected_rows: usize, prepared: Option<&mut Option<Instant>>) -> QueryExecutionResult {
     assert!(algebrized.unbound_variables().is_empty(),
             "Unbound variables should be checked by now");
     if algebrized.is_known_empty() {
@@ -335,9 +434,12 @@ fn run_algebrized_query<'sqlite>(sqlite: &'sqlite rusqlite::Connection, algebriz
             let SQLQuery { sql, args } = query.to_sql_query()?;
 
             let mut statement = sqlite.prepare(sql.as_str())?;
+            if let Some(prepared) = prepared {
+                *prepared = Some(Instant::now());
+            }
             let rows = run_statement(&mut statement, &args)?;
 
-            projector.project(rows).map_err(|e| e.into())
+            projector.project_with_capacity(rows, expected_rows).map_err(|e| e.into())
         },
     }
 }
@@ -357,7 +459,122 @@ pub fn q_once<'sqlite, 'query, T>
         where T: Into<Option<QueryInputs>>
 {
     let algebrized = algebrize_query_str(known, query, inputs)?;
-    run_algebrized_query(sqlite, algebrized)
+    run_algebrized_query(sqlite, algebrized, 0, None)
+}
+
+/// Just like `q_once`, but takes an already-parsed query. This is useful when the caller has
+/// rewritten the query after parsing it -- see `ground_collection_input`.
+pub fn q_once_parsed<'sqlite, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ known: Known,
+ query: FindQuery,
+ inputs: T) -> QueryExecutionResult
+        where T: Into<Option<QueryInputs>>
+{
+    let algebrized = algebrize_query(known, query, inputs)?;
+    run_algebrized_query(sqlite, algebrized, 0, None)
+}
+
+/// Just like `q_once_parsed`, but reserves room for `expected_rows` rows when projecting a
+/// relation. This only saves allocations; the results are the same whatever the estimate.
+pub fn q_once_parsed_hinted<'sqlite, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ known: Known,
+ query: FindQuery,
+ inputs: T,
+ expected_rows: usize) -> QueryExecutionResult
+        where T: Into<Option<QueryInputs>>
+{
+    let algebrized = algebrize_query(known, query, inputs)?;
+    run_algebrized_query(sqlite, algebrized, expected_rows, None)
+}
+
+/// Just like `q_once_parsed_hinted`, but records in `timing` how long each phase took. The time
+/// taken here is added to `timing.prepare`, so callers may first record their own parsing.
+pub fn q_once_parsed_timed<'sqlite, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ known: Known,
+ query: FindQuery,
+ inputs: T,
+ expected_rows: usize,
+ timing: &mut QueryTiming) -> QueryExecutionResult
+        where T: Into<Option<QueryInputs>>
+{
+    let started = Instant::now();
+    let algebrized = algebrize_query(known, query, inputs)?;
+    let mut prepared = None;
+    let output = run_algebrized_query(sqlite, algebrized, expected_rows, Some(&mut prepared))?;
+    let finished = Instant::now();
+    let prepared = prepared.unwrap_or(finished);
+    timing.prepare += prepared - started;
+    timing.execute = finished - prepared;
+    timing.rows = output.len();
+    Ok(output)
+}
+
+/// Count the rows `query` would return without fetching them, by wrapping its SQL in
+/// `count(*)`. A query that aggregates is an error: the count of its rows is rarely what the
+/// caller wants, so they should count in the query instead.
+pub fn q_count_parsed<'sqlite, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ known: Known,
+ query: FindQuery,
+ inputs: T) -> Result<i64>
+        where T: Into<Option<QueryInputs>>
+{
+    let aggregates = query.find_spec.columns().any(|e| match e {
+        &Element::Aggregate(_) => true,
+        _ => false,
+    });
+    if aggregates {
+        bail!(ErrorKind::CannotCountAggregateQuery);
+    }
+
+    let algebrized = algebrize_query(known, query, inputs)?;
+    if algebrized.is_known_empty() {
+        return Ok(0);
+    }
+    match query_to_select(algebrized)? {
+        ProjectedSelect::Constant(constant) => Ok(constant.project_without_rows()?.len() as i64),
+        ProjectedSelect::Query { query, projector: _projector } => {
+            let SQLQuery { sql, args } = query.to_sql_query()?;
+            let count_sql = format!("SELECT count(*) FROM ({})", sql);
+            let counts = run_sql_query(sqlite, &count_sql, &args, |row| row.get(0))?;
+            Ok(counts.into_iter().next().unwrap_or(0))
+        },
+    }
+}
+
+fn typed_value_to_fn_arg(value: TypedValue) -> FnArg {
+    match value {
+        TypedValue::Ref(x) => FnArg::EntidOrInteger(x),
+        TypedValue::Long(x) => FnArg::EntidOrInteger(x),
+        TypedValue::Boolean(x) => FnArg::Constant(NonIntegerConstant::Boolean(x)),
+        TypedValue::Double(x) => FnArg::Constant(NonIntegerConstant::Float(x)),
+        TypedValue::Instant(x) => FnArg::Constant(NonIntegerConstant::Instant(x)),
+        TypedValue::String(x) => FnArg::Constant(NonIntegerConstant::Text(x)),
+        TypedValue::Uuid(x) => FnArg::Constant(NonIntegerConstant::Uuid(x)),
+        TypedValue::Keyword(x) => FnArg::IdentOrKeyword((*x).clone()),
+    }
+}
+
+/// `QueryInputs` can only bind scalars. To bind an `:in` variable to a collection of values, we
+/// remove it from `:in` and instead append `[(ground [...]) [?var ...]]` to the `:where` clauses.
+/// The clause goes last so that the algebrizer already knows the variable's type when it grounds
+/// it: refs and longs are indistinguishable in a ground vector.
+///
+/// `values` must not be empty; the caller should short-circuit to an empty result instead.
+pub fn ground_collection_input(query: &mut FindQuery, var: Variable, values: Vec<TypedValue>) -> Result<()> {
+    if !query.in_vars.remove(&var) {
+        bail!(ErrorKind::InvalidArgumentName(var.to_string()));
+    }
+    let args = vec![FnArg::Vector(values.into_iter().map(typed_value_to_fn_arg).collect())];
+    query.where_clauses.push(WhereClause::WhereFn(WhereFn {
+        operator: PlainSymbol::new("ground"),
+        args: args,
+        binding: Binding::BindColl(var),
+    }));
+    Ok(())
 }
 
 /// Just like `q_once`, but doesn't use any cached values.
@@ -371,7 +588,7 @@ pub fn q_uncached<'sqlite, 'schema, 'query, T>
     let known = Known::for_schema(schema);
     let algebrized = algebrize_query_str(known, query, inputs)?;
 
-    run_algebrized_query(sqlite, algebrized)
+    run_algebrized_query(sqlite, algebrized, 0, None)
 }
 
 pub fn q_prepare<'sqlite, 'query, T>
@@ -417,6 +634,38 @@ pub fn q_prepare<'sqlite, 'query, T>
     }
 }
 
+/// Algebrize and translate `query` with `inputs` into a `QueryPlan`, without running it. Every
+/// `:in` variable must be bound.
+pub fn q_plan<T>
+(known: Known,
+ query: FindQuery,
+ inputs: T) -> Result<QueryPlan>
+        where T: Into<Option<QueryInputs>>
+{
+    let algebrized = algebrize_query(known, query, inputs)?;
+    if algebrized.is_known_empty() {
+        return Ok(QueryPlan::Empty {
+            find_spec: algebrized.find_spec,
+        });
+    }
+
+    match query_to_select(algebrized)? {
+        ProjectedSelect::Constant(constant) => {
+            Ok(QueryPlan::Constant {
+                select: constant,
+            })
+        },
+        ProjectedSelect::Query { query, projector } => {
+            let SQLQuery { sql, args } = query.to_sql_query()?;
+            Ok(QueryPlan::Bound {
+                sql,
+                args,
+                projector,
+            })
+        },
+    }
+}
+
 pub fn q_explain<'sqlite, 'query, T>
 (sqlite: &'sqlite rusqlite::Connection,
  known: Known,
@@ -13,18 +13,48 @@ use std::collections::{
     BTreeMap,
 };
 
+use std::rc::Rc;
+
+use std::sync::Arc;
+
+use std::time::Instant;
+
 use mentat_core::{
     Entid,
     NamespacedKeyword,
+    Schema,
     TypedValue,
     ValueType,
 };
 
+use mentat_query::{
+    Direction,
+    FindQuery,
+    FindSpec,
+    Limit,
+    Order,
+};
+
+use mentat_query_parser::{
+    parse_find_string,
+};
+
+use query::{
+    QueryPlan,
+    QueryTiming,
+    ground_collection_input,
+};
+
+use conn::{
+    InProgressRead,
+};
+
 use ::{
     HasSchema,
     Queryable,
     QueryInputs,
     QueryOutput,
+    QueryResults,
     Store,
     Variable,
 };
@@ -34,55 +64,353 @@ use errors::{
     Result,
 };
 
-pub struct QueryBuilder<'a> {
-    sql: String,
+/// The values and types bound to a query's `:in` variables.
+#[derive(Default)]
+pub struct QueryBindings {
     values: BTreeMap<Variable, TypedValue>,
     types: BTreeMap<Variable, ValueType>,
-    store: &'a mut Store,
+}
+
+/// Binding a query's `:in` variables, shared by `QueryBuilder` and `CachedQuery`. Each binding
+/// replaces any earlier one for the same variable.
+pub trait BindInputs {
+    /// The bindings to add to. Called once for each binding made.
+    fn bindings_mut(&mut self) -> &mut QueryBindings;
+
+    fn bind_value<T>(&mut self, var: &str, value: T) -> &mut Self where T: Into<TypedValue>, Self: Sized {
+        self.bindings_mut().values.insert(Variable::from_valid_name(var), value.into());
+        self
+    }
+
+    fn bind_ref<T>(&mut self, var: &str, value: T) -> &mut Self where T: Into<Entid>, Self: Sized {
+        self.bindings_mut().values.insert(Variable::from_valid_name(var), TypedValue::Ref(value.into()));
+        self
+    }
+
+    fn bind_long(&mut self, var: &str, value: i64) -> &mut Self where Self: Sized {
+        self.bindings_mut().values.insert(Variable::from_valid_name(var), TypedValue::Long(value));
+        self
+    }
+
+    fn bind_instant(&mut self, var: &str, value: i64) -> &mut Self where Self: Sized {
+        self.bindings_mut().values.insert(Variable::from_valid_name(var), TypedValue::instant(value));
+        self
+    }
+
+    fn bind_type(&mut self, var: &str, value_type: ValueType) -> &mut Self where Self: Sized {
+        self.bindings_mut().types.insert(Variable::from_valid_name(var), value_type);
+        self
+    }
+}
+
+/// What a `QueryBuilder` runs against: a store, in a read transaction of its own for each
+/// execution, or a read transaction shared with other queries.
+enum QuerySource<'a> {
+    Store(&'a mut Store),
+    Read(&'a InProgressRead<'a, 'a>),
+}
+
+pub struct QueryBuilder<'a> {
+    sql: String,
+    bindings: QueryBindings,
+    collections: BTreeMap<Variable, Vec<TypedValue>>,
+    order: Vec<Order>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+    source: QuerySource<'a>,
 }
 
 impl<'a> QueryBuilder<'a> {
     pub fn new<T>(store: &'a mut Store, sql: T) -> QueryBuilder where T: Into<String> {
-        QueryBuilder { sql: sql.into(), values: BTreeMap::new(), types: BTreeMap::new(), store }
+        QueryBuilder::with_source(QuerySource::Store(store), sql.into())
     }
 
-    pub fn bind_value<T>(&mut self, var: &str, value: T) -> &mut Self where T: Into<TypedValue> {
-        self.values.insert(Variable::from_valid_name(var), value.into());
-        self
+    /// Build a query that runs in `read`, so that it sees the same data as every other query
+    /// made in that read transaction.
+    pub fn for_read<T>(read: &'a InProgressRead<'a, 'a>, sql: T) -> QueryBuilder<'a> where T: Into<String> {
+        QueryBuilder::with_source(QuerySource::Read(read), sql.into())
+    }
+
+    fn with_source(source: QuerySource<'a>, sql: String) -> QueryBuilder<'a> {
+        QueryBuilder { sql, bindings: QueryBindings::default(), collections: BTreeMap::new(), order: vec![], limit: None, offset: None, source }
     }
 
     pub fn bind_ref_from_kw(&mut self, var: &str, value: NamespacedKeyword) -> Result<&mut Self> {
-        let entid = self.store.conn().current_schema().get_entid(&value).ok_or(ErrorKind::UnknownAttribute(value.to_string()))?;
-        self.values.insert(Variable::from_valid_name(var), TypedValue::Ref(entid.into()));
-        Ok(self)
+        let entid = match self.source {
+            QuerySource::Store(ref store) => store.conn().current_schema().get_entid(&value),
+            QuerySource::Read(read) => read.get_entid(&value),
+        }.ok_or(ErrorKind::UnknownAttribute(value.to_string()))?;
+        Ok(self.bind_ref(var, entid))
     }
 
-    pub fn bind_ref<T>(&mut self, var: &str, value: T) -> &mut Self where T: Into<Entid> {
-       self.values.insert(Variable::from_valid_name(var), TypedValue::Ref(value.into()));
-       self
+    /// Bind `var` to each of `values`, as if by `(ground [...]) [?var ...]`. `var` must still be
+    /// named in the query's `:in`.
+    pub fn bind_coll<T>(&mut self, var: &str, values: Vec<T>) -> &mut Self where T: Into<TypedValue> {
+        self.collections.insert(Variable::from_valid_name(var), values.into_iter().map(|v| v.into()).collect());
+        self
     }
 
-    pub fn bind_long(&mut self, var: &str, value: i64) -> &mut Self {
-       self.values.insert(Variable::from_valid_name(var), TypedValue::Long(value));
-       self
+    /// Order results by `var`. Each call adds a sort key after any ordering given in the query
+    /// text or by earlier calls. `var` must be one of the query's `:find` variables; an aggregate
+    /// of it doesn't count.
+    pub fn order_by(&mut self, var: &str, ascending: bool) -> Result<&mut Self> {
+        let var = Variable::from_valid_name(var);
+        if self.find_spec()?.column_index(&var).is_none() {
+            bail!(ErrorKind::OrderByUnknownVariable(var.to_string()));
+        }
+        let direction = if ascending { Direction::Ascending } else { Direction::Descending };
+        self.order.push(Order(direction, var));
+        Ok(self)
     }
 
-    pub fn bind_instant(&mut self, var: &str, value: i64) -> &mut Self {
-       self.values.insert(Variable::from_valid_name(var), TypedValue::instant(value));
-       self
+    /// Return at most `limit` rows. This replaces any `:limit` in the query text, and the last
+    /// call wins.
+    pub fn limit(&mut self, limit: u64) -> &mut Self {
+        self.limit = Some(limit);
+        self
     }
 
-    pub fn bind_type(&mut self, var: &str, value_type: ValueType) -> &mut Self {
-        self.types.insert(Variable::from_valid_name(var), value_type);
+    /// Skip the first `offset` rows. Without an `order_by`, which rows are skipped isn't
+    /// defined, so pages may overlap as the store changes.
+    pub fn offset(&mut self, offset: u64) -> &mut Self {
+        self.offset = Some(offset);
         self
     }
 
+    /// The query's find spec, which says whether it yields a scalar, a tuple, a collection or a
+    /// relation. This only parses the query; it doesn't run it.
+    pub fn find_spec(&self) -> Result<FindSpec> {
+        Ok(parse_find_string(&self.sql)?.find_spec)
+    }
+
+    /// The name of each column the query yields, in order, as it appears in `:find`: `?x` for a
+    /// variable, `(max ?x)` for an aggregate. Like `find_spec`, this doesn't run the query.
+    pub fn column_names(&self) -> Result<Vec<String>> {
+        Ok(self.find_spec()?.columns().map(|e| e.to_string()).collect())
+    }
+
     pub fn execute(&mut self) -> Result<QueryOutput> {
-        let values = ::std::mem::replace(&mut self.values, Default::default());
-        let types = ::std::mem::replace(&mut self.types, Default::default());
+        self.execute_hinted(0)
+    }
+
+    /// Like `execute`, but reserves room for `expected_rows` rows up front. For large relations
+    /// this avoids growing the result vector repeatedly; a poor estimate costs only memory.
+    pub fn execute_hinted(&mut self, expected_rows: usize) -> Result<QueryOutput> {
+        self.run(expected_rows, None)
+    }
+
+    /// Like `execute`, but also reports how long the query spent being prepared and executed, and
+    /// how many results it produced. Only this method pays for the measurements.
+    pub fn execute_with_timing(&mut self) -> Result<(QueryOutput, QueryTiming)> {
+        let mut timing = QueryTiming::default();
+        let output = self.run(0, Some(&mut timing))?;
+        Ok((output, timing))
+    }
+
+    fn run(&mut self, expected_rows: usize, timing: Option<&mut QueryTiming>) -> Result<QueryOutput> {
+        let started = timing.as_ref().map(|_| Instant::now());
+        let QueryBindings { values, types } = ::std::mem::replace(&mut self.bindings, Default::default());
+        let collections = ::std::mem::replace(&mut self.collections, Default::default());
+        let order = ::std::mem::replace(&mut self.order, Default::default());
+        let limit = self.limit.take();
+        let offset = self.offset.take();
+        let query_inputs = QueryInputs::new(types, values)?;
+        let own_read;
+        let read = match self.source {
+            QuerySource::Store(ref mut store) => {
+                own_read = store.begin_read()?;
+                &own_read
+            },
+            QuerySource::Read(read) => read,
+        };
+        if collections.is_empty() && order.is_empty() && limit.is_none() && offset.is_none() &&
+           expected_rows == 0 && timing.is_none() {
+            return read.q_once(&self.sql, query_inputs);
+        }
+
+        let mut query = parse_find_string(&self.sql)?;
+        if !order.is_empty() {
+            query.order.get_or_insert_with(Vec::new).extend(order);
+        }
+        if let Some(limit) = limit {
+            query.limit = Limit::Fixed(limit);
+        }
+        query.offset = offset;
+        let mut empty = false;
+        for (var, values) in collections {
+            if values.is_empty() {
+                // Binding to nothing can never match.
+                query.in_vars.remove(&var);
+                empty = true;
+            } else {
+                ground_collection_input(&mut query, var, values)?;
+            }
+        }
+        if empty {
+            if let (Some(timing), Some(started)) = (timing, started) {
+                timing.prepare = started.elapsed();
+            }
+            return Ok(QueryOutput::empty(&Rc::new(query.find_spec)));
+        }
+        match (timing, started) {
+            (Some(timing), Some(started)) => {
+                timing.prepare = started.elapsed();
+                read.q_once_parsed_timed(query, query_inputs, expected_rows, timing)
+            },
+            _ => read.q_once_parsed_hinted(query, query_inputs, expected_rows),
+        }
+    }
+
+    /// Count the rows the query would return, without fetching them. Queries that aggregate
+    /// can't be counted this way.
+    pub fn execute_count(&mut self) -> Result<i64> {
+        let QueryBindings { values, types } = ::std::mem::replace(&mut self.bindings, Default::default());
+        let collections = ::std::mem::replace(&mut self.collections, Default::default());
+        // Ordering doesn't change how many rows there are.
+        self.order.clear();
+        let limit = self.limit.take();
+        let offset = self.offset.take();
         let query_inputs = QueryInputs::new(types, values)?;
+
+        let mut query = parse_find_string(&self.sql)?;
+        if let Some(limit) = limit {
+            query.limit = Limit::Fixed(limit);
+        }
+        query.offset = offset;
+        for (var, values) in collections {
+            if values.is_empty() {
+                // Binding to nothing can never match.
+                return Ok(0);
+            }
+            ground_collection_input(&mut query, var, values)?;
+        }
+
+        let own_read;
+        let read = match self.source {
+            QuerySource::Store(ref mut store) => {
+                own_read = store.begin_read()?;
+                &own_read
+            },
+            QuerySource::Read(read) => read,
+        };
+        read.q_count_parsed(query, query_inputs)
+    }
+
+    pub fn execute_scalar(&mut self) -> Result<Option<TypedValue>> {
+        let results = self.execute()?;
+        results.into_scalar().map_err(|e| e.into())
+    }
+
+    pub fn execute_coll(&mut self) -> Result<Vec<TypedValue>> {
+        let results = self.execute()?;
+        results.into_coll().map_err(|e| e.into())
+    }
+
+    pub fn execute_tuple(&mut self) -> Result<Option<Vec<TypedValue>>> {
+        let results = self.execute()?;
+        results.into_tuple().map_err(|e| e.into())
+    }
+
+    pub fn execute_rel(&mut self) -> Result<Vec<Vec<TypedValue>>> {
+        let results = self.execute()?;
+        results.into_rel().map_err(|e| e.into())
+    }
+
+    /// Run the query and render the results as RFC 4180 CSV, with a header row naming each
+    /// column as it appears in `:find`.
+    pub fn execute_to_csv(&mut self) -> Result<String> {
+        let output = self.execute()?;
+        let header: Vec<String> = output.spec.columns().map(|e| csv_field(&e.to_string())).collect();
+        let rows: Vec<Vec<TypedValue>> = match output.results {
+            QueryResults::Scalar(v) => v.into_iter().map(|v| vec![v]).collect(),
+            QueryResults::Tuple(t) => t.into_iter().collect(),
+            QueryResults::Coll(vs) => vs.into_iter().map(|v| vec![v]).collect(),
+            QueryResults::Rel(rows) => rows,
+        };
+
+        let mut csv = header.join(",");
+        csv.push_str("\r\n");
+        for row in rows {
+            let fields: Vec<String> = row.iter().map(|v| csv_field(&typed_value_to_csv_text(v))).collect();
+            csv.push_str(&fields.join(","));
+            csv.push_str("\r\n");
+        }
+        Ok(csv)
+    }
+
+    /// Run the query and render the results as a JSON array with one object per row, keyed by
+    /// each column as it appears in `:find`. Refs, longs and doubles become numbers (non-finite
+    /// doubles become `null`), instants RFC 3339 strings, and keywords and UUIDs strings.
+    pub fn execute_to_json(&mut self) -> Result<String> {
+        let output = self.execute()?;
+        let columns: Vec<String> = output.spec.columns().map(|e| json_string(&e.to_string())).collect();
+        let rows: Vec<Vec<TypedValue>> = match output.results {
+            QueryResults::Scalar(v) => v.into_iter().map(|v| vec![v]).collect(),
+            QueryResults::Tuple(t) => t.into_iter().collect(),
+            QueryResults::Coll(vs) => vs.into_iter().map(|v| vec![v]).collect(),
+            QueryResults::Rel(rows) => rows,
+        };
+
+        let objects: Vec<String> = rows.iter().map(|row| {
+            let fields: Vec<String> = columns.iter()
+                                             .zip(row.iter())
+                                             .map(|(column, v)| format!("{}:{}", column, typed_value_to_json(v)))
+                                             .collect();
+            format!("{{{}}}", fields.join(","))
+        }).collect();
+        Ok(format!("[{}]", objects.join(",")))
+    }
+}
+
+impl<'a> BindInputs for QueryBuilder<'a> {
+    fn bindings_mut(&mut self) -> &mut QueryBindings {
+        &mut self.bindings
+    }
+}
+
+/// A query that is parsed once and can then be run any number of times against a store,
+/// rebinding its inputs between runs. Bindings persist from one execution to the next until
+/// they are replaced.
+///
+/// Bound values are folded into the generated SQL, so the query is algebrized and translated
+/// when it's first run with a new set of bindings, and that plan is reused, SQLite statement and
+/// all, until the bindings or the schema change.
+pub struct CachedQuery<'a> {
+    store: &'a mut Store,
+    query: FindQuery,
+    bindings: QueryBindings,
+    /// The plan for the current bindings, and the schema it was made against.
+    plan: Option<(Arc<Schema>, QueryPlan)>,
+}
+
+impl<'a> BindInputs for CachedQuery<'a> {
+    fn bindings_mut(&mut self) -> &mut QueryBindings {
+        self.plan = None;
+        &mut self.bindings
+    }
+}
+
+impl<'a> CachedQuery<'a> {
+    pub fn new<T>(store: &'a mut Store, sql: T) -> Result<CachedQuery<'a>> where T: AsRef<str> {
+        let query = parse_find_string(sql.as_ref())?;
+        Ok(CachedQuery { store, query, bindings: QueryBindings::default(), plan: None })
+    }
+
+    pub fn execute(&mut self) -> Result<QueryOutput> {
+        let schema = self.store.conn().current_schema();
+        let stale = match self.plan {
+            Some((ref planned_for, _)) => !Arc::ptr_eq(planned_for, &schema),
+            None => true,
+        };
         let read = self.store.begin_read()?;
-        read.q_once(&self.sql, query_inputs)
+        if stale {
+            let query_inputs = QueryInputs::new(self.bindings.types.clone(), self.bindings.values.clone())?;
+            self.plan = Some((schema, read.q_plan_parsed(self.query.clone(), query_inputs)?));
+        }
+        match self.plan {
+            Some((_, ref plan)) => read.run_plan(plan),
+            None => unreachable!(),
+        }
     }
 
     pub fn execute_scalar(&mut self) -> Result<Option<TypedValue>> {
@@ -106,14 +434,80 @@ impl<'a> QueryBuilder<'a> {
     }
 }
 
+fn typed_value_to_json(value: &TypedValue) -> String {
+    match value {
+        &TypedValue::Ref(x) => x.to_string(),
+        &TypedValue::Long(x) => x.to_string(),
+        &TypedValue::Boolean(x) => x.to_string(),
+        &TypedValue::Double(x) if x.0.is_finite() => x.0.to_string(),
+        &TypedValue::Double(_) => "null".to_string(),
+        &TypedValue::Instant(ref x) => json_string(&x.to_rfc3339()),
+        &TypedValue::String(ref x) => json_string(x),
+        &TypedValue::Keyword(ref x) => json_string(&x.to_string()),
+        &TypedValue::Uuid(ref x) => json_string(&x.hyphenated().to_string()),
+    }
+}
+
+/// Quote and escape `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn typed_value_to_csv_text(value: &TypedValue) -> String {
+    match value {
+        &TypedValue::Ref(x) => x.to_string(),
+        &TypedValue::Long(x) => x.to_string(),
+        &TypedValue::Boolean(x) => x.to_string(),
+        &TypedValue::Double(x) => x.0.to_string(),
+        &TypedValue::Instant(ref x) => x.to_rfc3339(),
+        &TypedValue::String(ref x) => x.to_string(),
+        &TypedValue::Keyword(ref x) => x.to_string(),
+        &TypedValue::Uuid(ref x) => x.hyphenated().to_string(),
+    }
+}
+
+/// Quote `text` if RFC 4180 requires it.
+fn csv_field(text: &str) -> String {
+    if text.contains(|c: char| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
     use super::{
+        BindInputs,
+        CachedQuery,
+        FindSpec,
         QueryBuilder,
+        QueryPlan,
         TypedValue,
         Store,
     };
 
+    use errors::{
+        Error,
+        ErrorKind,
+    };
+
     #[test]
     fn test_scalar_query() {
         let mut store = Store::open("").expect("store connection");
@@ -139,6 +533,32 @@ mod test {
         assert_eq!(entid, Some(yes));
     }
 
+    #[test]
+    fn test_for_read_sees_one_snapshot() {
+        let path = ::std::env::temp_dir().join(format!("mentat-snapshot-{}.db", ::uuid::Uuid::new_v4()));
+        let path = path.to_str().expect("path").to_string();
+        {
+            let mut store = Store::open(&path).expect("store connection");
+            store.transact(r#"[
+                [:db/add "s" :db/ident :foo/long]
+                [:db/add "s" :db/valueType :db.type/long]
+                [:db/add "s" :db/cardinality :db.cardinality/one]
+            ]"#).expect("successful transaction");
+            store.transact(r#"[[:db/add "l" :foo/long 1]]"#).expect("successful transaction");
+
+            let mut writer = Store::open(&path).expect("second connection");
+            let count = "[:find (count ?x) . :where [?x :foo/long _]]";
+
+            let read = store.begin_read().expect("read");
+            let before = QueryBuilder::for_read(&read, count).execute_scalar().expect("ScalarResult");
+            writer.transact(r#"[[:db/add "m" :foo/long 2]]"#).expect("successful transaction");
+            let after = QueryBuilder::for_read(&read, count).execute_scalar().expect("ScalarResult");
+            assert_eq!(before, Some(TypedValue::Long(1)));
+            assert_eq!(after, before);
+        }
+        ::std::fs::remove_file(&path).expect("removed");
+    }
+
     #[test]
     fn test_coll_query() {
         let mut store = Store::open("").expect("store connection");
@@ -386,4 +806,397 @@ mod test {
         assert_eq!(results.get(0).map_or(None, |t| t.to_owned().into_boolean()).expect("boolean"), true);
         assert_eq!(results.get(1).map_or(None, |t| t.to_owned().into_long()).expect("long"), 25);
     }
+
+    #[test]
+    fn test_execute_hinted() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "s" :db/ident :foo/long]
+            [:db/add "s" :db/valueType :db.type/long]
+            [:db/add "s" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+        store.transact(r#"[
+            [:db/add "l" :foo/long 25]
+            [:db/add "m" :foo/long 26]
+        ]"#).expect("successful transaction");
+
+        let query = r#"[:find ?x ?i :order ?i :where [?x :foo/long ?i]]"#;
+        let unhinted = QueryBuilder::new(&mut store, query).execute_rel().expect("RelResult");
+        let hinted = QueryBuilder::new(&mut store, query).execute_hinted(1000).expect("output")
+                                                          .into_rel().expect("RelResult");
+        assert_eq!(unhinted.len(), 2);
+        assert_eq!(hinted, unhinted);
+    }
+
+    #[test]
+    fn test_order_by() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "s" :db/ident :foo/boolean]
+            [:db/add "s" :db/valueType :db.type/boolean]
+            [:db/add "s" :db/cardinality :db.cardinality/one]
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+
+        store.transact(r#"[
+            [:db/add "l" :foo/boolean true]
+            [:db/add "l" :foo/long 25]
+            [:db/add "m" :foo/boolean false]
+            [:db/add "m" :foo/long 26]
+            [:db/add "n" :foo/boolean true]
+            [:db/add "n" :foo/long 27]
+        ]"#).expect("successful transaction");
+
+        let results = QueryBuilder::new(&mut store, r#"[:find ?v ?i
+                                                        :where [?x :foo/boolean ?v]
+                                                               [?x :foo/long ?i]]"#)
+                              .order_by("?v", true).expect("order")
+                              .order_by("?i", false).expect("order")
+                              .execute_rel().expect("RelResult");
+        assert_eq!(results, vec![
+            vec![TypedValue::Boolean(false), TypedValue::Long(26)],
+            vec![TypedValue::Boolean(true), TypedValue::Long(27)],
+            vec![TypedValue::Boolean(true), TypedValue::Long(25)],
+        ]);
+
+        // Ordering by a variable that isn't in `:find`, or only by an aggregate of one, is refused
+        // up front rather than failing, or silently not ordering, when the query runs.
+        let mut query_builder = QueryBuilder::new(&mut store, r#"[:find ?v (max ?i)
+                                                                  :where [?x :foo/boolean ?v]
+                                                                         [?x :foo/long ?i]]"#);
+        for var in &["?x", "?i", "?y"] {
+            match query_builder.order_by(var, true) {
+                Err(Error(ErrorKind::OrderByUnknownVariable(ref name), _)) => assert_eq!(name, var),
+                x => panic!("expected OrderByUnknownVariable, got {:?}", x.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_limit_and_offset() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+
+        store.transact(r#"[
+            [:db/add "l" :foo/long 25]
+            [:db/add "m" :foo/long 26]
+            [:db/add "n" :foo/long 27]
+            [:db/add "o" :foo/long 28]
+        ]"#).expect("successful transaction");
+
+        let results = QueryBuilder::new(&mut store, r#"[:find [?i ...] :where [_ :foo/long ?i]]"#)
+                              .order_by("?i", true).expect("order")
+                              .limit(2)
+                              .offset(1)
+                              .execute_coll().expect("CollResult");
+        assert_eq!(results, vec![TypedValue::Long(26), TypedValue::Long(27)]);
+
+        // An offset alone skips rows without limiting the rest.
+        let results = QueryBuilder::new(&mut store, r#"[:find [?i ...] :where [_ :foo/long ?i]]"#)
+                              .order_by("?i", false).expect("order")
+                              .offset(2)
+                              .execute_coll().expect("CollResult");
+        assert_eq!(results, vec![TypedValue::Long(26), TypedValue::Long(25)]);
+
+        // The builder's limit replaces the query's.
+        let results = QueryBuilder::new(&mut store, r#"[:find [?i ...] :where [_ :foo/long ?i] :order ?i :limit 1]"#)
+                              .limit(3)
+                              .execute_coll().expect("CollResult");
+        assert_eq!(results, vec![TypedValue::Long(25), TypedValue::Long(26), TypedValue::Long(27)]);
+    }
+
+    #[test]
+    fn test_find_spec() {
+        let mut store = Store::open("").expect("store connection");
+        let kinds: Vec<&str> = vec![
+            "[:find ?x . :where [?x :db/ident _]]",
+            "[:find [?x ?y] :where [?x :db/ident ?y]]",
+            "[:find [?x ...] :where [?x :db/ident _]]",
+            "[:find ?x ?y :where [?x :db/ident ?y]]",
+        ].into_iter().map(|query| {
+            match QueryBuilder::new(&mut store, query).find_spec().expect("find spec") {
+                FindSpec::FindScalar(_) => "scalar",
+                FindSpec::FindTuple(_) => "tuple",
+                FindSpec::FindColl(_) => "coll",
+                FindSpec::FindRel(_) => "rel",
+            }
+        }).collect();
+        assert_eq!(kinds, vec!["scalar", "tuple", "coll", "rel"]);
+
+        assert!(QueryBuilder::new(&mut store, "[:find ?x :where").find_spec().is_err());
+    }
+
+    #[test]
+    fn test_column_names() {
+        let mut store = Store::open("").expect("store connection");
+        let names = QueryBuilder::new(&mut store, "[:find ?ident ?e :where [?e :db/ident ?ident]]")
+                        .column_names().expect("column names");
+        assert_eq!(names, vec!["?ident".to_string(), "?e".to_string()]);
+
+        let names = QueryBuilder::new(&mut store, "[:find [?e ...] :where [?e :db/ident _]]")
+                        .column_names().expect("column names");
+        assert_eq!(names, vec!["?e".to_string()]);
+
+        let names = QueryBuilder::new(&mut store, "[:find ?a (count ?e) :where [?e ?a _]]")
+                        .column_names().expect("column names");
+        assert_eq!(names, vec!["?a".to_string(), "(count ?e)".to_string()]);
+    }
+
+    #[test]
+    fn test_execute_count() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "s" :db/ident :foo/boolean]
+            [:db/add "s" :db/valueType :db.type/boolean]
+            [:db/add "s" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+
+        store.transact(r#"[
+            [:db/add "l" :foo/boolean true]
+            [:db/add "m" :foo/boolean false]
+            [:db/add "n" :foo/boolean true]
+        ]"#).expect("successful transaction");
+
+        let count = QueryBuilder::new(&mut store, r#"[:find ?x :where [?x :foo/boolean _]]"#)
+                              .execute_count().expect("count");
+        assert_eq!(count, 3);
+
+        // Rows are distinct, so this is the number of distinct values.
+        let count = QueryBuilder::new(&mut store, r#"[:find ?v :where [_ :foo/boolean ?v]]"#)
+                              .execute_count().expect("count");
+        assert_eq!(count, 2);
+
+        let count = QueryBuilder::new(&mut store, r#"[:find ?x :in ?v :where [?x :foo/boolean ?v]]"#)
+                              .bind_value("?v", false)
+                              .execute_count().expect("count");
+        assert_eq!(count, 1);
+
+        let count = QueryBuilder::new(&mut store, r#"[:find ?x . :where [?x :foo/boolean true]]"#)
+                              .execute_count().expect("count");
+        assert_eq!(count, 1);
+
+        let count = QueryBuilder::new(&mut store, r#"[:find ?x :in [?v ...] :where [?x :foo/boolean ?v]]"#)
+                              .bind_coll::<bool>("?v", vec![])
+                              .execute_count().expect("count");
+        assert_eq!(count, 0);
+
+        assert!(QueryBuilder::new(&mut store, r#"[:find (count ?x) . :where [?x :foo/boolean _]]"#)
+                    .execute_count().is_err());
+    }
+
+    #[test]
+    fn test_execute_to_csv() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "s" :db/ident :foo/string]
+            [:db/add "s" :db/valueType :db.type/string]
+            [:db/add "s" :db/cardinality :db.cardinality/one]
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+
+        store.transact(r#"[
+            [:db/add "l" :foo/string "plain"]
+            [:db/add "l" :foo/long 25]
+            [:db/add "m" :foo/string "say \"hi\", please"]
+            [:db/add "m" :foo/long 26]
+        ]"#).expect("successful transaction");
+
+        let csv = QueryBuilder::new(&mut store, r#"[:find ?i ?s
+                                                    :where [?x :foo/string ?s]
+                                                           [?x :foo/long ?i]]"#)
+                              .order_by("?i", true).expect("order")
+                              .execute_to_csv().expect("CSV");
+        assert_eq!(csv, "?i,?s\r\n25,plain\r\n26,\"say \"\"hi\"\", please\"\r\n");
+    }
+
+    #[test]
+    fn test_execute_with_timing() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/many]
+        ]"#).expect("successful transaction");
+        store.transact(r#"[
+            [:db/add "l" :foo/long 25]
+            [:db/add "l" :foo/long 26]
+            [:db/add "l" :foo/long 27]
+        ]"#).expect("successful transaction");
+
+        let (output, timing) = QueryBuilder::new(&mut store, r#"[:find [?i ...] :where [_ :foo/long ?i]]"#)
+                                            .execute_with_timing().expect("results");
+        assert_eq!(output.len(), 3);
+        assert_eq!(timing.rows, 3);
+    }
+
+    #[test]
+    fn test_execute_to_json() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "s" :db/ident :foo/string]
+            [:db/add "s" :db/valueType :db.type/string]
+            [:db/add "s" :db/cardinality :db.cardinality/one]
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+
+        store.transact(r#"[
+            [:db/add "l" :foo/string "plain"]
+            [:db/add "l" :foo/long 25]
+            [:db/add "m" :foo/string "say \"hi\" \\o/"]
+            [:db/add "m" :foo/long 26]
+        ]"#).expect("successful transaction");
+
+        let json = QueryBuilder::new(&mut store, r#"[:find ?i ?s
+                                                     :where [?x :foo/string ?s]
+                                                            [?x :foo/long ?i]]"#)
+                               .order_by("?i", true).expect("order")
+                               .execute_to_json().expect("JSON");
+        assert_eq!(json, r#"[{"?i":25,"?s":"plain"},{"?i":26,"?s":"say \"hi\" \\o/"}]"#);
+
+        let json = QueryBuilder::new(&mut store, r#"[:find ?i . :where [_ :foo/long ?i] [(> ?i 100)]]"#)
+                               .execute_to_json().expect("JSON");
+        assert_eq!(json, "[]");
+    }
+
+    #[test]
+    fn test_cached_query_rebinds_between_executions() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+
+        let report = store.transact(r#"[
+            [:db/add "l" :foo/long 25]
+            [:db/add "m" :foo/long 26]
+        ]"#).expect("successful transaction");
+        let l = report.tempids.get("l").expect("found it").clone();
+        let m = report.tempids.get("m").expect("found it").clone();
+
+        let mut query = CachedQuery::new(&mut store, r#"[:find ?x .
+                                                         :in ?i
+                                                         :where [?x :foo/long ?i]]"#)
+                                    .expect("parsed");
+        assert_eq!(query.bind_long("?i", 25).execute_scalar().expect("ScalarResult"), Some(TypedValue::Ref(l)));
+        assert_eq!(query.bind_long("?i", 26).execute_scalar().expect("ScalarResult"), Some(TypedValue::Ref(m)));
+
+        // The binding persists until it is replaced.
+        assert_eq!(query.execute_scalar().expect("ScalarResult"), Some(TypedValue::Ref(m)));
+        assert_eq!(query.bind_long("?i", 27).execute_scalar().expect("ScalarResult"), None);
+    }
+
+    #[test]
+    fn test_cached_query_reuses_plan() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+
+        let mut query = CachedQuery::new(&mut store, r#"[:find ?x :in ?i :where [?x :foo/long ?i]]"#)
+                                    .expect("parsed");
+        assert!(query.plan.is_none());
+        query.bind_long("?i", 25).execute_coll().expect("CollResult");
+        let sql = match query.plan {
+            Some((_, QueryPlan::Bound { ref sql, .. })) => sql.clone(),
+            _ => panic!("expected a bound plan"),
+        };
+
+        // Running again with the same bindings reuses the plan.
+        query.execute_coll().expect("CollResult");
+        match query.plan {
+            Some((_, QueryPlan::Bound { sql: ref reused, .. })) => assert_eq!(reused, &sql),
+            _ => panic!("expected a bound plan"),
+        }
+
+        // Rebinding drops it.
+        query.bind_long("?i", 26);
+        assert!(query.plan.is_none());
+    }
+
+    #[test]
+    fn test_cached_query_replans_after_schema_change() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+        let report = store.transact(r#"[[:db/add "l" :foo/long 25]]"#).expect("successful transaction");
+        let l = report.tempids.get("l").expect("found it").clone();
+
+        // Consumers across the FFI hold both the store and the prepared query, and can transact
+        // while the query is alive. Mimic that here.
+        let store_ptr: *mut Store = &mut store;
+        let mut query = CachedQuery::new(unsafe { &mut *store_ptr }, r#"[:find [?x ...] :in ?i :where [?x _ ?i]]"#)
+                                    .expect("parsed");
+        assert_eq!(query.bind_long("?i", 25).execute_coll().expect("CollResult"), vec![TypedValue::Ref(l)]);
+        let planned_for = query.plan.as_ref().map(|&(ref schema, _)| schema.clone()).expect("planned");
+
+        let report = unsafe { &mut *store_ptr }.transact(r#"[
+            [:db/add "t" :db/ident :foo/other]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+            [:db/add "o" :foo/other 25]
+        ]"#).expect("successful transaction");
+        let o = report.tempids.get("o").expect("found it").clone();
+
+        let mut results = query.execute_coll().expect("CollResult");
+        results.sort_by_key(|v| match v { &TypedValue::Ref(e) => e, _ => panic!("expected a ref") });
+        assert_eq!(results, vec![TypedValue::Ref(l), TypedValue::Ref(o)]);
+        let replanned_for = query.plan.as_ref().map(|&(ref schema, _)| schema.clone()).expect("planned");
+        assert!(!Arc::ptr_eq(&planned_for, &replanned_for));
+    }
+
+    #[test]
+    fn test_cached_query_rejects_bad_query() {
+        let mut store = Store::open("").expect("store connection");
+        assert!(CachedQuery::new(&mut store, "[:find ?x :where").is_err());
+    }
+
+    #[test]
+    fn test_bind_coll() {
+        let mut store = Store::open("").expect("store connection");
+        store.transact(r#"[
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction");
+
+        let report = store.transact(r#"[
+            [:db/add "l" :foo/long 25]
+            [:db/add "m" :foo/long 26]
+            [:db/add "n" :foo/long 27]
+        ]"#).expect("successful transaction");
+
+        let l = report.tempids.get("l").expect("found it").clone();
+        let n = report.tempids.get("n").expect("found it").clone();
+
+        let mut results = QueryBuilder::new(&mut store, r#"[:find [?i ...]
+                                                            :in ?x
+                                                            :where [?x :foo/long ?i]]"#)
+                              .bind_coll("?x", vec![TypedValue::Ref(l), TypedValue::Ref(n)])
+                              .execute_coll().expect("CollResult");
+        results.sort();
+        assert_eq!(results, vec![TypedValue::Long(25), TypedValue::Long(27)]);
+
+        let results = QueryBuilder::new(&mut store, r#"[:find [?x ...]
+                                                        :in ?i
+                                                        :where [?x :foo/long ?i]]"#)
+                              .bind_coll::<TypedValue>("?i", vec![])
+                              .execute_coll().expect("CollResult");
+        assert_eq!(results, vec![]);
+    }
 }
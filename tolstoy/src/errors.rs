@@ -54,6 +54,11 @@ error_chain! {
             display("not yet implemented: {}", t)
         }
 
+        ServerDiverged(local_head: uuid::Uuid, remote_head: uuid::Uuid) {
+            description("server changed since last sync")
+            display("server head moved from {} to {} since the last sync; merging isn't supported yet", local_head, remote_head)
+        }
+
         DuplicateMetadata(k: String) {
             description("encountered more than one metadata value for key")
             display("encountered more than one metadata value for key: {}", k)
@@ -64,6 +69,11 @@ error_chain! {
             display("Tx processor couldn't finish")
         }
 
+        SyncCancelled {
+            description("sync cancelled")
+            display("sync cancelled before it finished")
+        }
+
         BadServerResponse(s: String) {
             description("Received bad response from the server")
             display("Received bad response from the server: {}", s)
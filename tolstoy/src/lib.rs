@@ -39,7 +39,11 @@ pub mod tx_processor;
 pub mod errors;
 pub mod syncer;
 pub mod tx_mapper;
-pub use syncer::Syncer;
+pub use syncer::{
+    SyncPhase,
+    SyncStatus,
+    Syncer,
+};
 pub use errors::{
     Error,
     ErrorKind,
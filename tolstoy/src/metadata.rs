@@ -48,6 +48,26 @@ impl HeadTrackable for SyncMetadataClient {
     }
 }
 
+impl SyncMetadataClient {
+    /// When the last successful sync finished, in microseconds since the epoch, or `None` if
+    /// this store has never synced.
+    pub fn last_sync(tx: &rusqlite::Transaction) -> Result<Option<i64>> {
+        let mut stmt = tx.prepare_cached("SELECT value FROM tolstoy_metadata WHERE key = ?")?;
+        let mut rows = stmt.query_map(&[&schema::LAST_SYNC_KEY], |r| -> i64 { r.get(0) })?;
+        let last_sync = match rows.next() {
+            Some(micros) => Some(micros?),
+            None => None,
+        };
+        Ok(last_sync)
+    }
+
+    pub fn set_last_sync(tx: &rusqlite::Transaction, micros: i64) -> Result<()> {
+        tx.execute("INSERT OR REPLACE INTO tolstoy_metadata (key, value) VALUES (?, ?)",
+            &[&schema::LAST_SYNC_KEY, &micros])?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,4 +87,14 @@ mod tests {
         SyncMetadataClient::set_remote_head(&tx, &uuid).expect("update succeeded");
         assert_eq!(uuid, SyncMetadataClient::remote_head(&tx).expect("fetch succeeded"));
     }
+
+    #[test]
+    fn test_last_sync() {
+        let mut conn = schema::tests::setup_conn();
+        let tx = conn.transaction().expect("db tx");
+        assert_eq!(None, SyncMetadataClient::last_sync(&tx).expect("fetch succeeded"));
+        SyncMetadataClient::set_last_sync(&tx, 1_500_000_000_000_000).expect("update succeeded");
+        SyncMetadataClient::set_last_sync(&tx, 1_500_000_000_000_001).expect("update succeeded");
+        assert_eq!(Some(1_500_000_000_000_001), SyncMetadataClient::last_sync(&tx).expect("fetch succeeded"));
+    }
 }
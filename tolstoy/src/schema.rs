@@ -12,6 +12,7 @@ use rusqlite;
 use errors::Result;
 
 pub static REMOTE_HEAD_KEY: &str = r#"remote_head"#;
+pub static LAST_SYNC_KEY: &str = r#"last_sync"#;
 
 lazy_static! {
     /// SQL statements to be executed, in order, to create the Tolstoy SQL schema (version 1).
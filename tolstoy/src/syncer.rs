@@ -10,6 +10,10 @@
 
 use std;
 use std::collections::HashMap;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
 
 use futures::{future, Future, Stream};
 use hyper;
@@ -25,7 +29,11 @@ use serde_json;
 use tokio_core::reactor::Core;
 use uuid::Uuid;
 
-use mentat_core::Entid;
+use mentat_core::{
+    Entid,
+    ToMicros,
+    now,
+};
 use metadata::SyncMetadataClient;
 use metadata::HeadTrackable;
 use schema::ensure_current_version;
@@ -66,6 +74,31 @@ pub fn d(message: &str) {
 
 pub struct Syncer {}
 
+/// The stages a sync passes through, as reported to the progress callback given to
+/// `Syncer::flow_with_progress`.
+///
+/// Downloading fetches the server's head. Nothing is transacted yet: a server that has moved on
+/// since our last sync is refused rather than merged, so `Transact` is never reported today.
+/// Uploading counts the local transactions sent to the server.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SyncPhase {
+    Download,
+    Transact,
+    Upload,
+}
+
+/// A snapshot of a store's sync metadata, as reported by `Syncer::status`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyncStatus {
+    /// When the last successful sync finished, in microseconds since the epoch.
+    pub last_sync: Option<i64>,
+    /// The server's head as of the last successful sync; nil if this store has never synced.
+    pub remote_head: Uuid,
+    /// The UUID our latest transaction was uploaded under, or `None` if it hasn't been.
+    pub local_head: Option<Uuid>,
+    pub has_local_changes: bool,
+}
+
 // TODO this is sub-optimal, we don't need to walk the table
 // to query the last thing in it w/ an index on tx!!
 // but it's the hammer at hand!
@@ -103,15 +136,21 @@ struct UploadingTxReceiver<'c> {
     remote_client: &'c RemoteClient,
     remote_head: &'c Uuid,
     rolling_temp_head: Option<Uuid>,
+    progress: &'c mut FnMut(SyncPhase, usize, usize),
+    total: usize,
+    cancelled: &'c AtomicBool,
 }
 
 impl<'c> UploadingTxReceiver<'c> {
-    fn new(client: &'c RemoteClient, remote_head: &'c Uuid) -> UploadingTxReceiver<'c> {
+    fn new(client: &'c RemoteClient, remote_head: &'c Uuid, progress: &'c mut FnMut(SyncPhase, usize, usize), total: usize, cancelled: &'c AtomicBool) -> UploadingTxReceiver<'c> {
         UploadingTxReceiver {
             tx_temp_uuids: HashMap::new(),
             remote_client: client,
             remote_head: remote_head,
             rolling_temp_head: None,
+            progress: progress,
+            total: total,
+            cancelled: cancelled,
             is_done: false
         }
     }
@@ -120,6 +159,12 @@ impl<'c> UploadingTxReceiver<'c> {
 impl<'c> TxReceiver for UploadingTxReceiver<'c> {
     fn tx<T>(&mut self, tx_id: Entid, datoms: &mut T) -> Result<()>
     where T: Iterator<Item=TxPart> {
+        // Between transactions is a safe place to stop: nothing is committed locally, and the
+        // remote head only moves once every transaction has been uploaded.
+        if self.cancelled.load(Ordering::SeqCst) {
+            bail!(ErrorKind::SyncCancelled);
+        }
+
         // Yes, we generate a new UUID for a given Tx, even if we might
         // already have one mapped locally. Pre-existing local mapping will
         // be replaced if this sync succeeds entirely.
@@ -164,6 +209,7 @@ impl<'c> TxReceiver for UploadingTxReceiver<'c> {
 
         d(&format!("updating rolling head: {:?}", tx_uuid));
         self.rolling_temp_head = Some(tx_uuid.clone());
+        (self.progress)(SyncPhase::Upload, self.tx_temp_uuids.len(), self.total);
 
         Ok(())
     }
@@ -175,12 +221,17 @@ impl<'c> TxReceiver for UploadingTxReceiver<'c> {
 }
 
 impl Syncer {
-    fn upload_ours(db_tx: &mut rusqlite::Transaction, from_tx: Option<Entid>, remote_client: &RemoteClient, remote_head: &Uuid) -> Result<()> {
-        let mut uploader = UploadingTxReceiver::new(remote_client, remote_head);
+    fn upload_ours(db_tx: &mut rusqlite::Transaction, from_tx: Option<Entid>, remote_client: &RemoteClient, remote_head: &Uuid, progress: &mut FnMut(SyncPhase, usize, usize), cancelled: &AtomicBool) -> Result<()> {
+        let total = Syncer::tx_count(db_tx, from_tx)?;
+        progress(SyncPhase::Upload, 0, total);
+        let mut uploader = UploadingTxReceiver::new(remote_client, remote_head, progress, total, cancelled);
         Processor::process(db_tx, from_tx, &mut uploader)?;
         if !uploader.is_done {
             bail!(ErrorKind::TxProcessorUnfinished);
         }
+        if cancelled.load(Ordering::SeqCst) {
+            bail!(ErrorKind::SyncCancelled);
+        }
         // Last tx uuid uploaded by the tx receiver.
         // It's going to be our new head.
         if let Some(last_tx_uploaded) = uploader.rolling_temp_head {
@@ -197,8 +248,69 @@ impl Syncer {
         Ok(())
     }
 
+    /// How many transactions come after `from_tx`, or exist at all if `from_tx` is `None`.
+    fn tx_count(db_tx: &rusqlite::Transaction, from_tx: Option<Entid>) -> Result<usize> {
+        let count: i64 = db_tx.query_row("SELECT count(DISTINCT tx) FROM transactions WHERE tx > ?",
+            &[&from_tx.unwrap_or(0)], |r| r.get(0))?;
+        Ok(count as usize)
+    }
+
+    /// Local head: the latest transaction that we have in the store, and the UUID it was
+    /// uploaded under. The UUID is `None` if that transaction has never been synced successfully,
+    /// which means HEAD moved since the last sync and the server needs to be updated. With no
+    /// transactions at all, there is nothing to upload, so an empty store reports `(None, Some(nil))`.
+    fn local_head(db_tx: &rusqlite::Transaction) -> Result<(Option<Entid>, Option<Uuid>)> {
+        let mut inquiring_tx_receiver = InquiringTxReceiver::new();
+        // TODO don't just start from the beginning... but then again, we should do this
+        // without walking the table at all, and use the tx index.
+        Processor::process(db_tx, None, &mut inquiring_tx_receiver)?;
+        if !inquiring_tx_receiver.is_done {
+            bail!(ErrorKind::TxProcessorUnfinished);
+        }
+        match inquiring_tx_receiver.last_tx {
+            Some(tx) => Ok((Some(tx), TxMapper::get(db_tx, tx)?)),
+            None => Ok((None, Some(Uuid::nil()))),
+        }
+    }
+
+    /// Report what this store knows about its sync state without contacting the server: when it
+    /// last synced, the remote head it saw then, and whether it has transactions yet to upload.
+    pub fn status(sqlite: &mut rusqlite::Connection) -> Result<SyncStatus> {
+        ensure_current_version(sqlite)?;
+        let db_tx = sqlite.transaction()?;
+
+        let last_sync = SyncMetadataClient::last_sync(&db_tx)?;
+        let remote_head = SyncMetadataClient::remote_head(&db_tx)?;
+        let (_, local_head) = Syncer::local_head(&db_tx)?;
+
+        Ok(SyncStatus {
+            last_sync: last_sync,
+            remote_head: remote_head,
+            has_local_changes: local_head.is_none(),
+            local_head: local_head,
+        })
+    }
+
     pub fn flow(sqlite: &mut rusqlite::Connection, server_uri: &String, user_uuid: &Uuid) -> Result<()> {
+        Syncer::flow_with_progress(sqlite, server_uri, user_uuid, &mut |_, _, _| {})
+    }
+
+    /// Like `flow`, but calls `progress` with the current phase and how much of it is done, out of
+    /// how much in total, as the sync proceeds. Calls are made on the calling thread.
+    pub fn flow_with_progress(sqlite: &mut rusqlite::Connection, server_uri: &String, user_uuid: &Uuid, progress: &mut FnMut(SyncPhase, usize, usize)) -> Result<()> {
+        Syncer::flow_cancellable(sqlite, server_uri, user_uuid, progress, &AtomicBool::new(false))
+    }
+
+    /// Like `flow_with_progress`, but gives up with `SyncCancelled` at the next safe point once
+    /// `cancelled` is set: before starting, after fetching the remote head, or between uploaded
+    /// transactions.
+    /// A cancelled sync commits nothing locally and leaves the remote head where it was; any
+    /// transactions it already uploaded are left for the server to collect.
+    pub fn flow_cancellable(sqlite: &mut rusqlite::Connection, server_uri: &String, user_uuid: &Uuid, progress: &mut FnMut(SyncPhase, usize, usize), cancelled: &AtomicBool) -> Result<()> {
         d(&format!("sync flowing"));
+        if cancelled.load(Ordering::SeqCst) {
+            bail!(ErrorKind::SyncCancelled);
+        }
 
         ensure_current_version(sqlite)?;
         
@@ -206,38 +318,24 @@ impl Syncer {
         let remote_client = RemoteClient::new(server_uri.clone(), user_uuid.clone());
         let mut db_tx = sqlite.transaction()?;
 
+        progress(SyncPhase::Download, 0, 1);
         let remote_head = remote_client.get_head()?;
+        progress(SyncPhase::Download, 1, 1);
+        if cancelled.load(Ordering::SeqCst) {
+            bail!(ErrorKind::SyncCancelled);
+        }
         d(&format!("remote head {:?}", remote_head));
 
         let locally_known_remote_head = SyncMetadataClient::remote_head(&db_tx)?;
         d(&format!("local head {:?}", locally_known_remote_head));
 
-        // Local head: latest transaction that we have in the store,
-        // but with one caveat: its tx might will not be mapped if it's
-        // never been synced successfully.
-        // In other words: if latest tx isn't mapped, then HEAD moved
-        // since last sync and server needs to be updated.
-        let mut inquiring_tx_receiver = InquiringTxReceiver::new();
-        // TODO don't just start from the beginning... but then again, we should do this
-        // without walking the table at all, and use the tx index.
-        Processor::process(&db_tx, None, &mut inquiring_tx_receiver)?;
-        if !inquiring_tx_receiver.is_done {
-            bail!(ErrorKind::TxProcessorUnfinished);
-        }
-        let have_local_changes = match inquiring_tx_receiver.last_tx {
-            Some(tx) => {
-                match TxMapper::get(&db_tx, tx)? {
-                    Some(_) => false,
-                    None => true
-                }
-            },
-            None => false
-        };
+        let (_, local_head) = Syncer::local_head(&db_tx)?;
+        let have_local_changes = local_head.is_none();
 
         // Check if the server is empty - populate it.
         if remote_head == Uuid::nil() {
             d(&format!("empty server!"));
-            Syncer::upload_ours(&mut db_tx, None, &remote_client, &remote_head)?;
+            Syncer::upload_ours(&mut db_tx, None, &remote_client, &remote_head, progress, cancelled)?;
         
         // Check if the server is the same as us, and if our HEAD moved.
         } else if locally_known_remote_head == remote_head {
@@ -245,6 +343,8 @@ impl Syncer {
             
             if !have_local_changes {
                 d(&format!("local HEAD did not move. Nothing to do!"));
+                SyncMetadataClient::set_last_sync(&db_tx, now().to_micros())?;
+                db_tx.commit()?;
                 return Ok(());
             }
 
@@ -254,7 +354,7 @@ impl Syncer {
             // our sync becomes just bumping our local head. AFAICT below would currently fail.
             if let Some(upload_from_tx) = TxMapper::get_tx_for_uuid(&db_tx, &locally_known_remote_head)? {
                 d(&format!("Fast-forwarding the server."));
-                Syncer::upload_ours(&mut db_tx, Some(upload_from_tx), &remote_client, &remote_head)?;
+                Syncer::upload_ours(&mut db_tx, Some(upload_from_tx), &remote_client, &remote_head, progress, cancelled)?;
             } else {
                 d(&format!("Unable to fast-forward the server; missing local tx mapping"));
                 bail!(ErrorKind::TxIncorrectlyMapped(0));
@@ -265,13 +365,12 @@ impl Syncer {
         } else {
             d(&format!("server changed since last sync."));
 
-            bail!(ErrorKind::NotYetImplemented(
-                format!("Can't yet sync against changed server. Local head {:?}, remote head {:?}", locally_known_remote_head, remote_head)
-            ));
+            bail!(ErrorKind::ServerDiverged(locally_known_remote_head, remote_head));
         }
 
         // Commit everything, if there's anything to commit!
         // Any new tx->uuid mappings and the new HEAD. We're synced!
+        SyncMetadataClient::set_last_sync(&db_tx, now().to_micros())?;
         db_tx.commit()?;
 
         Ok(())
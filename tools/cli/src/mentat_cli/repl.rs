@@ -27,7 +27,6 @@ use mentat::{
     CacheDirection,
     NamespacedKeyword,
     Queryable,
-    QueryExplanation,
     QueryOutput,
     QueryResults,
     Store,
@@ -462,38 +461,8 @@ impl Repl {
         match self.store.q_explain(query.as_str(), None) {
             Result::Err(err) =>
                 println!("{:?}.", err),
-            Result::Ok(QueryExplanation::KnownConstant) =>
-                println!("Query is known constant!"),
-            Result::Ok(QueryExplanation::KnownEmpty(empty_because)) =>
-                println!("Query is known empty: {:?}", empty_because),
-            Result::Ok(QueryExplanation::ExecutionPlan { query, steps }) => {
-                println!("SQL: {}", query.sql);
-                if !query.args.is_empty() {
-                    println!("  Bindings:");
-                    for (arg_name, value) in query.args {
-                        println!("    {} = {:?}", arg_name, *value)
-                    }
-                }
-
-                println!("Plan: select id | order | from | detail");
-                // Compute the number of columns we need for order, select id, and from,
-                // so that longer query plans don't become misaligned.
-                let (max_select_id, max_order, max_from) = steps.iter().fold((0, 0, 0), |acc, step|
-                    (acc.0.max(step.select_id), acc.1.max(step.order), acc.2.max(step.from)));
-                // This is less efficient than computing it via the logarithm base 10,
-                // but it's clearer and doesn't have require special casing "0"
-                let max_select_digits = max_select_id.to_string().len();
-                let max_order_digits = max_order.to_string().len();
-                let max_from_digits = max_from.to_string().len();
-                for step in steps {
-                    // Note: > is right align.
-                    println!("  {:>sel_cols$}|{:>ord_cols$}|{:>from_cols$}|{}",
-                             step.select_id, step.order, step.from, step.detail,
-                             sel_cols = max_select_digits,
-                             ord_cols = max_order_digits,
-                             from_cols = max_from_digits);
-                }
-            }
+            Result::Ok(explanation) =>
+                println!("{}", explanation),
         };
     }
 